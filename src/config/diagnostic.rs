@@ -0,0 +1,185 @@
+//! Structured reports of where and why a meta file failed to be read.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::{Path, PathBuf};
+
+use crate::config::Format;
+use crate::metadata::Arity;
+
+/// Describes a failure to deserialize the contents of a meta file, along with
+/// the location of the failure in the meta file, if known.
+/// Lines and columns are 1-based.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    path: Option<PathBuf>,
+    format: Format,
+    arity: Option<Arity>,
+    line: Option<usize>,
+    column: Option<usize>,
+    snippet: Option<String>,
+    message: String,
+}
+
+impl Diagnostic {
+    /// Creates a new diagnostic for the source text of a meta file. If a line
+    /// and column are given, the offending line is extracted from the source
+    /// text as a snippet.
+    pub(crate) fn new(
+        format: Format,
+        arity: Option<Arity>,
+        source: &str,
+        location: Option<(usize, usize)>,
+        message: String,
+    ) -> Self {
+        let (line, column) = match location {
+            Some((l, c)) => (Some(l), Some(c)),
+            None => (None, None),
+        };
+
+        let snippet = line
+            .and_then(|l| source.lines().nth(l.saturating_sub(1)))
+            .map(|s| s.trim_end().to_string());
+
+        Self { path: None, format, arity, line, column, snippet, message, }
+    }
+
+    /// Sets the path of the meta file that this diagnostic refers to.
+    pub(crate) fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// The path of the meta file, if the meta file was read from disk.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The arity of the schema that was expected. This is `None` for meta
+    /// files with a combined layout, which provide both arities.
+    pub fn arity(&self) -> Option<Arity> {
+        self.arity
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// The text of the offending line in the meta file.
+    pub fn snippet(&self) -> Option<&str> {
+        self.snippet.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Describes the shape of the meta file contents that was expected.
+    pub fn expected(&self) -> &'static str {
+        match self.arity {
+            Some(Arity::Unit) => "a mapping of metadata for a single item",
+            Some(Arity::Many) => "a sequence or mapping of metadata for multiple items",
+            None => "a mapping of album and track metadata",
+        }
+    }
+
+    /// Renders this diagnostic over multiple lines, starting with the path of
+    /// the meta file if known, and followed by the offending line and a marker
+    /// pointing at the offending column.
+    pub fn render(&self) -> String {
+        let mut rendered = match &self.path {
+            Some(path) => format!("{}:{}", path.display(), self),
+            None => self.to_string(),
+        };
+
+        if let (Some(line), Some(snippet)) = (self.line, &self.snippet) {
+            let gutter = line.to_string();
+
+            rendered.push_str(&format!("\n{} | {}", gutter, snippet));
+
+            if let Some(column) = self.column {
+                let padding = " ".repeat(gutter.len() + column.saturating_sub(1));
+                rendered.push_str(&format!("\n{}   ^", padding));
+            }
+        }
+
+        rendered
+    }
+}
+
+/// The path of the meta file is not displayed, since errors for meta files
+/// already mention their paths.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+
+            if let Some(column) = self.column {
+                write!(f, "{}:", column)?;
+            }
+
+            write!(f, " ")?;
+        }
+
+        write!(f, "{} (expected {})", self.message, self.expected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+
+    #[test]
+    fn render() {
+        let source = indoc! {"
+            title: album_title
+            artist: [album_artist
+        "};
+
+        let diagnostic = Diagnostic::new(
+            Format::Yaml,
+            Some(Arity::Unit),
+            source,
+            Some((2, 9)),
+            String::from("unclosed sequence"),
+        )
+        .with_path(Path::new("album.yml"));
+
+        assert_eq!(diagnostic.snippet(), Some("artist: [album_artist"));
+        assert_eq!(
+            diagnostic.to_string(),
+            "2:9: unclosed sequence (expected a mapping of metadata for a single item)",
+        );
+        assert_eq!(
+            diagnostic.render(),
+            indoc! {"
+                album.yml:2:9: unclosed sequence (expected a mapping of metadata for a single item)
+                2 | artist: [album_artist
+                            ^"
+            },
+        );
+
+        let diagnostic = Diagnostic::new(
+            Format::Json,
+            Some(Arity::Many),
+            "",
+            None,
+            String::from("no variant matched"),
+        );
+
+        assert_eq!(diagnostic.snippet(), None);
+        assert_eq!(
+            diagnostic.render(),
+            "no variant matched (expected a sequence or mapping of metadata for multiple items)",
+        );
+    }
+}
@@ -1,14 +1,20 @@
 use std::path::Path;
 use std::fs::File;
-use std::io::{Error as IoError, Read};
+use std::io::{BufReader, Error as IoError, ErrorKind as IoErrorKind, Read, Write};
 
 use serde::Deserialize;
 use serde_yaml::Error as YamlError;
 use serde_json::Error as JsonError;
+use toml::de::Error as TomlError;
+use toml::ser::Error as TomlSerError;
 use strum::{EnumString, EnumIter, AsRefStr};
 use thiserror::Error;
 
-use crate::metadata::{Arity, Schema, SchemaRepr};
+use crate::config::Diagnostic;
+use crate::metadata::{Arity, Metadata, Schema, SchemaRepr};
+use crate::metadata::table::{CellTyping, Error as TableError};
+use crate::metadata::tags::Error as TagsError;
+use crate::types::{Block, BlockMap, BlockSeq};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -17,9 +23,47 @@ pub enum Error {
     #[error("cannot read metadata file: {0}")]
     CannotReadFile(#[source] IoError),
     #[error("cannot deserialize YAML: {0}")]
-    YamlDeserialize(#[source] YamlError),
+    YamlDeserialize(Box<Diagnostic>, #[source] YamlError),
     #[error("cannot deserialize JSON: {0}")]
-    JsonDeserialize(#[source] JsonError),
+    JsonDeserialize(Box<Diagnostic>, #[source] JsonError),
+    #[error("cannot deserialize TOML: {0}")]
+    TomlDeserialize(Box<Diagnostic>, #[source] TomlError),
+    #[error("cannot write metadata file: {0}")]
+    CannotWriteFile(#[source] IoError),
+    #[error("cannot serialize YAML: {0}")]
+    YamlSerialize(#[source] YamlError),
+    #[error("cannot serialize JSON: {0}")]
+    JsonSerialize(#[source] JsonError),
+    #[error("cannot serialize TOML: {0}")]
+    TomlSerialize(#[source] TomlSerError),
+    #[error("cannot deserialize table: {0}")]
+    TableDeserialize(#[source] TableError),
+    #[error("tables do not support {0}")]
+    TableUnsupported(&'static str),
+    #[error("cannot read embedded tags: {0}")]
+    TagsRead(#[source] TagsError),
+    #[error("embedded tags do not support {0}")]
+    TagsUnsupported(&'static str),
+}
+
+impl Error {
+    /// Returns the location and details of a deserialization failure, if
+    /// this error was caused by one.
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match self {
+            Self::YamlDeserialize(d, _) | Self::JsonDeserialize(d, _) | Self::TomlDeserialize(d, _) => Some(d),
+            _ => None,
+        }
+    }
+
+    fn with_path(self, path: &Path) -> Self {
+        match self {
+            Self::YamlDeserialize(d, e) => Self::YamlDeserialize(Box::new(d.with_path(path)), e),
+            Self::JsonDeserialize(d, e) => Self::JsonDeserialize(Box::new(d.with_path(path)), e),
+            Self::TomlDeserialize(d, e) => Self::TomlDeserialize(Box::new(d.with_path(path)), e),
+            other => other,
+        }
+    }
 }
 
 /// Represents all the different metadata formats that are supported.
@@ -30,6 +74,16 @@ pub enum Format {
     Json,
     #[strum(serialize = "YML", serialize = "yml")]
     Yaml,
+    #[strum(serialize = "TOML", serialize = "toml")]
+    Toml,
+    #[strum(serialize = "CSV", serialize = "csv")]
+    Csv,
+    #[strum(serialize = "TSV", serialize = "tsv")]
+    Tsv,
+    /// Audio tags embedded in item files, which are not meta files in their
+    /// own right, and thus do not have a file extension.
+    #[strum(disabled)]
+    Tags,
 }
 
 impl Format {
@@ -47,20 +101,256 @@ impl Format {
         }.map(Into::into)
     }
 
+    fn read_toml(s: &str, arity: &Arity) -> Result<Schema, TomlError> {
+        // Deserialize directly from the text, since only then are native TOML
+        // datetimes presented in a form that is read as datetimes. Strings are
+        // never read as datetimes.
+        // NOTE: TOML documents are always tables at the top level, so files
+        //       with an arity of `Arity::Many` always produce `Schema::Map`.
+        match arity {
+            Arity::Unit => toml::from_str(s).map(SchemaRepr::Unit),
+            Arity::Many => toml::from_str(s).map(SchemaRepr::Many),
+        }.map(Into::into)
+    }
+
+    // Lines and columns are normalized to be 1-based.
+    fn yaml_location(err: &YamlError) -> Option<(usize, usize)> {
+        err.location().map(|loc| (loc.line(), loc.column()))
+    }
+
+    fn json_location(err: &JsonError) -> Option<(usize, usize)> {
+        match err.line() {
+            0 => None,
+            line => Some((line, err.column().max(1))),
+        }
+    }
+
+    fn toml_location(err: &TomlError) -> Option<(usize, usize)> {
+        err.line_col().map(|(line, col)| (line + 1, col + 1))
+    }
+
+    /// Out of several errors, returns the one that is located the furthest
+    /// into the meta file, along with its location.
+    fn furthest_error<E>(
+        errs: Vec<Option<E>>,
+        locate: fn(&E) -> Option<(usize, usize)>,
+    ) -> Option<(E, (usize, usize))> {
+        errs.into_iter()
+            .flatten()
+            .filter_map(|e| locate(&e).map(|loc| (e, loc)))
+            .max_by_key(|(_, loc)| *loc)
+    }
+
+    /// Creates a diagnostic for a deserialization error. Errors that do not
+    /// have a location, such as failures to match any of the possible schema
+    /// shapes, are located by retrying with each of the concrete shapes for
+    /// the expected arity, and using the error that made it the furthest.
+    /// The error that the diagnostic was created from is returned with it.
+    fn diagnose<E: std::fmt::Display>(
+        &self,
+        s: &str,
+        arity: Option<&Arity>,
+        err: E,
+        locate: fn(&E) -> Option<(usize, usize)>,
+        retry: impl FnOnce(&Arity) -> Vec<Option<E>>,
+    ) -> (Box<Diagnostic>, E) {
+        let (err, location) = match (locate(&err), arity) {
+            (Some(loc), _) => (err, Some(loc)),
+            (None, None) => (err, None),
+            (None, Some(arity)) => match Self::furthest_error(retry(arity), locate) {
+                Some((retry_err, loc)) => (retry_err, Some(loc)),
+                None => (err, None),
+            },
+        };
+
+        // The location is already provided separately, so remove it from the
+        // end of the error message.
+        let mut message = err.to_string();
+        if location.is_some() {
+            if let Some(i) = message.rfind(" at line ") {
+                message.truncate(i);
+            }
+        }
+
+        (Box::new(Diagnostic::new(*self, arity.copied(), s, location, message)), err)
+    }
+
+    fn diagnose_yaml(s: &str, arity: Option<&Arity>, err: YamlError) -> Error {
+        let (diagnostic, err) = Self::Yaml.diagnose(s, arity, err, Self::yaml_location, |arity| match arity {
+            Arity::Unit => vec![serde_yaml::from_str::<Block>(s).err()],
+            Arity::Many => vec![
+                serde_yaml::from_str::<BlockSeq>(s).err(),
+                serde_yaml::from_str::<BlockMap>(s).err(),
+            ],
+        });
+
+        Error::YamlDeserialize(diagnostic, err)
+    }
+
+    fn diagnose_json(s: &str, arity: Option<&Arity>, err: JsonError) -> Error {
+        let (diagnostic, err) = Self::Json.diagnose(s, arity, err, Self::json_location, |arity| match arity {
+            Arity::Unit => vec![serde_json::from_str::<Block>(s).err()],
+            Arity::Many => vec![
+                serde_json::from_str::<BlockSeq>(s).err(),
+                serde_json::from_str::<BlockMap>(s).err(),
+            ],
+        });
+
+        Error::JsonDeserialize(diagnostic, err)
+    }
+
+    fn diagnose_toml(s: &str, arity: Option<&Arity>, err: TomlError) -> Error {
+        let (diagnostic, err) = Self::Toml.diagnose(s, arity, err, Self::toml_location, |arity| match arity {
+            Arity::Unit => vec![toml::from_str::<Block>(s).err()],
+            Arity::Many => vec![toml::from_str::<BlockMap>(s).err()],
+        });
+
+        Error::TomlDeserialize(diagnostic, err)
+    }
+
+    /// Reads a table, which always describes multiple items, with one item
+    /// per row.
+    fn read_table(s: &str, delimiter: u8, arity: &Arity, cell_typing: CellTyping) -> Result<Schema, Error> {
+        match arity {
+            Arity::Unit => Err(Error::TableUnsupported("metadata for a single item")),
+            Arity::Many => {
+                crate::metadata::table::read_schema(s.as_bytes(), delimiter, cell_typing)
+                    .map_err(Error::TableDeserialize)
+            },
+        }
+    }
+
     pub fn read_schema_str(&self, s: &str, arity: &Arity) -> Result<Schema, Error> {
+        self.read_schema_str_with(s, arity, CellTyping::default())
+    }
+
+    /// Similar to `read_schema_str`, but also specifies how the cells of
+    /// tables are converted into values. This has no effect on other formats.
+    pub fn read_schema_str_with(&self, s: &str, arity: &Arity, cell_typing: CellTyping) -> Result<Schema, Error> {
         match self {
-            Self::Yaml => Self::read_yaml(s, arity).map_err(Error::YamlDeserialize),
-            Self::Json => Self::read_json(s, arity).map_err(Error::JsonDeserialize),
+            Self::Yaml => Self::read_yaml(s, arity).map_err(|e| Self::diagnose_yaml(s, Some(arity), e)),
+            Self::Json => Self::read_json(s, arity).map_err(|e| Self::diagnose_json(s, Some(arity), e)),
+            Self::Toml => Self::read_toml(s, arity).map_err(|e| Self::diagnose_toml(s, Some(arity), e)),
+            Self::Csv => Self::read_table(s, b',', arity, cell_typing),
+            Self::Tsv => Self::read_table(s, b'\t', arity, cell_typing),
+            Self::Tags => Self::read_tags(s.as_bytes()),
         }
     }
 
     pub fn read_schema_path(&self, path: &Path, arity: &Arity) -> Result<Schema, Error> {
+        self.read_schema_path_with(path, arity, CellTyping::default())
+    }
+
+    /// Similar to `read_schema_path`, but also specifies how the cells of
+    /// tables are converted into values. This has no effect on other formats.
+    pub fn read_schema_path_with(&self, path: &Path, arity: &Arity, cell_typing: CellTyping) -> Result<Schema, Error> {
+        if let Self::Tags = self {
+            // Tags are binary data at the start of the item file, so only
+            // the needed part of the file is read.
+            let f = File::open(path).map_err(Error::CannotOpenFile)?;
+            return Self::read_tags(BufReader::new(f));
+        }
+
+        let buffer = Self::read_path(path)?;
+        self.read_schema_str_with(&buffer, arity, cell_typing).map_err(|e| e.with_path(path))
+    }
+
+    /// Reads embedded tags, which always describe exactly one item.
+    fn read_tags<R: Read>(reader: R) -> Result<Schema, Error> {
+        crate::metadata::tags::read_block(reader)
+            .map(Schema::One)
+            .map_err(Error::TagsRead)
+    }
+
+    /// Reads a combined meta file, which contains both the metadata for its
+    /// containing directory and the metadata for the items in that directory.
+    pub fn read_metadata_str(&self, s: &str) -> Result<Metadata, Error> {
+        match self {
+            Self::Yaml => serde_yaml::from_str(s).map_err(|e| Self::diagnose_yaml(s, None, e)),
+            Self::Json => serde_json::from_str(s).map_err(|e| Self::diagnose_json(s, None, e)),
+            Self::Toml => toml::from_str(s).map_err(|e| Self::diagnose_toml(s, None, e)),
+            Self::Csv | Self::Tsv => Err(Error::TableUnsupported("combined layouts")),
+            Self::Tags => Err(Error::TagsUnsupported("combined layouts")),
+        }
+    }
+
+    pub fn read_metadata_path(&self, path: &Path) -> Result<Metadata, Error> {
+        let buffer = Self::read_path(path)?;
+        self.read_metadata_str(&buffer).map_err(|e| e.with_path(path))
+    }
+
+    fn read_path(path: &Path) -> Result<String, Error> {
         let mut f = File::open(path).map_err(Error::CannotOpenFile)?;
 
         let mut buffer = String::new();
         f.read_to_string(&mut buffer).map_err(Error::CannotReadFile)?;
 
-        self.read_schema_str(&buffer, arity)
+        Ok(buffer)
+    }
+
+    fn write_toml(schema: &Schema) -> Result<String, TomlSerError> {
+        // Serializing via a generic TOML value ensures that plain values are
+        // emitted before any nested tables, as TOML requires.
+        let value = toml::Value::try_from(schema)?;
+
+        if !value.is_table() {
+            let msg = "top-level value must be a table";
+            return Err(serde::ser::Error::custom(msg));
+        }
+
+        toml::to_string_pretty(&value)
+    }
+
+    /// Serializes a schema into a string in this format.
+    /// Note that TOML documents must be tables at the top level, so
+    /// `Schema::Seq` cannot be written as TOML.
+    pub fn write_schema_str(&self, schema: &Schema) -> Result<String, Error> {
+        match self {
+            Self::Yaml => serde_yaml::to_string(schema).map_err(Error::YamlSerialize),
+            Self::Json => serde_json::to_string_pretty(schema).map_err(Error::JsonSerialize),
+            Self::Toml => Self::write_toml(schema).map_err(Error::TomlSerialize),
+            Self::Csv | Self::Tsv => Err(Error::TableUnsupported("writing")),
+            Self::Tags => Err(Error::TagsUnsupported("writing")),
+        }
+    }
+
+    /// Serializes a schema and writes it to a file in this format, replacing
+    /// the file if it already exists.
+    pub fn write_schema_path(&self, path: &Path, schema: &Schema) -> Result<(), Error> {
+        let buffer = self.write_schema_str(schema)?;
+        Self::write_path(path, &buffer)
+    }
+
+    fn write_path(path: &Path, contents: &str) -> Result<(), Error> {
+        let file_name = path.file_name().ok_or_else(|| {
+            let msg = format!("path does not have a file name: {}", path.display());
+            Error::CannotWriteFile(IoError::new(IoErrorKind::InvalidInput, msg))
+        })?;
+
+        // Write to a uniquely-named temporary file in the same directory, and
+        // then rename it over the target path. Since the rename is atomic,
+        // readers never see a partially-written meta file, and concurrent
+        // writers never share a temporary file.
+        let dir_path = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut prefix = std::ffi::OsString::from(".");
+        prefix.push(file_name);
+
+        // The temporary file is removed if it is dropped before being persisted.
+        let mut temp_file = tempfile::Builder::new()
+            .prefix(&prefix)
+            .suffix(".tmp")
+            .tempfile_in(dir_path)
+            .map_err(Error::CannotWriteFile)?;
+
+        temp_file.write_all(contents.as_bytes()).map_err(Error::CannotWriteFile)?;
+        temp_file.as_file().sync_all().map_err(Error::CannotWriteFile)?;
+        temp_file.persist(path).map_err(|err| Error::CannotWriteFile(err.error))?;
+
+        Ok(())
     }
 }
 
@@ -68,6 +358,14 @@ impl Format {
 mod tests {
     use super::*;
 
+    use indexmap::indexmap;
+    use maplit::btreemap;
+    use str_macro::str;
+    use tempfile::Builder;
+
+    use crate::types::{Block, BlockMap, BlockSeq, DateTime, Value};
+    use crate::test_util::TestUtil as TU;
+
     #[test]
     fn read_yaml() {
         let input = r#"
@@ -173,4 +471,311 @@ mod tests {
         "#;
         assert!(matches!(Format::read_json(input, &Arity::Many), Ok(Schema::Map(_))));
     }
+
+    #[test]
+    fn read_toml() {
+        let input = r#"
+            key_a = "val_a"
+            key_b = "val_b"
+            key_c = "val_c"
+            key_d = "val_d"
+        "#;
+        assert!(matches!(Format::read_toml(input, &Arity::Unit), Ok(Schema::One(_))));
+
+        let input = r#"
+            key_a = "val_a"
+            key_c = ["val_a", "val_b"]
+            key_d = {sub_key_a = "sub_val_a", sub_key_b = "sub_val_b"}
+            key_e = [
+                "val_a",
+                "val_b",
+            ]
+
+            [key_b]
+            sub_key_a = "sub_val_a"
+            sub_key_b = "sub_val_b"
+        "#;
+        assert!(matches!(Format::read_toml(input, &Arity::Unit), Ok(Schema::One(_))));
+
+        let input = r#"
+            [item_1]
+            key_1_a = "val_1_a"
+            key_1_b = "val_1_b"
+
+            [item_2]
+            key_2_a = "val_2_a"
+            key_2_b = "val_2_b"
+        "#;
+        assert!(matches!(Format::read_toml(input, &Arity::Many), Ok(Schema::Map(_))));
+
+        // Native datetimes are read as datetimes, but strings are kept as is.
+        let input = r#"
+            date = 2020-08-20
+            datetime = 1979-05-27T07:32:00Z
+            string_date = '2019-08'
+            title = '1999'
+        "#;
+        match Format::read_toml(input, &Arity::Unit) {
+            Ok(Schema::One(block)) => {
+                let dt = |s| Some(Value::DateTime(DateTime::parse(s).unwrap()));
+                assert_eq!(block.get("date").cloned(), dt("2020-08-20"));
+                assert_eq!(block.get("datetime").cloned(), dt("1979-05-27T07:32:00Z"));
+                assert_eq!(block.get("string_date"), Some(&Value::from("2019-08")));
+                assert_eq!(block.get("title"), Some(&Value::from("1999")));
+            },
+            _ => panic!("unexpected schema"),
+        }
+    }
+
+    #[test]
+    fn read_table() {
+        let input = "title,rating\ntrack_1_title,5\ntrack_2_title,4\n";
+        assert!(matches!(Format::Csv.read_schema_str(input, &Arity::Many), Ok(Schema::Seq(_))));
+        assert!(matches!(
+            Format::Csv.read_schema_str(input, &Arity::Unit),
+            Err(Error::TableUnsupported(_)),
+        ));
+
+        let input = "file\ttitle\ntrack_1.flac\ttrack_1_title\n";
+        assert!(matches!(Format::Tsv.read_schema_str(input, &Arity::Many), Ok(Schema::Map(_))));
+
+        let input = "title,rating\ntrack_1_title,5\n";
+        match Format::Csv.read_schema_str_with(input, &Arity::Many, CellTyping::Strings) {
+            Ok(Schema::Seq(block_seq)) => {
+                assert_eq!(block_seq.get(0).unwrap().get("rating"), Some(&Value::from("5")));
+            },
+            _ => panic!("unexpected schema"),
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let inputs_and_expected = vec![
+            // Syntax errors.
+            (
+                (Format::Yaml, "title: a\nartist: [b, c\nrating: 5\n", Arity::Unit),
+                (Some((3, 7)), Some("rating: 5")),
+            ),
+            (
+                (Format::Json, "{\n  \"title\": \"a\",\n  \"artist\" \"b\"\n}", Arity::Unit),
+                (Some((3, 12)), Some(r#"  "artist" "b""#)),
+            ),
+            (
+                (Format::Toml, "title = 'a'\nartist = \n", Arity::Unit),
+                (Some((2, 10)), Some("artist =")),
+            ),
+            // Schema shape errors.
+            (
+                (Format::Yaml, "- title: a\n- title: b\n", Arity::Unit),
+                (Some((1, 1)), Some("- title: a")),
+            ),
+            (
+                (Format::Yaml, "- title: a\n- b\n", Arity::Many),
+                (Some((2, 3)), Some("- b")),
+            ),
+            (
+                (Format::Json, "[\n  {\"title\": \"a\"},\n  \"b\"\n]", Arity::Many),
+                (Some((3, 5)), Some(r#"  "b""#)),
+            ),
+            (
+                (Format::Toml, "title = 'a'\n[b]\nc = 1\n", Arity::Many),
+                (Some((1, 9)), Some("title = 'a'")),
+            ),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (format, text, arity) = input;
+            let (expected_location, expected_snippet) = expected;
+
+            let err = format.read_schema_str(text, &arity).unwrap_err();
+            let diagnostic = err.diagnostic().unwrap();
+
+            assert_eq!(format, diagnostic.format());
+            assert_eq!(Some(arity), diagnostic.arity());
+            assert_eq!(expected_location, diagnostic.line().zip(diagnostic.column()));
+            assert_eq!(expected_snippet, diagnostic.snippet());
+            assert!(!diagnostic.message().contains(" at line "));
+
+            // The underlying parse error is still available as the source.
+            assert!(std::error::Error::source(&err).is_some());
+        }
+
+        // Meta files read from disk include their path.
+        let temp_dir = TU::create_simple_dir("diagnostics", &[] as &[&str]);
+        let path = temp_dir.path().join("track.yml");
+        std::fs::write(&path, "- title: a\n- b\n").unwrap();
+
+        let err = Format::Yaml.read_schema_path(&path, &Arity::Many).unwrap_err();
+        assert_eq!(Some(path.as_path()), err.diagnostic().unwrap().path());
+
+        let err = Format::Yaml.read_metadata_path(&path).unwrap_err();
+        assert_eq!(Some(path.as_path()), err.diagnostic().unwrap().path());
+        assert_eq!(None, err.diagnostic().unwrap().arity());
+    }
+
+    #[test]
+    fn read_metadata_str() {
+        let input = r#"
+            album:
+                title: album_title
+            tracks:
+                -   title: track_1_title
+                -   title: track_2_title
+        "#;
+        let metadata = Format::Yaml.read_metadata_str(input).unwrap();
+        assert!(matches!(metadata.clone().into_schema(&Arity::Unit), Schema::One(_)));
+        assert!(matches!(metadata.into_schema(&Arity::Many), Schema::Seq(_)));
+
+        let input = r#"
+        {
+            "album": {"title": "album_title"},
+            "tracks": {
+                "track_1.flac": {"title": "track_1_title"},
+                "track_2.flac": {"title": "track_2_title"}
+            }
+        }
+        "#;
+        let metadata = Format::Json.read_metadata_str(input).unwrap();
+        assert!(matches!(metadata.clone().into_schema(&Arity::Unit), Schema::One(_)));
+        assert!(matches!(metadata.into_schema(&Arity::Many), Schema::Map(_)));
+
+        let input = r#"
+            [album]
+            title = "album_title"
+            date = 2020-08-20
+
+            [[tracks]]
+            title = "track_1_title"
+            date = 2020-08-20T10:30:00Z
+
+            [[tracks]]
+            title = "track_2_title"
+        "#;
+        let metadata = Format::Toml.read_metadata_str(input).unwrap();
+
+        // Native TOML datetimes are read the same way as in plain meta files.
+        match metadata.clone().into_schema(&Arity::Unit) {
+            Schema::One(block) => {
+                assert_eq!(Some(&Value::DateTime(DateTime::parse("2020-08-20").unwrap())), block.get("date"));
+            },
+            schema => panic!("unexpected schema: {:?}", schema),
+        }
+
+        match metadata.into_schema(&Arity::Many) {
+            Schema::Seq(block_seq) => {
+                let date = block_seq.get(0).and_then(|block| block.get("date"));
+                assert_eq!(Some(&Value::DateTime(DateTime::parse("2020-08-20T10:30:00Z").unwrap())), date);
+            },
+            schema => panic!("unexpected schema: {:?}", schema),
+        }
+
+        // Both parts are optional.
+        let metadata = Format::Toml.read_metadata_str("").unwrap();
+        assert!(matches!(metadata.clone().into_schema(&Arity::Unit), Schema::One(b) if b.is_empty()));
+        assert!(matches!(metadata.into_schema(&Arity::Many), Schema::Seq(s) if s.is_empty()));
+    }
+
+    #[test]
+    fn write_schema_str() {
+        let block_a = Block(btreemap![
+            str!("key_a") => TU::s("val_a"),
+            str!("key_b") => TU::i(27),
+            str!("key_c") => Value::Boolean(true),
+            str!("key_d") => Value::Sequence(vec![TU::s("val_a"), TU::s("val_b")]),
+            str!("key_e") => Value::Mapping(Block(btreemap![
+                str!("sub_key_a") => TU::s("sub_val_a"),
+            ])),
+            str!("key_f") => TU::s("val_f"),
+        ]);
+        let block_b = Block(btreemap![str!("key_b") => TU::s("val_b")]);
+        let block_c = Block(btreemap![str!("key_c") => TU::s("val_c")]);
+
+        // Tags are intentionally out of lexical order.
+        let block_map = BlockMap(indexmap![
+            str!("item_c") => block_c.clone(),
+            str!("item_a") => block_a.clone(),
+            str!("item_b") => block_b.clone(),
+        ]);
+        let block_seq = BlockSeq(vec![block_a.clone(), block_b.clone(), block_c.clone()]);
+
+        for format in &[Format::Json, Format::Yaml, Format::Toml] {
+            let text = format.write_schema_str(&Schema::One(block_a.clone())).unwrap();
+            match format.read_schema_str(&text, &Arity::Unit) {
+                Ok(Schema::One(produced)) => assert_eq!(block_a, produced),
+                _ => panic!("unexpected schema"),
+            }
+
+            let text = format.write_schema_str(&Schema::Map(block_map.clone())).unwrap();
+            match format.read_schema_str(&text, &Arity::Many) {
+                Ok(Schema::Map(produced)) => {
+                    assert_eq!(block_map, produced);
+                    assert_eq!(
+                        block_map.keys().collect::<Vec<_>>(),
+                        produced.keys().collect::<Vec<_>>(),
+                    );
+                },
+                _ => panic!("unexpected schema"),
+            }
+
+            let res = format.write_schema_str(&Schema::Seq(block_seq.clone()));
+            if let Format::Toml = format {
+                // TOML is not able to represent a top-level sequence.
+                assert!(matches!(res, Err(Error::TomlSerialize(_))));
+            } else {
+                match format.read_schema_str(&res.unwrap(), &Arity::Many) {
+                    Ok(Schema::Seq(produced)) => assert_eq!(block_seq, produced),
+                    _ => panic!("unexpected schema"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn write_schema_path() {
+        let temp_dir = Builder::new().suffix("write_schema_path").tempdir().unwrap();
+        let path = temp_dir.path().join("self.yml");
+
+        let block_a = Block(btreemap![str!("key_a") => TU::s("val_a")]);
+        let block_b = Block(btreemap![str!("key_b") => TU::s("val_b")]);
+
+        Format::Yaml.write_schema_path(&path, &Schema::One(block_a.clone())).unwrap();
+        match Format::Yaml.read_schema_path(&path, &Arity::Unit) {
+            Ok(Schema::One(produced)) => assert_eq!(block_a, produced),
+            _ => panic!("unexpected schema"),
+        }
+
+        // Writing again replaces the existing file.
+        Format::Yaml.write_schema_path(&path, &Schema::One(block_b.clone())).unwrap();
+        match Format::Yaml.read_schema_path(&path, &Arity::Unit) {
+            Ok(Schema::One(produced)) => assert_eq!(block_b, produced),
+            _ => panic!("unexpected schema"),
+        }
+
+        // Concurrent writers in the same process do not share temporary files,
+        // so the result is always one complete meta file.
+        std::thread::scope(|scope| {
+            for block in [&block_a, &block_b].repeat(4) {
+                let path = &path;
+                scope.spawn(move || {
+                    Format::Yaml.write_schema_path(path, &Schema::One(block.clone())).unwrap();
+                });
+            }
+        });
+        match Format::Yaml.read_schema_path(&path, &Arity::Unit) {
+            Ok(Schema::One(produced)) => assert!(produced == block_a || produced == block_b),
+            _ => panic!("unexpected schema"),
+        }
+
+        // No temporary files are left behind.
+        let file_names = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(file_names, vec![std::ffi::OsString::from("self.yml")]);
+
+        // Writing into a missing directory fails.
+        let path = temp_dir.path().join("missing").join("self.yml");
+        let res = Format::Yaml.write_schema_path(&path, &Schema::One(block_a));
+        assert!(matches!(res, Err(Error::CannotWriteFile(_))));
+    }
 }
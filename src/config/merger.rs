@@ -0,0 +1,426 @@
+//! Defines how metadata blocks from multiple sources are merged together.
+
+use std::convert::TryFrom;
+use std::mem;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::config::selection::MatcherError;
+use crate::types::{Block, Value};
+
+/// A string value that marks a key for deletion. When merging, a tombstone
+/// removes the key from the merged block instead of being stored, so that a
+/// later source can unset a value provided by an earlier source.
+pub const TOMBSTONE: &str = "$unset";
+
+/// Returns whether a value is a tombstone.
+pub fn is_tombstone(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s == TOMBSTONE)
+}
+
+/// Describes what happened to a key when a later value was merged into it.
+#[derive(Debug)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub(crate) enum MergeOutcome {
+    /// There was no earlier value, and the later value was inserted.
+    Inserted,
+
+    /// The earlier value was kept as is, or there was nothing to remove.
+    Unchanged,
+
+    /// The earlier and later values were combined into one value.
+    Combined,
+
+    /// The earlier value was replaced by the later value.
+    Replaced(Value),
+
+    /// The earlier value was removed by a tombstone.
+    Removed(Value),
+}
+
+/// Merges a later value for a key into a block, using a merge strategy.
+/// Tombstones always remove the key, regardless of the strategy.
+fn merge_key(earlier: &mut Block, key: String, later_val: Value, strategy: MergeStrategy) -> MergeOutcome {
+    if is_tombstone(&later_val) {
+        return match earlier.remove(&key) {
+            Some(earlier_val) => MergeOutcome::Removed(earlier_val),
+            None => MergeOutcome::Unchanged,
+        };
+    }
+
+    match earlier.get_mut(&key) {
+        Some(earlier_val) => {
+            if strategy.combines(earlier_val, &later_val) {
+                strategy.merge(earlier_val, later_val);
+                MergeOutcome::Combined
+            } else if let MergeStrategy::KeepFirst = strategy {
+                MergeOutcome::Unchanged
+            } else {
+                MergeOutcome::Replaced(mem::replace(earlier_val, strip_tombstones(later_val)))
+            }
+        },
+        None => {
+            earlier.insert(key, strip_tombstones(later_val));
+            MergeOutcome::Inserted
+        },
+    }
+}
+
+/// Removes any nested tombstones from a value that is not merged with an
+/// earlier value, since there is nothing for them to remove. Tombstones that
+/// are elements of sequences are also removed, since sequence elements are
+/// not keyed and cannot be unset.
+fn strip_tombstones(value: Value) -> Value {
+    match value {
+        Value::Mapping(block) => Value::Mapping(
+            block
+                .into_iter()
+                .filter(|(_, v)| !is_tombstone(v))
+                .map(|(k, v)| (k, strip_tombstones(v)))
+                .collect()
+        ),
+        Value::Sequence(seq) => Value::Sequence(strip_seq_tombstones(seq)),
+        other => other,
+    }
+}
+
+fn strip_seq_tombstones(seq: Vec<Value>) -> Vec<Value> {
+    seq.into_iter()
+        .filter(|v| !is_tombstone(v))
+        .map(strip_tombstones)
+        .collect()
+}
+
+/// Represents how a value from a later source is merged with a value for the
+/// same key from an earlier source.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The later value replaces the earlier value.
+    #[default]
+    Overwrite,
+
+    /// The earlier value is kept, and the later value is discarded.
+    KeepFirst,
+
+    /// If both values are sequences, the later sequence is appended to the
+    /// earlier sequence. Otherwise, the later value replaces the earlier one.
+    Append,
+
+    /// Similar to `Append`, but elements of the later sequence that are equal
+    /// to an element of the earlier sequence are skipped.
+    Union,
+
+    /// If both values are mappings, they are merged recursively, with nested
+    /// mappings also being merged and other nested values being replaced.
+    /// Nested tombstones remove their keys from the merged mapping.
+    /// Otherwise, the later value replaces the earlier one.
+    DeepMerge,
+}
+
+impl MergeStrategy {
+    /// Merges a later value into an earlier value using this strategy.
+    pub fn merge(&self, earlier: &mut Value, later: Value) {
+        match (self, earlier, later) {
+            (Self::KeepFirst, ..) => {},
+            (Self::Append, Value::Sequence(e), Value::Sequence(l)) => e.extend(strip_seq_tombstones(l)),
+            (Self::Union, Value::Sequence(e), Value::Sequence(l)) => {
+                for val in strip_seq_tombstones(l) {
+                    if !e.iter().any(|v| v.val_eq(&val)) {
+                        e.push(val);
+                    }
+                }
+            },
+            (Self::DeepMerge, Value::Mapping(e), Value::Mapping(l)) => {
+                for (key, later_val) in l {
+                    merge_key(e, key, later_val, Self::DeepMerge);
+                }
+            },
+            (_, earlier, later) => *earlier = strip_tombstones(later),
+        }
+    }
+
+    /// Returns whether this strategy combines an earlier and a later value,
+    /// instead of keeping or replacing the earlier value.
+    fn combines(&self, earlier: &Value, later: &Value) -> bool {
+        matches!(
+            (self, earlier, later),
+            (Self::Append | Self::Union, Value::Sequence(_), Value::Sequence(_))
+            | (Self::DeepMerge, Value::Mapping(_), Value::Mapping(_))
+        )
+    }
+}
+
+/// Merges metadata blocks, choosing a merge strategy for each key based on
+/// glob patterns. The first pattern that matches a key decides its strategy,
+/// and keys that match no patterns use the default strategy.
+/// Patterns only apply to the top-level keys of blocks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "MergerRepr")]
+pub struct Merger {
+    default: MergeStrategy,
+    key_patterns: GlobSet,
+    key_strategies: Vec<MergeStrategy>,
+}
+
+impl Default for Merger {
+    fn default() -> Self {
+        Self::new(MergeStrategy::default())
+    }
+}
+
+impl Merger {
+    /// Creates a new merger that uses a default strategy for all keys.
+    pub fn new(default: MergeStrategy) -> Self {
+        Self {
+            default,
+            key_patterns: GlobSet::empty(),
+            key_strategies: Vec::new(),
+        }
+    }
+
+    /// Attempts to build a merger out of a default strategy and an iterable of
+    /// key patterns and their strategies, in priority order.
+    pub fn build<I, S>(default: MergeStrategy, key_strategies: I) -> Result<Self, MatcherError>
+    where
+        I: IntoIterator<Item = (S, MergeStrategy)>,
+        S: AsRef<str>,
+    {
+        let mut builder = GlobSetBuilder::new();
+        let mut strategies = Vec::new();
+
+        for (pattern, strategy) in key_strategies {
+            let glob = Glob::new(pattern.as_ref()).map_err(|e| MatcherError::Pattern(e.into()))?;
+            builder.add(glob);
+            strategies.push(strategy);
+        }
+
+        let key_patterns = builder.build().map_err(|e| MatcherError::Build(e.into()))?;
+
+        Ok(Self { default, key_patterns, key_strategies: strategies })
+    }
+
+    /// Returns the merge strategy to use for a key.
+    pub fn strategy(&self, key: &str) -> MergeStrategy {
+        // Matches are returned in pattern order, so the first one has priority.
+        self.key_patterns
+            .matches(key)
+            .first()
+            .map(|&i| self.key_strategies[i])
+            .unwrap_or(self.default)
+    }
+
+    /// Merges a later block into an earlier block, key by key.
+    /// Keys with tombstone values in the later block are removed from the
+    /// earlier block.
+    pub fn merge(&self, earlier: &mut Block, later: Block) {
+        for (key, later_val) in later {
+            self.merge_entry(earlier, key, later_val);
+        }
+    }
+
+    /// Merges a later value for a single key into an earlier block, and
+    /// reports what happened to the earlier value.
+    pub(crate) fn merge_entry(&self, earlier: &mut Block, key: String, later_val: Value) -> MergeOutcome {
+        let strategy = self.strategy(&key);
+        merge_key(earlier, key, later_val, strategy)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MergerRepr {
+    default: MergeStrategy,
+    keys: IndexMap<String, MergeStrategy>,
+}
+
+impl TryFrom<MergerRepr> for Merger {
+    type Error = MatcherError;
+
+    fn try_from(value: MergerRepr) -> Result<Self, Self::Error> {
+        Self::build(value.default, value.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn merge_strategy() {
+        let seq_a = Value::Sequence(vec![TU::s("jazz"), TU::s("funk")]);
+        let seq_b = Value::Sequence(vec![TU::s("funk"), TU::s("soul")]);
+        let map_a = Value::Mapping(Block(btreemap![
+            str!("name") => TU::s("Aiguille"),
+            str!("links") => Value::Mapping(Block(btreemap![
+                str!("web") => TU::s("a.example"),
+            ])),
+        ]));
+        let map_b = Value::Mapping(Block(btreemap![
+            str!("country") => TU::s("FR"),
+            str!("links") => Value::Mapping(Block(btreemap![
+                str!("social") => TU::s("b.example"),
+            ])),
+        ]));
+
+        let inputs_and_expected = vec![
+            ((MergeStrategy::Overwrite, seq_a.clone(), seq_b.clone()), seq_b.clone()),
+            ((MergeStrategy::KeepFirst, seq_a.clone(), seq_b.clone()), seq_a.clone()),
+            (
+                (MergeStrategy::Append, seq_a.clone(), seq_b.clone()),
+                Value::Sequence(vec![TU::s("jazz"), TU::s("funk"), TU::s("funk"), TU::s("soul")]),
+            ),
+            (
+                (MergeStrategy::Union, seq_a.clone(), seq_b.clone()),
+                Value::Sequence(vec![TU::s("jazz"), TU::s("funk"), TU::s("soul")]),
+            ),
+            ((MergeStrategy::Append, TU::s("jazz"), seq_b.clone()), seq_b.clone()),
+            ((MergeStrategy::Union, seq_a.clone(), TU::s("soul")), TU::s("soul")),
+            (
+                (MergeStrategy::DeepMerge, map_a.clone(), map_b.clone()),
+                Value::Mapping(Block(btreemap![
+                    str!("name") => TU::s("Aiguille"),
+                    str!("country") => TU::s("FR"),
+                    str!("links") => Value::Mapping(Block(btreemap![
+                        str!("web") => TU::s("a.example"),
+                        str!("social") => TU::s("b.example"),
+                    ])),
+                ])),
+            ),
+            ((MergeStrategy::Overwrite, map_a.clone(), map_b.clone()), map_b.clone()),
+            ((MergeStrategy::DeepMerge, map_a.clone(), seq_b.clone()), seq_b.clone()),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (strategy, mut earlier, later) = input;
+            strategy.merge(&mut earlier, later);
+            assert_eq!(expected, earlier);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let merger = Merger::build(
+            MergeStrategy::Overwrite,
+            vec![
+                ("genre", MergeStrategy::Union),
+                ("artist*", MergeStrategy::Append),
+                ("*", MergeStrategy::KeepFirst),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(MergeStrategy::Union, merger.strategy("genre"));
+        assert_eq!(MergeStrategy::Append, merger.strategy("artists"));
+        assert_eq!(MergeStrategy::KeepFirst, merger.strategy("title"));
+
+        let mut earlier = Block(btreemap![
+            str!("genre") => Value::Sequence(vec![TU::s("jazz")]),
+            str!("artists") => Value::Sequence(vec![TU::s("Aiguille")]),
+            str!("title") => TU::s("Daylight"),
+        ]);
+        let later = Block(btreemap![
+            str!("genre") => Value::Sequence(vec![TU::s("funk"), TU::s("jazz")]),
+            str!("artists") => Value::Sequence(vec![TU::s("Aiguille")]),
+            str!("title") => TU::s("Nightfall"),
+            str!("rating") => TU::i(5),
+        ]);
+
+        merger.merge(&mut earlier, later);
+
+        let expected = Block(btreemap![
+            str!("genre") => Value::Sequence(vec![TU::s("jazz"), TU::s("funk")]),
+            str!("artists") => Value::Sequence(vec![TU::s("Aiguille"), TU::s("Aiguille")]),
+            str!("title") => TU::s("Daylight"),
+            str!("rating") => TU::i(5),
+        ]);
+        assert_eq!(expected, earlier);
+    }
+
+    #[test]
+    fn tombstones() {
+        let merger = Merger::build(
+            MergeStrategy::Overwrite,
+            vec![("credits", MergeStrategy::DeepMerge), ("genre", MergeStrategy::KeepFirst)],
+        )
+        .unwrap();
+
+        let mut earlier = Block(btreemap![
+            str!("feat") => TU::s("Evil Needle"),
+            str!("genre") => TU::s("jazz"),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("composer") => TU::s("Aiguille"),
+                str!("producer") => TU::s("Aiguille"),
+            ])),
+        ]);
+        let later = Block(btreemap![
+            str!("feat") => TU::s(TOMBSTONE),
+            str!("genre") => TU::s(TOMBSTONE),
+            str!("rating") => TU::s(TOMBSTONE),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("composer") => TU::s(TOMBSTONE),
+                str!("mixer") => TU::s("Evil Needle"),
+            ])),
+            str!("links") => Value::Mapping(Block(btreemap![
+                str!("web") => TU::s(TOMBSTONE),
+            ])),
+            str!("tags") => Value::Sequence(vec![
+                TU::s("calm"),
+                TU::s(TOMBSTONE),
+                Value::Mapping(Block(btreemap![str!("mood") => TU::s(TOMBSTONE)])),
+            ]),
+        ]);
+
+        merger.merge(&mut earlier, later);
+
+        // Tombstones never end up in the merged block, even if there was no
+        // earlier value to remove, and even with a strategy of `KeepFirst`.
+        let expected = Block(btreemap![
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("mixer") => TU::s("Evil Needle"),
+                str!("producer") => TU::s("Aiguille"),
+            ])),
+            str!("links") => Value::Mapping(Block::new()),
+            str!("tags") => Value::Sequence(vec![TU::s("calm"), Value::Mapping(Block::new())]),
+        ]);
+        assert_eq!(expected, earlier);
+
+        // Tombstones in sequences that are combined are also removed.
+        for strategy in [MergeStrategy::Append, MergeStrategy::Union] {
+            let mut earlier = Value::Sequence(vec![TU::s("jazz")]);
+            strategy.merge(&mut earlier, Value::Sequence(vec![TU::s(TOMBSTONE), TU::s("funk")]));
+            assert_eq!(Value::Sequence(vec![TU::s("jazz"), TU::s("funk")]), earlier);
+        }
+    }
+
+    #[test]
+    fn deserialization() {
+        let text = indoc! {r#"
+            default = "deep_merge"
+
+            [keys]
+            genre = "union"
+            "*" = "keep_first"
+        "#};
+
+        let merger: Merger = toml::from_str(text).unwrap();
+
+        assert_eq!(MergeStrategy::Union, merger.strategy("genre"));
+        assert_eq!(MergeStrategy::KeepFirst, merger.strategy("title"));
+
+        let merger: Merger = toml::from_str("").unwrap();
+
+        assert_eq!(MergeStrategy::Overwrite, merger.strategy("genre"));
+
+        assert!(toml::from_str::<Merger>(r#"default = "unknown""#).is_err());
+        assert!(toml::from_str::<Merger>("[keys]\n\"[abc\" = \"union\"").is_err());
+    }
+}
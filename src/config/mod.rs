@@ -1,22 +1,28 @@
 //! Provides configuration options for a library, both programmatically and via config files.
 
+pub mod diagnostic;
 pub mod format;
+pub mod merger;
 pub mod selection;
 pub mod sorter;
 
+pub use self::diagnostic::Diagnostic;
 pub use self::format::{Format, Error as FormatError};
+pub use self::merger::{Merger, MergeStrategy, TOMBSTONE};
 pub use self::selection::Selection;
 pub use self::sorter::Sorter;
 
 use std::convert::{TryFrom, TryInto};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 use thiserror::Error;
 
 use self::selection::{SelectionRepr, MatcherError};
 
-use crate::sources::{Anchor, Source, Sourcer, CreateError as SourceCreateError};
+use crate::metadata::processor::Strictness;
+use crate::metadata::table::CellTyping;
+use crate::sources::{Anchor, Layout, Mirror, Source, Sourcer, CreateError as SourceCreateError};
 
 const DEFAULT_INTERNAL_STUB: &str = "album";
 const DEFAULT_EXTERNAL_STUB: &str = "track";
@@ -36,6 +42,14 @@ pub struct SourcesRepr {
     external: Vec<String>,
     #[serde(rename = "album")]
     internal: Vec<String>,
+    combined: Vec<String>,
+    sidecar: Vec<String>,
+    index: Vec<String>,
+    meta_dir: Option<String>,
+    mirror: Option<Mirror>,
+    embedded: bool,
+    cell_typing: CellTyping,
+    coerce_dates: bool,
 }
 
 impl Default for SourcesRepr {
@@ -46,7 +60,16 @@ impl Default for SourcesRepr {
         let external = vec![format!("{}.{}", DEFAULT_EXTERNAL_STUB, default_ext)];
         let internal = vec![format!("{}.{}", DEFAULT_INTERNAL_STUB, default_ext)];
 
-        Self { external, internal, }
+        let combined = Vec::new();
+        let sidecar = Vec::new();
+        let index = Vec::new();
+        let meta_dir = None;
+        let mirror = None;
+        let embedded = false;
+        let cell_typing = CellTyping::default();
+        let coerce_dates = false;
+
+        Self { external, internal, combined, sidecar, index, meta_dir, mirror, embedded, cell_typing, coerce_dates, }
     }
 }
 
@@ -59,6 +82,24 @@ pub(crate) struct ConfigRepr {
     pub sorter_repr: Sorter,
     #[serde(rename = "sourcing")]
     pub sources_repr: SourcesRepr,
+    #[serde(rename = "merging")]
+    pub merger: Merger,
+    #[serde(rename = "processing")]
+    pub processing_repr: ProcessingRepr,
+    #[serde(rename = "caching")]
+    pub caching_repr: CachingRepr,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ProcessingRepr {
+    strictness: Strictness,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CachingRepr {
+    dir: Option<PathBuf>,
 }
 
 #[derive(Deserialize)]
@@ -67,6 +108,12 @@ pub struct Config {
     pub selection: Selection,
     pub sorter: Sorter,
     pub sourcer: Sourcer,
+    pub merger: Merger,
+    pub strictness: Strictness,
+
+    /// The directory to store a persistent cache of meta files in. If `None`,
+    /// meta files are only cached in memory.
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl TryFrom<ConfigRepr> for Config {
@@ -77,21 +124,83 @@ impl TryFrom<ConfigRepr> for Config {
 
         let mut selection_repr = value.selection_repr;
 
-        for name in value.sources_repr.external {
+        let sources_repr = value.sources_repr;
+
+        // Index meta files cover a whole library, so they have the lowest priority.
+        for name in sources_repr.index {
+            let src = Source::from_name(name, Anchor::Index)?;
+            sources.push(src);
+        }
+
+        for name in sources_repr.external {
             let src = Source::from_name(name, Anchor::External)?;
             sources.push(src);
         }
 
-        for name in value.sources_repr.internal {
+        // Combined meta files act as both an external and an internal source.
+        for name in sources_repr.combined.iter() {
+            let src = Source::from_name(name.clone(), Anchor::External)?;
+            sources.push(src.with_layout(Layout::Combined));
+        }
+
+        for name in sources_repr.sidecar {
+            let src = Source::from_name(name, Anchor::Sidecar)?;
+            sources.push(src);
+        }
+
+        for name in sources_repr.internal {
             let src = Source::from_name(name, Anchor::Internal)?;
             sources.push(src);
         }
 
+        for name in sources_repr.combined {
+            let src = Source::from_name(name, Anchor::Internal)?;
+            sources.push(src.with_layout(Layout::Combined));
+        }
+
+        let cell_typing = sources_repr.cell_typing;
+        let coerce_dates = sources_repr.coerce_dates;
+        sources = sources
+            .into_iter()
+            .map(|src| src.with_cell_typing(cell_typing).with_coerce_dates(coerce_dates))
+            .collect();
+
+        if let Some(mirror) = sources_repr.mirror {
+            sources = sources
+                .into_iter()
+                .map(|src| src.with_mirror(mirror.clone()))
+                .collect();
+        }
+
+        if let Some(meta_dir) = sources_repr.meta_dir {
+            sources = sources
+                .into_iter()
+                .map(|src| src.with_meta_dir(meta_dir.clone()))
+                .collect::<Result<_, _>>()?;
+
+            if selection_repr.exclude_sources {
+                // Add the meta directory to the list of excluded directories.
+                selection_repr.exclude_dirs.add_pattern(&meta_dir).map_err(Into::<MatcherError>::into)?;
+            }
+        }
+
+        // Embedded tags are a fallback for any external meta files, so they
+        // have the lowest priority of all.
+        if sources_repr.embedded {
+            sources.insert(0, Source::embedded());
+        }
+
         if selection_repr.exclude_sources {
             // Add sources to the list of excluded files.
-            for source in sources.iter() {
-                let pattern = &source.name;
-                selection_repr.exclude_files.add_pattern(pattern).map_err(Into::<MatcherError>::into)?;
+            // NOTE: Item files that contain embedded tags are never excluded.
+            for pattern in sources.iter().filter_map(Source::name_pattern) {
+                selection_repr.exclude_files.add_pattern(&pattern).map_err(Into::<MatcherError>::into)?;
+            }
+
+            // Sidecar meta file names depend on their item files, so these are
+            // excluded based on the other entries in their directories.
+            for template in sources.iter().filter_map(Source::sidecar_template) {
+                selection_repr.exclude_sidecars.push(template.to_owned());
             }
         }
 
@@ -104,6 +213,9 @@ impl TryFrom<ConfigRepr> for Config {
             selection,
             sorter: value.sorter_repr,
             sourcer,
+            merger: value.merger,
+            strictness: value.processing_repr.strictness,
+            cache_dir: value.caching_repr.dir,
         })
     }
 }
@@ -131,6 +243,8 @@ impl Config {
 mod tests {
     use super::*;
 
+    use std::ffi::OsStr;
+
     use crate::config::sorter::sort_by::SortBy;
 
     use str_macro::str;
@@ -225,5 +339,182 @@ mod tests {
                 Source::from_name(str!("album.json"), Anchor::Internal).unwrap(),
             ]
         );
+
+        let text_config = r#"
+            [sourcing]
+            track = []
+            album = []
+            combined = ["album.toml"]
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(config.selection.is_file_pattern_match(&"music.flac"));
+        assert!(!config.selection.is_file_pattern_match(&"album.toml"));
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("album.toml"), Anchor::External).unwrap()
+                    .with_layout(Layout::Combined),
+                Source::from_name(str!("album.toml"), Anchor::Internal).unwrap()
+                    .with_layout(Layout::Combined),
+            ]
+        );
+
+        let text_config = r#"
+            [merging]
+            default = "keep_first"
+            [merging.keys]
+            "genre*" = "union"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.merger.strategy("genres"), MergeStrategy::Union);
+        assert_eq!(config.merger.strategy("title"), MergeStrategy::KeepFirst);
+        assert_eq!(config.strictness, Strictness::Strict);
+
+        let text_config = r#"
+            [processing]
+            strictness = "lenient"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.strictness, Strictness::Lenient);
+        assert_eq!(config.cache_dir, None);
+
+        let text_config = r#"
+            [caching]
+            dir = "/tmp/anagma"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/tmp/anagma")));
+
+        let text_config = r#"
+            [sourcing]
+            track = ["tracks.csv", "tracks.tsv"]
+            cell_typing = "strings"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(!config.selection.is_file_pattern_match(&"tracks.csv"));
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("tracks.csv"), Anchor::External).unwrap()
+                    .with_cell_typing(CellTyping::Strings),
+                Source::from_name(str!("tracks.tsv"), Anchor::External).unwrap()
+                    .with_cell_typing(CellTyping::Strings),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap()
+                    .with_cell_typing(CellTyping::Strings),
+            ]
+        );
+
+        let text_config = r#"
+            [sourcing]
+            coerce_dates = true
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("track.json"), Anchor::External).unwrap()
+                    .with_coerce_dates(true),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap()
+                    .with_coerce_dates(true),
+            ]
+        );
+
+        let text_config = r#"
+            [sourcing]
+            embedded = true
+            meta_dir = ".meta"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(config.selection.is_file_pattern_match(&"music.flac"));
+        assert!(!config.selection.is_file_pattern_match(&"track.json"));
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::embedded(),
+                Source::from_name(str!("track.json"), Anchor::External).unwrap()
+                    .with_meta_dir(str!(".meta")).unwrap(),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap()
+                    .with_meta_dir(str!(".meta")).unwrap(),
+            ]
+        );
+
+        let text_config = r#"
+            [sourcing]
+            index = ["catalog.yml"]
+            sidecar = ["{name}.yml"]
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(config.selection.is_file_pattern_match(&"music.flac"));
+        assert!(config.selection.is_file_pattern_match(&"music.flac.yml"));
+        assert!(config.selection.sidecar_names(vec![OsStr::new("music.flac")]).contains(OsStr::new("music.flac.yml")));
+        assert!(!config.selection.is_file_pattern_match(&"catalog.yml"));
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("catalog.yml"), Anchor::Index).unwrap(),
+                Source::from_name(str!("track.json"), Anchor::External).unwrap(),
+                Source::from_name(str!("{name}.yml"), Anchor::Sidecar).unwrap(),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap(),
+            ]
+        );
+
+        let text_config = r#"
+            [sourcing]
+            track = ["track.yml"]
+            album = ["self.yml"]
+            meta_dir = ".anagma"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(config.selection.is_dir_pattern_match(&"music"));
+        assert!(!config.selection.is_dir_pattern_match(&".anagma"));
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+                    .with_meta_dir(str!(".anagma")).unwrap(),
+                Source::from_name(str!("self.yml"), Anchor::Internal).unwrap()
+                    .with_meta_dir(str!(".anagma")).unwrap(),
+            ]
+        );
+
+        let text_config = r#"
+            [sourcing]
+            track = ["track.yml"]
+            album = ["self.yml"]
+            [sourcing.mirror]
+            media_root = "/media"
+            meta_root = "/meta"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        let mirror = Mirror::new("/media".into(), "/meta".into());
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+                    .with_mirror(mirror.clone()),
+                Source::from_name(str!("self.yml"), Anchor::Internal).unwrap()
+                    .with_mirror(mirror),
+            ]
+        );
     }
 }
@@ -1,4 +1,5 @@
 use std::convert::{TryFrom, TryInto};
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 
 use globset::Error as GlobError;
@@ -27,11 +28,11 @@ pub enum Error {
 }
 
 #[derive(Debug)]
-pub(crate) struct MatcherBuilder(GlobSetBuilder);
+pub(crate) struct MatcherBuilder(GlobSetBuilder, Vec<Glob>);
 
 impl MatcherBuilder {
     pub fn new() -> Self {
-        Self(GlobSetBuilder::new())
+        Self(GlobSetBuilder::new(), Vec::new())
     }
 
     pub fn add_pattern<S: AsRef<str>>(&mut self, pattern: &S) -> Result<(), PatternError> {
@@ -40,18 +41,26 @@ impl MatcherBuilder {
     }
 
     pub fn add_glob(&mut self, glob: Glob) {
-        self.0.add(glob);
+        self.0.add(glob.clone());
+        self.1.push(glob);
     }
 
     pub fn build(self) -> Result<Matcher, BuildError> {
-        Ok(Matcher(self.0.build()?))
+        Ok(Matcher(self.0.build()?, self.1))
     }
 }
 
 /// Filter for file paths that uses zero or more glob patterns to perform matching.
+/// The original globs are kept, so that matchers can be hashed.
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "MatcherRepr")]
-pub struct Matcher(GlobSet);
+pub struct Matcher(GlobSet, Vec<Glob>);
+
+impl Hash for Matcher {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.1.hash(state);
+    }
+}
 
 impl Matcher {
     /// Attempts to build a matcher out of an iterable of string-likes.
@@ -87,7 +96,7 @@ impl Matcher {
 
     /// Returns a matcher that matches no paths.
     pub fn empty() -> Self {
-        Self(GlobSet::empty())
+        Self(GlobSet::empty(), Vec::new())
     }
 }
 
@@ -1,14 +1,17 @@
 mod matcher;
 
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
-use std::fs::ReadDir;
+use std::ffi::{OsStr, OsString};
 use std::io::Result as IoResult;
 use std::path::Path;
 use std::path::PathBuf;
+use std::vec::IntoIter as VecIntoIter;
 
 use serde::Deserialize;
 
 use crate::config::Sorter;
+use crate::sources::source::render_sidecar_name;
 
 pub use self::matcher::{Error as MatcherError, Matcher};
 pub(crate) use self::matcher::MatcherRepr;
@@ -19,12 +22,13 @@ enum FileOrDir {
 }
 
 /// A type that represents included and excluded item files and directories.
-#[derive(Debug)]
+#[derive(Debug, Hash)]
 pub struct Selection {
     include_files: Matcher,
     exclude_files: Matcher,
     include_dirs: Matcher,
     exclude_dirs: Matcher,
+    exclude_sidecars: Vec<String>,
 }
 
 impl Default for Selection {
@@ -50,9 +54,57 @@ impl Selection {
             exclude_files,
             include_dirs,
             exclude_dirs,
+            exclude_sidecars: Vec::new(),
         }
     }
 
+    /// Excludes the meta files of a sidecar source, given its name template.
+    /// Since sidecar meta file names are derived from the names of their item
+    /// files, these are recognised by rendering the template for the other
+    /// entries in the same directory.
+    pub fn with_excluded_sidecar(mut self, template: String) -> Self {
+        self.exclude_sidecars.push(template);
+        self
+    }
+
+    /// Returns the names of the sidecar meta files that belong to any of the
+    /// given sibling names, which are excluded from selection.
+    pub fn sidecar_names<'a, I>(&self, sibling_names: I) -> HashSet<OsString>
+    where
+        I: IntoIterator<Item = &'a OsStr>,
+    {
+        if self.exclude_sidecars.is_empty() {
+            return HashSet::new();
+        }
+
+        sibling_names
+            .into_iter()
+            .flat_map(|name| {
+                self.exclude_sidecars
+                    .iter()
+                    .map(move |template| render_sidecar_name(template, name))
+                    // A meta file is never its own item file.
+                    .filter(move |rendered| rendered != name)
+            })
+            .collect()
+    }
+
+    /// Returns the names of the sidecar meta files inside a directory, which
+    /// are excluded from selection.
+    /// This only reads the directory if there are any excluded sidecars.
+    pub fn sidecar_names_in_dir(&self, dir_path: &Path) -> IoResult<HashSet<OsString>> {
+        if self.exclude_sidecars.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let sibling_names = dir_path
+            .read_dir()?
+            .map(|res| res.map(|e| e.file_name()))
+            .collect::<IoResult<Vec<_>>>()?;
+
+        Ok(self.sidecar_names(sibling_names.iter().map(OsString::as_os_str)))
+    }
+
     pub fn from_patterns<'a, IA, SA, IB, SB, IC, SC, ID, SD>(
         include_file_patterns: IA,
         exclude_file_patterns: IB,
@@ -111,11 +163,29 @@ impl Selection {
 
     /// Returns true if a path is selected.
     /// This accesses the filesystem to tell if the path is a file or directory.
+    /// Note that excluded sidecar meta files are not detected by this method,
+    /// use `is_selected_excluding` with the sidecar names of the containing
+    /// directory for that.
     pub fn is_selected<P: AsRef<Path>>(&self, path: &P) -> IoResult<bool> {
+        self.is_selected_excluding(path, &HashSet::new())
+    }
+
+    /// Similar to `is_selected`, but also treats files with any of the given
+    /// names as not selected.
+    pub fn is_selected_excluding<P: AsRef<Path>>(
+        &self,
+        path: &P,
+        excluded_names: &HashSet<OsString>,
+    ) -> IoResult<bool> {
         let file_info = std::fs::metadata(&path)?;
 
         Ok(if file_info.is_file() {
-            self.is_file_pattern_match(path)
+            let is_excluded = path
+                .as_ref()
+                .file_name()
+                .is_some_and(|name| excluded_names.contains(name));
+
+            !is_excluded && self.is_file_pattern_match(path)
         } else if file_info.is_dir() {
             self.is_dir_pattern_match(path)
         } else {
@@ -129,9 +199,18 @@ impl Selection {
     //       `Result`s for errors encountered when iterating over sub-paths.
     pub fn select_in_dir(&self, dir_path: &Path) -> IoResult<SelectedSubPaths> {
         // Try to open the path as a directory, handle the error as appropriate.
-        let dir_reader = dir_path.read_dir()?;
+        // The directory is only read once, and the listing is used for both
+        // finding sidecar meta files and selecting sub-paths.
+        let listing = dir_path
+            .read_dir()?
+            .map(|res| res.map(|e| e.path()))
+            .collect::<Vec<_>>();
+
+        let sidecar_names = self.sidecar_names(
+            listing.iter().filter_map(|res| res.as_ref().ok()?.file_name())
+        );
 
-        Ok(SelectedSubPaths(dir_reader, &self))
+        Ok(SelectedSubPaths(listing.into_iter(), sidecar_names, &self))
     }
 
     /// Selects paths inside a directory that match this `Selection`, and sorts them.
@@ -156,6 +235,8 @@ pub(crate) struct SelectionRepr {
     pub exclude_files: MatcherRepr,
     pub include_dirs: MatcherRepr,
     pub exclude_dirs: MatcherRepr,
+    #[serde(skip)]
+    pub exclude_sidecars: Vec<String>,
 }
 
 impl Default for SelectionRepr {
@@ -166,6 +247,7 @@ impl Default for SelectionRepr {
             exclude_files: MatcherRepr::Empty,
             include_dirs: MatcherRepr::Any,
             exclude_dirs: MatcherRepr::Empty,
+            exclude_sidecars: Vec::new(),
         }
     }
 }
@@ -179,25 +261,26 @@ impl TryFrom<SelectionRepr> for Selection {
             exclude_files: value.exclude_files.try_into()?,
             include_dirs: value.include_dirs.try_into()?,
             exclude_dirs: value.exclude_dirs.try_into()?,
+            exclude_sidecars: value.exclude_sidecars,
         })
     }
 }
 
-pub struct SelectedSubPaths<'a>(ReadDir, &'a Selection);
+pub struct SelectedSubPaths<'a>(VecIntoIter<IoResult<PathBuf>>, HashSet<OsString>, &'a Selection);
 
 impl<'a> Iterator for SelectedSubPaths<'a> {
     type Item = IoResult<PathBuf>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // LEARN: Unable to inline these, had to use `let`, why is that?
-        let read_dir = &mut self.0;
-        let selection = &self.1;
-
-        // Get next entry from the directory reader.
-        read_dir.find_map(|res| match res {
-            Ok(dir_entry) => {
-                let sub_path = dir_entry.path();
-                match selection.is_selected(&sub_path) {
+        let listing = &mut self.0;
+        let sidecar_names = &self.1;
+        let selection = &self.2;
+
+        // Get next entry from the directory listing.
+        listing.find_map(|res| match res {
+            Ok(sub_path) => {
+                match selection.is_selected_excluding(&sub_path, sidecar_names) {
                     Ok(true) => Some(Ok(sub_path)),
                     Ok(false) => None,
                     Err(err) => Some(Err(err)),
@@ -1,7 +1,9 @@
 pub mod config;
+pub mod library;
 pub mod metadata;
 pub mod sources;
 pub mod types;
+pub mod watch;
 mod util;
 
 #[cfg(test)] mod test_util;
@@ -9,21 +11,32 @@ mod util;
 use std::path::Path;
 
 use crate::config::Config;
-use crate::metadata::processor::Processor;
+use crate::metadata::processor::{Error as ProcessorError, Processor, Warning};
 use crate::types::Block;
 
+pub use crate::library::Library;
 pub use crate::util::FileWalker;
 
-pub fn get<P: AsRef<Path>>(path: &P) -> Block {
+pub fn get<P: AsRef<Path>>(path: &P) -> Result<Block, ProcessorError> {
     let config = Config::default();
     get_with_config(path, &config)
 }
 
-pub fn get_with_config<P: AsRef<Path>>(path: &P, config: &Config) -> Block {
-    Processor::process_item_file(
+/// Loads the metadata for an item file. If the config is lenient, meta files
+/// that cause errors are skipped, and their warnings are discarded.
+pub fn get_with_config<P: AsRef<Path>>(path: &P, config: &Config) -> Result<Block, ProcessorError> {
+    get_with_warnings(path, config).map(|(block, _)| block)
+}
+
+/// Loads the metadata for an item file, along with any warnings for meta
+/// files that were skipped if the config is lenient.
+pub fn get_with_warnings<P: AsRef<Path>>(path: &P, config: &Config) -> Result<(Block, Vec<Warning>), ProcessorError> {
+    Processor::process_item_file_with_warnings(
         path.as_ref(),
         &config.sourcer,
         &config.selection,
         &config.sorter,
-    ).unwrap()
+        &config.merger,
+        config.strictness,
+    )
 }
@@ -0,0 +1,545 @@
+//! A handle to a tree of item files and their metadata, which processes each
+//! meta file at most once.
+
+use std::borrow::Cow;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use indexmap::IndexMap;
+
+use crate::config::Config;
+use crate::metadata::cache::{CACHE_FILE_NAME, Error as CacheError, MetaCache};
+use crate::metadata::filter::Filter;
+use crate::metadata::processor::{Aggregation, Error as ProcessorError, Processed, Processor, Warning};
+use crate::types::{Block, Value};
+use crate::util::file_walker::ChildFileWalker;
+
+/// Owns a root directory and a config, along with a cache of plexed meta files.
+/// Item files in the same directory usually share meta files, so the cache
+/// avoids reading and plexing those meta files again for each item file.
+/// If the config has a cache directory, the cache is persistent, and is loaded
+/// from and saved to a cache file in that directory. A persistent cache checks
+/// whether meta files changed on disk. Otherwise, the cache is only kept in
+/// memory, and is not invalidated when meta files change on disk.
+/// A library can be shared across threads.
+pub struct Library {
+    root_dir: PathBuf,
+    config: Config,
+    cache: MetaCache,
+}
+
+impl Library {
+    pub fn new<P: Into<PathBuf>>(root_dir: P, config: Config) -> Self {
+        let cache = match &config.cache_dir {
+            Some(cache_dir) => MetaCache::load(&cache_dir.join(CACHE_FILE_NAME)),
+            None => MetaCache::new(),
+        };
+
+        Self {
+            root_dir: root_dir.into(),
+            config,
+            cache,
+        }
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Loads the metadata for an item file, which must be inside of the root
+    /// directory. If the config is lenient, meta files that cause errors are
+    /// skipped, and their warnings are discarded.
+    pub fn get<P: AsRef<Path>>(&self, item_path: &P) -> Result<Block, ProcessorError> {
+        self.get_with_warnings(item_path).map(|(block, _)| block)
+    }
+
+    /// Loads the metadata for an item file, along with any warnings for meta
+    /// files that were skipped if the config is lenient.
+    pub fn get_with_warnings<P: AsRef<Path>>(&self, item_path: &P) -> Result<(Block, Vec<Warning>), ProcessorError> {
+        let item_path = item_path.as_ref();
+
+        if !item_path.starts_with(&self.root_dir) {
+            return Err(ProcessorError::OutsideRootDir(item_path.into()));
+        }
+
+        self.process(item_path)
+    }
+
+    /// Iterates over the metadata of every selected item file in the library,
+    /// starting with the root directory, in sorted depth-first order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            walker: ChildFileWalker::new(&self.root_dir),
+            library: self,
+        }
+    }
+
+    /// Searches an item file and the selected item files inside of it, in
+    /// sorted depth-first order, for item files whose metadata matches a
+    /// filter. The item file must be inside of the root directory.
+    pub fn search<'l, P: AsRef<Path>>(&'l self, item_path: &'l P, filter: &'l Filter) -> Search<'l> {
+        let item_path = item_path.as_ref();
+
+        if !item_path.starts_with(&self.root_dir) {
+            return Search {
+                iter: None,
+                filter,
+                error: Some(ProcessorError::OutsideRootDir(item_path.into())),
+            };
+        }
+
+        let iter = Iter {
+            walker: ChildFileWalker::new(item_path),
+            library: self,
+        };
+
+        Search { iter: Some(iter), filter, error: None, }
+    }
+
+    /// Finds the value at a key path in the metadata of the nearest ancestor
+    /// of an item file that defines it, starting with the item file itself.
+    /// The walk stops at the root directory, which must contain the item file.
+    /// Meta files are read through the cache, and if the config is lenient,
+    /// warnings are discarded.
+    pub fn find_in_ancestors<'p, S: AsRef<str>>(
+        &self,
+        item_path: &'p Path,
+        key_path: &[S],
+    ) -> Result<Option<(Cow<'p, Path>, Value)>, ProcessorError> {
+        Processor::find_in_ancestors_with(item_path, key_path, &self.root_dir, |path| self.process_cached(path))
+    }
+
+    /// Collects the values at a key path in the metadata of the descendants
+    /// of an item file, which must be inside of the root directory.
+    /// Meta files are read through the cache, and if the config is lenient,
+    /// warnings are discarded.
+    pub fn aggregate_descendants<S: AsRef<str>>(
+        &self,
+        item_path: &Path,
+        key_path: &[S],
+        aggregation: &Aggregation,
+    ) -> Result<Value, ProcessorError> {
+        if !item_path.starts_with(&self.root_dir) {
+            return Err(ProcessorError::OutsideRootDir(item_path.into()));
+        }
+
+        Processor::aggregate_descendants_with(
+            item_path,
+            key_path,
+            aggregation,
+            &self.config.selection,
+            &self.config.sorter,
+            |path| self.process_cached(path).map(|processed| processed.block),
+        )
+    }
+
+    /// Loads the metadata of every selected item file in the library using a
+    /// number of worker threads, and returns the results in the same order as
+    /// `iter`. Item files in the same directory are processed together, since
+    /// they usually share meta files, and meta files are shared across threads.
+    /// If the config is lenient, warnings are discarded.
+    /// Errors from walking the library are reported for the directory that
+    /// could not be walked.
+    pub fn process_parallel(&self, threads: NonZeroUsize) -> Vec<(PathBuf, Result<Block, ProcessorError>)> {
+        let mut walked = Vec::new();
+        self.walk(&self.root_dir, &mut walked);
+
+        // Group the item files to process by their parent directory, keeping
+        // the groups in walk order.
+        let mut groups = IndexMap::<&Path, Vec<usize>>::new();
+
+        for (index, (item_path, walk_res)) in walked.iter().enumerate() {
+            if walk_res.is_none() {
+                let parent_dir = item_path.parent().unwrap_or(item_path);
+                groups.entry(parent_dir).or_default().push(index);
+            }
+        }
+
+        let groups = groups.into_values().collect::<Vec<_>>();
+        let next_group = AtomicUsize::new(0);
+
+        let processed = thread::scope(|scope| {
+            let handles = (0..threads.get().min(groups.len()))
+                .map(|_| scope.spawn(|| {
+                    let mut processed = Vec::new();
+
+                    while let Some(group) = groups.get(next_group.fetch_add(1, Ordering::Relaxed)) {
+                        for &index in group {
+                            let res = self.process(&walked[index].0).map(|(block, _)| block);
+                            processed.push((index, res));
+                        }
+                    }
+
+                    processed
+                }))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+                .collect::<Vec<_>>()
+        });
+
+        for (index, res) in processed {
+            walked[index].1 = Some(res);
+        }
+
+        walked
+            .into_iter()
+            .filter_map(|(item_path, opt_res)| opt_res.map(|res| (item_path, res)))
+            .collect()
+    }
+
+    /// Removes all cached meta files, so that they are read again when needed.
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// Removes the cached meta files at a path, or inside of it if it is a
+    /// directory, so that they are read again when needed.
+    pub fn invalidate_cache(&self, path: &Path) {
+        self.cache.invalidate(path);
+    }
+
+    /// Saves the cache to the cache directory in the config, creating the
+    /// directory if needed. This is a no-op if there is no cache directory.
+    pub fn save_cache(&self) -> Result<(), CacheError> {
+        if let Some(cache_dir) = &self.config.cache_dir {
+            std::fs::create_dir_all(cache_dir)?;
+            self.cache.save(&cache_dir.join(CACHE_FILE_NAME))?;
+        }
+
+        Ok(())
+    }
+
+    fn process(&self, item_path: &Path) -> Result<(Block, Vec<Warning>), ProcessorError> {
+        self.process_cached(item_path).map(|processed| (processed.block, processed.warnings))
+    }
+
+    fn process_cached(&self, item_path: &Path) -> Result<Processed, ProcessorError> {
+        Processor::process_item_file_cached(
+            item_path,
+            Some(&self.root_dir),
+            &self.config.sourcer,
+            &self.config.selection,
+            &self.config.sorter,
+            &self.config.merger,
+            self.config.strictness,
+            &self.cache,
+        )
+    }
+
+    /// Collects the selected item files in a directory tree in sorted
+    /// depth-first order, starting with the item file itself. Errors from
+    /// reading a directory are added after the directory.
+    fn walk(&self, item_path: &Path, walked: &mut Vec<(PathBuf, Option<Result<Block, ProcessorError>>)>) {
+        walked.push((item_path.to_path_buf(), None));
+
+        if !item_path.is_dir() {
+            return;
+        }
+
+        let sub_item_paths = match self.config.selection.select_in_dir_sorted(item_path, &self.config.sorter) {
+            Ok(sub_item_paths) => sub_item_paths,
+            Err(err) => {
+                walked.push((item_path.to_path_buf(), Some(Err(ProcessorError::CannotWalk(err)))));
+                return;
+            },
+        };
+
+        for sub_item_path_res in sub_item_paths {
+            match sub_item_path_res {
+                Ok(sub_item_path) => self.walk(&sub_item_path, walked),
+                Err(err) => walked.push((item_path.to_path_buf(), Some(Err(ProcessorError::CannotWalk(err))))),
+            }
+        }
+    }
+}
+
+/// An iterator over the item files of a library and their metadata, along with
+/// any warnings for meta files that were skipped if the config is lenient.
+pub struct Iter<'l> {
+    walker: ChildFileWalker<'l>,
+    library: &'l Library,
+}
+
+impl<'l> Iterator for Iter<'l> {
+    type Item = Result<(PathBuf, Block, Vec<Warning>), ProcessorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item_path = match self.walker.next()? {
+            Ok(item_path) => item_path,
+            Err(err) => return Some(Err(ProcessorError::CannotWalk(err))),
+        };
+
+        let config = &self.library.config;
+
+        if let Err(err) = self.walker.delve(&config.selection, &config.sorter) {
+            return Some(Err(ProcessorError::CannotWalk(err)));
+        }
+
+        let res = self.library.process(&item_path);
+
+        Some(res.map(|(block, warnings)| (item_path.into_owned(), block, warnings)))
+    }
+}
+
+/// An iterator over the item files of a library whose metadata matches a
+/// filter, along with their metadata and any warnings. Errors are yielded
+/// regardless of the filter.
+pub struct Search<'l> {
+    /// Nothing is searched if the starting item file is invalid.
+    iter: Option<Iter<'l>>,
+    filter: &'l Filter,
+    error: Option<ProcessorError>,
+}
+
+impl<'l> Iterator for Search<'l> {
+    type Item = Result<(PathBuf, Block, Vec<Warning>), ProcessorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        self.iter.as_mut()?.find(|res| match res {
+            Ok((_, block, _)) => self.filter.is_match(block),
+            Err(_) => true,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn iter() {
+        let temp_dir = TU::create_simple_dir("library_iter", ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("album.json"), r#"{"title": "album_title"}"#).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "track_01_title"}, {"title": "track_02_title"}]"#,
+        ).unwrap();
+
+        let library = Library::new(path, Config::default());
+
+        let produced = library.iter().map(|res| {
+            let (item_path, block, warnings) = res.unwrap();
+            assert!(warnings.is_empty());
+            (item_path, block)
+        }).collect::<Vec<_>>();
+
+        let expected = vec![
+            (path.to_path_buf(), Block(btreemap![str!("title") => TU::s("album_title")])),
+            (path.join("01.flac"), Block(btreemap![str!("title") => TU::s("track_01_title")])),
+            (path.join("02.flac"), Block(btreemap![str!("title") => TU::s("track_02_title")])),
+        ];
+        assert_eq!(expected, produced);
+
+        // Each meta file is only processed once, so changes on disk are not
+        // seen until the cache is cleared.
+        assert_eq!(library.cache.len(), 2);
+
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "new_01_title"}, {"title": "new_02_title"}]"#,
+        ).unwrap();
+
+        assert_eq!(
+            library.get(&path.join("02.flac")).unwrap(),
+            Block(btreemap![str!("title") => TU::s("track_02_title")]),
+        );
+
+        library.clear_cache();
+
+        assert_eq!(
+            library.get(&path.join("02.flac")).unwrap(),
+            Block(btreemap![str!("title") => TU::s("new_02_title")]),
+        );
+
+        assert!(matches!(
+            library.get(&temp_dir.path().parent().unwrap()),
+            Err(ProcessorError::OutsideRootDir(_)),
+        ));
+    }
+
+    #[test]
+    fn process_parallel() {
+        let temp_dir = TU::create_meta_fanout_test_dir("library_process_parallel", 3, 2, |_, _| true);
+        let path = temp_dir.path();
+
+        let config = Config::from_str(r#"
+            [sourcing]
+            track = ["item.json"]
+            album = ["self.json"]
+        "#).unwrap();
+
+        let library = Library::new(path, config);
+
+        let expected = library.iter().map(|res| {
+            let (item_path, block, _) = res.unwrap();
+            (item_path, block)
+        }).collect::<Vec<_>>();
+
+        // The root, 3 + 9 directories, and 27 files.
+        assert_eq!(expected.len(), 40);
+
+        for threads in [1, 2, 8] {
+            library.clear_cache();
+
+            let produced = library
+                .process_parallel(NonZeroUsize::new(threads).unwrap())
+                .into_iter()
+                .map(|(item_path, res)| (item_path, res.unwrap()))
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn save_cache() {
+        let temp_dir = TU::create_simple_dir("library_save_cache", ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(path.join("album.json"), r#"{"title": "album_title"}"#).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "track_01_title"}, {"title": "track_02_title"}]"#,
+        ).unwrap();
+
+        let make_config = || Config {
+            cache_dir: Some(cache_dir.path().join("nested")),
+            ..Config::default()
+        };
+
+        let library = Library::new(path, make_config());
+        assert!(library.cache.is_persistent());
+        assert!(library.iter().all(|res| res.is_ok()));
+        library.save_cache().unwrap();
+
+        let library = Library::new(path, make_config());
+        assert_eq!(library.cache.len(), 2);
+        assert_eq!(
+            library.get(&path.join("01.flac")).unwrap(),
+            Block(btreemap![str!("title") => TU::s("track_01_title")]),
+        );
+
+        // Without a cache directory, saving is a no-op.
+        let library = Library::new(path, Config::default());
+        assert!(!library.cache.is_persistent());
+        library.save_cache().unwrap();
+    }
+
+    #[test]
+    fn search() {
+        let temp_dir = TU::create_simple_dir("library_search", ["01.flac", "02.flac", "03.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("album.json"), r#"{"title": "album_title", "genre": ["jazz"]}"#).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[
+                {"genre": ["jazz", "bebop"], "rating": 4},
+                {"genre": ["rock"], "rating": 5},
+                {"genre": ["jazz"], "rating": 3.5}
+            ]"#,
+        ).unwrap();
+
+        let library = Library::new(path, Config::default());
+
+        let search = |item_path: &Path, filter: &str| {
+            let filter = Filter::parse(filter).unwrap();
+
+            library
+                .search(&item_path, &filter)
+                .map(|res| res.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search(path, r#"genre contains "jazz" and rating >= 4"#),
+            vec![path.join("01.flac")],
+        );
+        assert_eq!(
+            search(path, r#"genre contains "jazz""#),
+            vec![path.to_path_buf(), path.join("01.flac"), path.join("03.flac")],
+        );
+        assert_eq!(search(path, "not exists rating"), vec![path.to_path_buf()]);
+
+        // Searching an item file only visits that item file.
+        assert_eq!(search(&path.join("02.flac"), "rating > 4"), vec![path.join("02.flac")]);
+
+        let filter = Filter::parse("exists title").unwrap();
+        let outside_path = path.parent().unwrap();
+        let produced = library.search(&outside_path, &filter).collect::<Vec<_>>();
+        assert_eq!(produced.len(), 1);
+        assert!(matches!(produced[0], Err(ProcessorError::OutsideRootDir(_))));
+    }
+
+    #[test]
+    fn find_and_aggregate() {
+        let temp_dir = TU::create_simple_dir("library_find_and_aggregate", ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("album.json"), r#"{"artist": "album_artist"}"#).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"rating": 4}, {"rating": 5}]"#,
+        ).unwrap();
+
+        let library = Library::new(path, Config::default());
+
+        assert_eq!(
+            library.find_in_ancestors(&path.join("01.flac"), &["artist"]).unwrap(),
+            Some((Cow::Borrowed(path), TU::s("album_artist"))),
+        );
+        assert_eq!(
+            library.aggregate_descendants(path, &["rating"], &Aggregation::default()).unwrap(),
+            Value::Sequence(vec![TU::i(4), TU::i(5)]),
+        );
+
+        // Both walks read meta files through the cache of the library.
+        assert_eq!(library.cache.len(), 2);
+
+        std::fs::write(path.join("album.json"), r#"{"artist": "new_artist"}"#).unwrap();
+
+        assert_eq!(
+            library.find_in_ancestors(&path.join("02.flac"), &["artist"]).unwrap(),
+            Some((Cow::Borrowed(path), TU::s("album_artist"))),
+        );
+
+        assert!(matches!(
+            library.aggregate_descendants(path.parent().unwrap(), &["rating"], &Aggregation::default()),
+            Err(ProcessorError::OutsideRootDir(_)),
+        ));
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Library>();
+        assert_send_sync::<Config>();
+        assert_send_sync::<crate::config::Selection>();
+        assert_send_sync::<crate::config::Sorter>();
+        assert_send_sync::<crate::sources::Sourcer>();
+        assert_send_sync::<ProcessorError>();
+    }
+}
@@ -0,0 +1,622 @@
+//! Caching of plexed meta files, so that each meta file is only processed once.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::{Selection, Sorter};
+use crate::metadata::plexer::StaleEntry;
+use crate::sources::{Anchor, Source};
+use crate::types::{Block, DateTime, Decimal, Value};
+use crate::util::Util;
+
+/// The file name of a persistent cache inside of its cache directory.
+pub const CACHE_FILE_NAME: &str = "anagma_cache.json";
+
+/// The version of the persistent cache file layout. Cache files with other
+/// versions are ignored.
+const CACHE_VERSION: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot write cache file: {0}")]
+    Io(#[from] IoError),
+    #[error("cannot serialize cache file: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// The meta blocks plexed from a meta file, keyed by item file path, along with
+/// the entries of an index meta file that do not match any selected item file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plexed {
+    pub blocks: HashMap<PathBuf, Block>,
+    pub stale_entries: Vec<StaleEntry>,
+}
+
+/// Describes the state of a meta file and the config used to plex it.
+/// If any of these change, the plexed results of the meta file are stale.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    mtime: SystemTime,
+    size: u64,
+
+    /// Adding or removing item files changes how a meta file is plexed, and
+    /// also changes the mod time of the directory that contains them.
+    anchor_dir_mtime: Option<SystemTime>,
+
+    /// A hash of the source, selection, and sorter used for plexing.
+    config_hash: u64,
+}
+
+impl Stamp {
+    fn new(meta_path: &Path, source: &Source, selection: &Selection, sorter: &Sorter) -> Option<Self> {
+        let mtime = Util::mtime(meta_path)?;
+        let size = meta_path.metadata().ok()?.len();
+
+        let anchor_dir_mtime = source
+            .anchor_dir_path(meta_path)
+            .ok()
+            .and_then(|anchor_dir| Util::mtime(&anchor_dir));
+
+        let mut hasher = DefaultHasher::new();
+        (source, selection, sorter).hash(&mut hasher);
+        let config_hash = hasher.finish();
+
+        Some(Self { mtime, size, anchor_dir_mtime, config_hash, })
+    }
+}
+
+/// Identifies a cached meta file by its path and a hash of the source that
+/// processed it.
+type Key = (PathBuf, u64);
+
+/// Hashes a source, so that sources that read the same meta file in different
+/// ways, such as with a different layout or cell typing, have separate entries.
+fn source_hash(source: &Source) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: Option<Stamp>,
+    plexed: Arc<Plexed>,
+}
+
+/// A metadata value as stored in a cache file. The kind of each value is
+/// recorded explicitly, and decimals and datetimes are stored as strings, so
+/// that values are read back exactly as they were written.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+enum StoredValue {
+    Null,
+    DateTime(String),
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Decimal(String),
+    Sequence(Vec<StoredValue>),
+    Mapping(StoredBlock),
+}
+
+type StoredBlock = BTreeMap<String, StoredValue>;
+
+impl StoredValue {
+    fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::DateTime(dt) => Self::DateTime(dt.to_string()),
+            Value::String(s) => Self::String(s.clone()),
+            Value::Integer(i) => Self::Integer(*i),
+            Value::Boolean(b) => Self::Boolean(*b),
+            Value::Decimal(d) => Self::Decimal(d.to_string()),
+            Value::Sequence(seq) => Self::Sequence(seq.iter().map(Self::from_value).collect()),
+            Value::Mapping(block) => Self::Mapping(store_block(block)),
+        }
+    }
+
+    /// Returns `None` if a stored decimal or datetime cannot be parsed.
+    fn into_value(self) -> Option<Value> {
+        let value = match self {
+            Self::Null => Value::Null,
+            Self::DateTime(s) => Value::DateTime(DateTime::parse(&s).ok()?),
+            Self::String(s) => Value::String(s),
+            Self::Integer(i) => Value::Integer(i),
+            Self::Boolean(b) => Value::Boolean(b),
+            Self::Decimal(s) => Value::Decimal(s.parse::<Decimal>().ok()?),
+            Self::Sequence(seq) => Value::Sequence(seq.into_iter().map(Self::into_value).collect::<Option<_>>()?),
+            Self::Mapping(block) => Value::Mapping(restore_block(block)?),
+        };
+
+        Some(value)
+    }
+}
+
+fn store_block(block: &Block) -> StoredBlock {
+    block.iter().map(|(k, v)| (k.clone(), StoredValue::from_value(v))).collect()
+}
+
+fn restore_block(stored: StoredBlock) -> Option<Block> {
+    stored.into_iter().map(|(k, v)| v.into_value().map(|v| (k, v))).collect()
+}
+
+#[derive(Serialize)]
+struct CacheFileRef<'a> {
+    version: u32,
+    entries: Vec<EntryRef<'a>>,
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    meta_path: &'a Path,
+    source_hash: u64,
+    stamp: &'a Stamp,
+    plexed: HashMap<&'a Path, StoredBlock>,
+}
+
+#[derive(Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: Vec<EntryOwned>,
+}
+
+#[derive(Deserialize)]
+struct EntryOwned {
+    meta_path: PathBuf,
+    source_hash: u64,
+    stamp: Stamp,
+    plexed: HashMap<PathBuf, StoredBlock>,
+}
+
+/// A cache of plexed meta files, keyed by the path of each meta file and its
+/// source. A meta file may be processed once per source, such as for sources
+/// with a combined layout.
+/// The cache can be shared across threads.
+///
+/// A cache that is loaded from a file is persistent, and checks whether each
+/// cached meta file is stale before using it. Stale meta files are processed
+/// again. A cache created with `new` does not check for stale meta files.
+/// Index meta files are never checked or saved, even by a persistent cache.
+#[derive(Debug, Default)]
+pub struct MetaCache {
+    entries: RwLock<HashMap<Key, Entry>>,
+    persistent: bool,
+}
+
+impl MetaCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a persistent cache from a cache file. A cache file that is
+    /// missing, unreadable, or from a different version results in an empty
+    /// persistent cache, since it will be overwritten when saved. Entries with
+    /// values that cannot be read back are skipped.
+    pub fn load<P: AsRef<Path>>(cache_path: &P) -> Self {
+        let mut entries = HashMap::new();
+
+        let opt_cache_file = File::open(cache_path)
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, CacheFile>(BufReader::new(file)).ok())
+            .filter(|cache_file| cache_file.version == CACHE_VERSION);
+
+        if let Some(cache_file) = opt_cache_file {
+            for entry in cache_file.entries {
+                let opt_blocks = entry.plexed
+                    .into_iter()
+                    .map(|(item_path, stored)| restore_block(stored).map(|block| (item_path, block)))
+                    .collect::<Option<HashMap<_, _>>>();
+
+                if let Some(blocks) = opt_blocks {
+                    let plexed = Plexed { blocks, stale_entries: Vec::new() };
+                    let key = (entry.meta_path, entry.source_hash);
+                    entries.insert(key, Entry { stamp: Some(entry.stamp), plexed: Arc::new(plexed) });
+                }
+            }
+        }
+
+        Self { entries: RwLock::new(entries), persistent: true, }
+    }
+
+    /// Saves this cache to a cache file. The cache file is replaced as a
+    /// whole, so that a failed save does not leave a partial cache file.
+    pub fn save<P: AsRef<Path>>(&self, cache_path: &P) -> Result<(), Error> {
+        let cache_path = cache_path.as_ref();
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+
+        let cache_file = CacheFileRef {
+            version: CACHE_VERSION,
+            entries: entries
+                .iter()
+                .filter_map(|((meta_path, source_hash), entry)| {
+                    entry.stamp.as_ref().map(|stamp| EntryRef {
+                        meta_path,
+                        source_hash: *source_hash,
+                        stamp,
+                        plexed: entry.plexed
+                            .blocks
+                            .iter()
+                            .map(|(item_path, block)| (item_path.as_path(), store_block(block)))
+                            .collect(),
+                    })
+                })
+                .collect(),
+        };
+
+        let file_name = cache_path.file_name().ok_or_else(|| {
+            let msg = format!("path does not have a file name: {}", cache_path.display());
+            IoError::new(IoErrorKind::InvalidInput, msg)
+        })?;
+
+        // Write to a uniquely-named temporary file in the same directory, so
+        // that concurrent saves never share a temporary file, and then rename
+        // it over the cache file.
+        let dir_path = match cache_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut prefix = OsString::from(".");
+        prefix.push(file_name);
+
+        // The temporary file is removed if it is dropped before being persisted.
+        let mut temp_file = tempfile::Builder::new()
+            .prefix(&prefix)
+            .suffix(".tmp")
+            .tempfile_in(dir_path)?;
+
+        let mut writer = BufWriter::new(temp_file.as_file_mut());
+        serde_json::to_writer(&mut writer, &cache_file)?;
+        writer.flush()?;
+        drop(writer);
+
+        temp_file.persist(cache_path).map_err(|err| err.error)?;
+
+        Ok(())
+    }
+
+    /// Returns the cached plexed results of a meta file, or produces and
+    /// caches them if they are not cached yet. Errors are not cached.
+    /// If multiple threads miss the cache for the same meta file at the same
+    /// time, the meta file may be processed more than once.
+    pub(crate) fn get_or_try_insert_with<F, E>(
+        &self,
+        meta_path: &Path,
+        source: &Source,
+        selection: &Selection,
+        sorter: &Sorter,
+        produce: F,
+    ) -> Result<Arc<Plexed>, E>
+    where
+        F: FnOnce() -> Result<Plexed, E>,
+    {
+        let key = (meta_path.to_path_buf(), source_hash(source));
+
+        // The plexed results of an index meta file depend on item files in any
+        // directory below it, which a stamp does not cover. So index meta files
+        // are only cached in memory, and are never checked or saved.
+        let checked = self.persistent && !matches!(source.anchor, Anchor::Index);
+
+        let stamp = if checked {
+            Stamp::new(meta_path, source, selection, sorter)
+        } else {
+            None
+        };
+
+        if let Some(entry) = self.entries.read().unwrap_or_else(PoisonError::into_inner).get(&key) {
+            if !checked || (stamp.is_some() && entry.stamp == stamp) {
+                return Ok(Arc::clone(&entry.plexed));
+            }
+        }
+
+        // The lock is not held while producing, so that other meta files can
+        // be processed concurrently.
+        let plexed = Arc::new(produce()?);
+
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+
+        if checked {
+            // Any existing entry is stale, so it is replaced.
+            entries.insert(key, Entry { stamp, plexed: Arc::clone(&plexed) });
+            Ok(plexed)
+        } else {
+            // Keep the first result, if another thread produced one meanwhile.
+            let entry = entries.entry(key).or_insert(Entry { stamp, plexed });
+            Ok(Arc::clone(&entry.plexed))
+        }
+    }
+
+    /// Returns whether this cache checks for stale meta files. Only the meta
+    /// files cached by a persistent cache are saved, apart from index meta
+    /// files.
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Returns the number of cached meta files.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all cached meta files.
+    pub fn clear(&self) {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    /// Removes the cached meta files at a path, or inside of it if it is a
+    /// directory, for all sources.
+    pub fn invalidate(&self, path: &Path) {
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(meta_path, _), _| !meta_path.starts_with(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::FileTimes;
+
+    use maplit::{btreemap, hashmap};
+    use rust_decimal_macros::dec;
+    use str_macro::str;
+
+    use crate::config::Merger;
+    use crate::config::selection::Matcher;
+    use crate::metadata::processor::{Processor, Strictness};
+    use crate::metadata::table::CellTyping;
+    use crate::sources::{Anchor, Sourcer};
+    use crate::test_util::TestUtil as TU;
+    use crate::types::Value;
+
+    #[test]
+    fn persistence() {
+        let temp_dir = TU::create_simple_dir("meta_cache_persistence", ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+        let meta_path = path.join("item.json");
+        // Writing the cache file into the item directory would change its mod time.
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(CACHE_FILE_NAME);
+
+        std::fs::write(
+            &meta_path,
+            r#"[{"title": "track_01", "rating": 4.5}, {"title": "track_02", "rating": 0.1}]"#,
+        ).unwrap();
+        let original_mtime = Util::mtime(&meta_path).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer.source(Source::from_name(str!("item.json"), Anchor::External).unwrap());
+
+        let process = |cache: &MetaCache, selection: &Selection| {
+            Processor::process_item_file_cached(
+                &path.join("02.flac"),
+                Some(path),
+                &sourcer,
+                selection,
+                &sorter,
+                &Merger::default(),
+                Strictness::Strict,
+                cache,
+            )
+            .map(|processed| processed.block)
+        };
+
+        let expected = Block(btreemap![
+            str!("rating") => TU::d(dec!(0.1)),
+            str!("title") => TU::s("track_02"),
+        ]);
+
+        // A missing cache file results in an empty cache.
+        let cache = MetaCache::load(&cache_path);
+        assert!(cache.is_persistent());
+        assert!(cache.is_empty());
+
+        assert_eq!(process(&cache, &selection).unwrap(), expected);
+        assert_eq!(cache.len(), 1);
+        cache.save(&cache_path).unwrap();
+
+        // Replace the meta file with invalid contents of the same size, and
+        // restore its mod time. Since the meta file looks unchanged, the cached
+        // results are used.
+        let contents = std::fs::read(&meta_path).unwrap();
+        std::fs::write(&meta_path, vec![b' '; contents.len()]).unwrap();
+        File::options()
+            .write(true)
+            .open(&meta_path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(original_mtime))
+            .unwrap();
+
+        let cache = MetaCache::load(&cache_path);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(process(&cache, &selection).unwrap(), expected);
+
+        // A different selection makes the cached results stale.
+        let other_selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json", "*.tmp"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        assert!(process(&cache, &other_selection).is_err());
+
+        // Changing the meta file makes the cached results stale.
+        std::fs::write(&meta_path, r#"[{"title": "new_01"}, {"title": "new_02"}]"#).unwrap();
+
+        let cache = MetaCache::load(&cache_path);
+        assert_eq!(
+            process(&cache, &selection).unwrap(),
+            Block(btreemap![str!("title") => Value::String(str!("new_02"))]),
+        );
+
+        // Corrupt cache files are ignored.
+        std::fs::write(&cache_path, "{").unwrap();
+        assert!(MetaCache::load(&cache_path).is_empty());
+    }
+
+    #[test]
+    fn lossless_values() {
+        let temp_dir = TU::create_simple_dir("meta_cache_lossless_values", ["01.flac"]);
+        let path = temp_dir.path();
+        let meta_path = path.join("item.json");
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(CACHE_FILE_NAME);
+
+        std::fs::write(&meta_path, "[{}]").unwrap();
+
+        let source = Source::from_name(str!("item.json"), Anchor::External).unwrap();
+        let selection = Selection::default();
+        let sorter = Sorter::default();
+
+        let block = Block(btreemap![
+            str!("rating") => TU::d(dec!(0.12345678901234567891)),
+            str!("released") => TU::s("2019-08-20"),
+            str!("recorded") => Value::DateTime(DateTime::parse("2019-08").unwrap()),
+            str!("tags") => Value::Sequence(vec![TU::s("1999"), Value::Null, TU::d(dec!(1.50))]),
+        ]);
+        let plexed = Plexed {
+            blocks: hashmap![path.join("01.flac") => block],
+            stale_entries: Vec::new(),
+        };
+
+        let cache = MetaCache::load(&cache_path);
+        let warm = cache
+            .get_or_try_insert_with(&meta_path, &source, &selection, &sorter, || Ok::<_, ()>(plexed.clone()))
+            .unwrap();
+        cache.save(&cache_path).unwrap();
+
+        // Reading the cache file back must not change the kind or precision of
+        // any value.
+        let cache = MetaCache::load(&cache_path);
+        let cold = cache
+            .get_or_try_insert_with(&meta_path, &source, &selection, &sorter, || Err(()))
+            .unwrap();
+        assert_eq!(cold, warm);
+        assert_eq!(*cold, plexed);
+
+        // No temporary files are left behind.
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn index_not_saved() {
+        let temp_dir = TU::create_simple_dir("meta_cache_index_not_saved", &[] as &[&str]);
+        let path = temp_dir.path();
+        let album_path = path.join("Artist").join("Album");
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(CACHE_FILE_NAME);
+
+        std::fs::create_dir_all(&album_path).unwrap();
+        std::fs::write(album_path.join("01.flac"), "").unwrap();
+        std::fs::write(
+            path.join("catalog.json"),
+            r#"{
+                "Artist/Album/01.flac": {"title": "track_01"},
+                "Artist/Album/02.flac": {"title": "track_02"}
+            }"#,
+        ).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer.source(Source::from_name(str!("catalog.json"), Anchor::Index).unwrap());
+
+        let process = |cache: &MetaCache, item_path: &Path| {
+            Processor::process_item_file_cached(
+                item_path,
+                Some(path),
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
+                Strictness::Lenient,
+                cache,
+            )
+            .unwrap()
+        };
+
+        let cache = MetaCache::load(&cache_path);
+        let processed = process(&cache, &album_path.join("01.flac"));
+        assert_eq!(processed.block, Block(btreemap![str!("title") => TU::s("track_01")]));
+        assert_eq!(processed.warnings.len(), 1);
+        assert_eq!(cache.len(), 1);
+        cache.save(&cache_path).unwrap();
+
+        // Adding a nested item file does not change the stamp of the index, so
+        // the index must not be reused from the cache file.
+        std::fs::write(album_path.join("02.flac"), "").unwrap();
+
+        let cache = MetaCache::load(&cache_path);
+        assert!(cache.is_empty());
+
+        let processed = process(&cache, &album_path.join("02.flac"));
+        assert_eq!(processed.block, Block(btreemap![str!("title") => TU::s("track_02")]));
+        assert!(processed.warnings.is_empty());
+    }
+
+    #[test]
+    fn keyed_by_source() {
+        let meta_path = Path::new("item.csv");
+        let selection = Selection::default();
+        let sorter = Sorter::default();
+
+        let inferred = Source::from_name(str!("item.csv"), Anchor::External).unwrap();
+        let strings = Source::from_name(str!("item.csv"), Anchor::External)
+            .unwrap()
+            .with_cell_typing(CellTyping::Strings);
+
+        let plexed = |value: Value| -> Result<Plexed, ()> {
+            Ok(Plexed {
+                blocks: hashmap![PathBuf::from("01.flac") => Block(btreemap![str!("n") => value])],
+                stale_entries: Vec::new(),
+            })
+        };
+
+        let cache = MetaCache::new();
+
+        let produced = cache.get_or_try_insert_with(meta_path, &inferred, &selection, &sorter, || plexed(Value::Integer(1)));
+        assert_eq!(produced.unwrap(), Arc::new(plexed(Value::Integer(1)).unwrap()));
+
+        // A source that reads the same meta file differently has its own entry.
+        let produced = cache.get_or_try_insert_with(meta_path, &strings, &selection, &sorter, || plexed(TU::s("1")));
+        assert_eq!(produced.unwrap(), Arc::new(plexed(TU::s("1")).unwrap()));
+        assert_eq!(cache.len(), 2);
+
+        // The same source uses its cached entry.
+        let produced = cache.get_or_try_insert_with(meta_path, &inferred, &selection, &sorter, || Err(()));
+        assert_eq!(produced.unwrap(), Arc::new(plexed(Value::Integer(1)).unwrap()));
+
+        // Invalidating a meta file removes its entries for all sources.
+        cache.get_or_try_insert_with(Path::new("other.csv"), &inferred, &selection, &sorter, || plexed(Value::Null)).unwrap();
+        cache.invalidate(meta_path);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,473 @@
+//! Filter expressions that test whether the metadata of an item file matches
+//! conditions on its values, such as `genre contains "jazz" and rating >= 4`.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::types::{Block, DateTime, Decimal, Number, Query, QueryError, Value};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unexpected end of filter")]
+    UnexpectedEnd,
+    #[error(r#"unexpected token "{0}" at position {1}"#)]
+    UnexpectedToken(String, usize),
+    #[error("unterminated string at position {0}")]
+    UnterminatedString(usize),
+    #[error(r#"invalid query "{0}": {1}"#)]
+    InvalidQuery(String, #[source] QueryError),
+    #[error(r#"invalid regex "{0}": {1}"#)]
+    InvalidRegex(String, #[source] regex::Error),
+}
+
+/// An operator that compares a value with a literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// Compares a value with a literal. Numbers are compared by their numerical
+    /// values, datetimes are compared chronologically, and strings are compared
+    /// lexicographically. Values of any other kind can only be compared for
+    /// equality. Values of different kinds are never equal, and cannot be
+    /// ordered.
+    pub fn test(&self, value: &Value, literal: &Value) -> bool {
+        let ordering = match (Number::try_from(value), Number::try_from(literal)) {
+            (Ok(l), Ok(r)) => Some(l.val_cmp(&r)),
+            _ => match (value, literal) {
+                (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                (Value::DateTime(l), Value::DateTime(r)) => Some(l.val_cmp(r)),
+                _ => None,
+            },
+        };
+
+        match self {
+            Self::Eq => value.val_eq(literal),
+            Self::Ne => !value.val_eq(literal),
+            Self::Lt => ordering == Some(Ordering::Less),
+            Self::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Self::Gt => ordering == Some(Ordering::Greater),
+            Self::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// A parsed filter expression, which can be tested against a block of metadata.
+///
+/// Conditions select values using a query, and a condition is true if any of
+/// the selected values satisfies it. Conditions on queries that select no
+/// values are false, except for `not` conditions wrapping them.
+///
+/// The supported conditions are:
+/// - `exists <query>`, which is true if the query selects any value.
+/// - `<query> <op> <literal>`, where the operator is one of `==`, `!=`, `<`,
+///   `<=`, `>`, or `>=`.
+/// - `<query> contains <literal>`, which is true for sequences that have an
+///   element equal to the literal, and strings that have the literal string
+///   as a substring.
+/// - `<query> matches "<regex>"`, which is true for strings that match the
+///   regex, and sequences that have such a string as an element.
+///
+/// Conditions can be combined using `and`, `or`, and `not`, in order of
+/// increasing precedence, and grouped using parentheses. Literals are strings
+/// in double quotes, integers, decimals, `true`, `false`, `null`, and unquoted
+/// dates or datetimes with at least a month, such as `2019-08`. Inside
+/// of strings, `\"` and `\\` are escapes for a quote and a backslash, and
+/// other backslashes are kept as is, so that they can be used in regexes.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Exists(Query),
+    Compare(Query, Comparison, Value),
+    Contains(Query, Value),
+    Matches(Query, Regex),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, Error> {
+        let tokens = tokenize(filter)?;
+        let mut parser = Parser { tokens, index: 0 };
+
+        let filter = parser.parse_or()?;
+
+        match parser.tokens.get(parser.index) {
+            None => Ok(filter),
+            Some((token, pos)) => Err(Error::UnexpectedToken(token.to_string(), *pos)),
+        }
+    }
+
+    /// Tests whether a block of metadata matches this filter.
+    pub fn is_match(&self, block: &Block) -> bool {
+        let any = |query: &Query, pred: &dyn Fn(&Value) -> bool| {
+            query.eval_block(block).into_iter().any(|m| pred(m.value))
+        };
+
+        match self {
+            Self::Exists(query) => any(query, &|_| true),
+            Self::Compare(query, cmp, literal) => any(query, &|v| cmp.test(v, literal)),
+            Self::Contains(query, literal) => any(query, &|v| match (v, literal) {
+                (Value::Sequence(seq), _) => seq.iter().any(|e| e.val_eq(literal)),
+                (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+                _ => false,
+            }),
+            Self::Matches(query, regex) => any(query, &|v| match v {
+                Value::Sequence(seq) => seq.iter().any(|e| matches!(e, Value::String(s) if regex.is_match(s))),
+                Value::String(s) => regex.is_match(s),
+                _ => false,
+            }),
+            Self::And(l, r) => l.is_match(block) && r.is_match(block),
+            Self::Or(l, r) => l.is_match(block) || r.is_match(block),
+            Self::Not(f) => !f.is_match(block),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(Comparison),
+    String(String),
+    Word(String),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Open => write!(f, "("),
+            Self::Close => write!(f, ")"),
+            Self::Op(cmp) => write!(f, "{}", match cmp {
+                Comparison::Eq => "==",
+                Comparison::Ne => "!=",
+                Comparison::Lt => "<",
+                Comparison::Le => "<=",
+                Comparison::Gt => ">",
+                Comparison::Ge => ">=",
+            }),
+            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::Word(w) => write!(f, "{}", w),
+        }
+    }
+}
+
+/// Splits a filter into tokens, along with the position of each token.
+fn tokenize(filter: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            },
+            '(' => {
+                chars.next();
+                Token::Open
+            },
+            ')' => {
+                chars.next();
+                Token::Close
+            },
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let has_eq = chars.next_if(|&(_, c)| c == '=').is_some();
+
+                let cmp = match (c, has_eq) {
+                    ('=', true) => Comparison::Eq,
+                    ('!', true) => Comparison::Ne,
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Le,
+                    ('>', false) => Comparison::Gt,
+                    ('>', true) => Comparison::Ge,
+                    _ => return Err(Error::UnexpectedToken(c.to_string(), pos)),
+                };
+
+                Token::Op(cmp)
+            },
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+
+                loop {
+                    match chars.next() {
+                        None => return Err(Error::UnterminatedString(pos)),
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next_if(|&(_, c)| c == '"' || c == '\\') {
+                            Some((_, c)) => s.push(c),
+                            None => s.push('\\'),
+                        },
+                        Some((_, c)) => s.push(c),
+                    }
+                }
+
+                Token::String(s)
+            },
+            _ => {
+                // Words may be queries, which can contain quoted keys in brackets.
+                let mut end = filter.len();
+                let mut depth = 0usize;
+                let mut quoted = false;
+                let mut escaped = false;
+
+                while let Some(&(p, c)) = chars.peek() {
+                    if quoted {
+                        match c {
+                            _ if escaped => escaped = false,
+                            '\\' => escaped = true,
+                            '"' => quoted = false,
+                            _ => {},
+                        }
+                    } else {
+                        match c {
+                            '"' if depth > 0 => quoted = true,
+                            '[' => depth += 1,
+                            ']' => depth = depth.saturating_sub(1),
+                            _ if depth > 0 => {},
+                            '(' | ')' | '=' | '!' | '<' | '>' | '"' => {
+                                end = p;
+                                break;
+                            },
+                            _ if c.is_whitespace() => {
+                                end = p;
+                                break;
+                            },
+                            _ => {},
+                        }
+                    }
+
+                    chars.next();
+                }
+
+                Token::Word(filter[pos..end].to_string())
+            },
+        };
+
+        tokens.push((token, pos));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<(Token, usize), Error> {
+        let token = self.tokens.get(self.index).cloned().ok_or(Error::UnexpectedEnd)?;
+        self.index += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the given keyword.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.index) {
+            Some((Token::Word(w), _)) if w == keyword => {
+                self.index += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, Error> {
+        let mut filter = self.parse_and()?;
+
+        while self.eat_keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, Error> {
+        let mut filter = self.parse_unary()?;
+
+        while self.eat_keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_unary()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, Error> {
+        if self.eat_keyword("not") {
+            return Ok(Filter::Not(Box::new(self.parse_unary()?)));
+        }
+
+        if self.eat_keyword("exists") {
+            return Ok(Filter::Exists(self.parse_query()?));
+        }
+
+        if let Some((Token::Open, _)) = self.tokens.get(self.index) {
+            self.index += 1;
+            let filter = self.parse_or()?;
+
+            return match self.next()? {
+                (Token::Close, _) => Ok(filter),
+                (token, pos) => Err(Error::UnexpectedToken(token.to_string(), pos)),
+            };
+        }
+
+        let query = self.parse_query()?;
+
+        if self.eat_keyword("contains") {
+            return Ok(Filter::Contains(query, self.parse_literal()?));
+        }
+
+        if self.eat_keyword("matches") {
+            return match self.next()? {
+                (Token::String(pattern), _) => match Regex::new(&pattern) {
+                    Ok(regex) => Ok(Filter::Matches(query, regex)),
+                    Err(err) => Err(Error::InvalidRegex(pattern, err)),
+                },
+                (token, pos) => Err(Error::UnexpectedToken(token.to_string(), pos)),
+            };
+        }
+
+        match self.next()? {
+            (Token::Op(cmp), _) => Ok(Filter::Compare(query, cmp, self.parse_literal()?)),
+            (token, pos) => Err(Error::UnexpectedToken(token.to_string(), pos)),
+        }
+    }
+
+    fn parse_query(&mut self) -> Result<Query, Error> {
+        match self.next()? {
+            (Token::Word(w), pos) if is_keyword(&w) => Err(Error::UnexpectedToken(w, pos)),
+            (Token::Word(w), _) => Query::parse(&w).map_err(|err| Error::InvalidQuery(w, err)),
+            (token, pos) => Err(Error::UnexpectedToken(token.to_string(), pos)),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Value, Error> {
+        match self.next()? {
+            (Token::String(s), _) => Ok(Value::String(s)),
+            (Token::Word(w), pos) => match w.as_str() {
+                "null" => Ok(Value::Null),
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => match w.parse::<i64>() {
+                    Ok(i) => Ok(Value::Integer(i)),
+                    Err(_) => match Decimal::from_str(&w) {
+                        Ok(d) => Ok(Value::Decimal(d)),
+                        Err(_) => match DateTime::coerce(&w) {
+                            Some(dt) => Ok(Value::DateTime(dt)),
+                            None => Err(Error::UnexpectedToken(w, pos)),
+                        },
+                    },
+                },
+            },
+            (token, pos) => Err(Error::UnexpectedToken(token.to_string(), pos)),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(word, "and" | "or" | "not" | "exists" | "contains" | "matches")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use rust_decimal_macros::dec;
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn is_match() {
+        let block = Block(btreemap![
+            str!("title") => TU::s("Blue in Green"),
+            str!("genre") => Value::Sequence(vec![TU::s("jazz"), TU::s("modal")]),
+            str!("rating") => TU::d(dec!(4.5)),
+            str!("year") => TU::i(1959),
+            str!("live") => Value::Boolean(false),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("performers") => Value::Sequence(vec![TU::s("Miles Davis"), TU::s("Bill Evans")]),
+            ])),
+            str!("track title") => TU::s("spaced"),
+            str!("date") => Value::DateTime(DateTime::parse("1959-08-17").unwrap()),
+        ]);
+
+        let inputs_and_expected = vec![
+            (r#"genre contains "jazz" and rating >= 4"#, true),
+            (r#"genre contains "rock" or rating >= 4"#, true),
+            (r#"genre contains "rock" and rating >= 4"#, false),
+            (r#"not genre contains "rock""#, true),
+            ("rating > 4.5", false),
+            ("rating == 4.50", true),
+            ("year == 1959.0", true),
+            ("year != 1959", false),
+            ("year < 2000 and year >= 1959", true),
+            (r#"year == "1959""#, false),
+            (r#"year != "1959""#, true),
+            ("live == false", true),
+            ("live <= false", false),
+            (r#"title contains "in""#, true),
+            (r#"title > "Blue""#, true),
+            (r#"title matches "^Blue\s+in""#, true),
+            (r#"title matches "^Green""#, false),
+            (r#"credits.performers matches "^Bill ""#, true),
+            (r#"credits.performers[0] == "Miles Davis""#, true),
+            (r#"credits.performers[-1] == "Miles Davis""#, false),
+            (r#"credits.performers[*] == "Bill Evans""#, true),
+            (r#"["track title"] == "spaced""#, true),
+            ("exists credits.performers", true),
+            ("exists composer", false),
+            ("not exists composer", true),
+            ("composer == null", false),
+            ("composer != null", false),
+            ("date >= 1959-01 and date < 1959-09", true),
+            ("date == 1959-08-17", true),
+            ("date > 1959-08-17T00:00", false),
+            (r#"date == "1959-08-17""#, false),
+            (r#"date < "August""#, false),
+            ("not (exists composer or year < 1900) and (live == false)", true),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let filter = Filter::parse(input).unwrap();
+            assert_eq!(expected, filter.is_match(&block), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(Filter::parse(""), Err(Error::UnexpectedEnd)));
+        assert!(matches!(Filter::parse("rating >="), Err(Error::UnexpectedEnd)));
+        assert!(matches!(Filter::parse("rating = 4"), Err(Error::UnexpectedToken(t, 7)) if t == "="));
+        assert!(matches!(Filter::parse("rating 4"), Err(Error::UnexpectedToken(t, 7)) if t == "4"));
+        assert!(matches!(Filter::parse("rating > 4 4"), Err(Error::UnexpectedToken(t, 11)) if t == "4"));
+        assert!(matches!(Filter::parse("(exists rating"), Err(Error::UnexpectedEnd)));
+        assert!(matches!(Filter::parse("exists and"), Err(Error::UnexpectedToken(t, 7)) if t == "and"));
+        assert!(matches!(Filter::parse(r#"title == "open"#), Err(Error::UnterminatedString(9))));
+        assert!(matches!(Filter::parse("genre[x] == 1"), Err(Error::InvalidQuery(..))));
+        assert!(matches!(Filter::parse(r#"title matches "(""#), Err(Error::InvalidRegex(..))));
+        assert!(matches!(Filter::parse("title matches 4"), Err(Error::UnexpectedToken(..))));
+    }
+}
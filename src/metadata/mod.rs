@@ -1,14 +1,22 @@
 //! Primitives and methods for accessing and working with item metadata.
 
+pub mod cache;
+pub mod filter;
 pub mod item_paths;
 pub mod new_schema;
 pub mod plexer;
 pub mod processor;
+pub mod provenance;
 pub mod schema;
+pub mod table;
+pub mod tags;
 
+pub use self::cache::{Error as CacheError, MetaCache};
+pub use self::filter::{Error as FilterError, Filter};
 pub use self::plexer::{Error as PlexerError, Plexer};
-pub use self::processor::Error as ProcessorError;
-pub use self::schema::{Arity, Schema};
+pub use self::processor::{Error as ProcessorError, Strictness, Warning};
+pub use self::provenance::{Origin, Position, Provenance};
+pub use self::schema::{Arity, Schema, SchemaKind};
 
 pub use self::new_schema::Metadata;
 
@@ -1,21 +1,17 @@
 //! Data representations of meta files.
 
-use std::collections::{BTreeMap, HashMap};
-
 use serde::{Deserialize, Serialize};
 
-use crate::types::Value;
-
-/// A metadata block, consisting of key-value pairs (aka "fields").
-pub type Block = BTreeMap<String, Value>;
+use crate::metadata::{Arity, Schema};
+use crate::types::{Block, BlockSeq, BlockMap};
 
 /// Represents a collection of metadata blocks.
 /// Metadata blocks may be untagged, or tagged with a file name.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Blocks {
-    Untagged(Vec<Block>),
-    Tagged(HashMap<String, Block>),
+    Untagged(BlockSeq),
+    Tagged(BlockMap),
 }
 
 impl Default for Blocks {
@@ -32,6 +28,21 @@ pub struct Metadata {
     tracks: Blocks,
 }
 
+impl Metadata {
+    /// Extracts the part of this metadata that matches an arity.
+    /// The `album` block provides the data for `Arity::Unit`, while the
+    /// `tracks` blocks provide the data for `Arity::Many`.
+    pub fn into_schema(self, arity: &Arity) -> Schema {
+        match arity {
+            Arity::Unit => Schema::One(self.album),
+            Arity::Many => match self.tracks {
+                Blocks::Untagged(mb_seq) => Schema::Seq(mb_seq),
+                Blocks::Tagged(mb_map) => Schema::Map(mb_map),
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
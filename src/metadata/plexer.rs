@@ -1,16 +1,19 @@
 //! Methods to assign blocks of metadata to their corresponding item file paths.
 
 use std::borrow::Cow;
-use std::io::{Error as IoError, Result as IoResult};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
 use std::iter::FusedIterator;
-use std::path::Path;
+use std::path::{Component, Path};
 use std::path::PathBuf;
 use std::vec::IntoIter as VecIntoIter;
 
 use thiserror::Error;
 
-use crate::config::Sorter;
+use crate::config::{Selection, Sorter};
 use crate::types::{Block, BlockMap};
+use crate::types::block_map::IntoIter as BlockMapIntoIter;
 use crate::types::block_seq::IntoIter as BlockSeqIntoIter;
 use crate::metadata::schema::Schema;
 
@@ -26,6 +29,33 @@ pub enum Error {
     UnusedTaggedBlock(Block, String),
     #[error("item path does not have a file name: {}", .0.display())]
     NamelessItemPath(PathBuf),
+    #[error("item path does not exist: {}", .0.display())]
+    MissingItemPath(PathBuf),
+    #[error(r#"index key is not a relative item path: "{0}""#)]
+    InvalidIndexKey(String),
+    #[error("index meta file must be a mapping of item paths to meta blocks")]
+    UntaggedIndex,
+}
+
+/// An entry of an index meta file that does not provide metadata for any
+/// selected item file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StaleEntry {
+    /// The item file path of the entry does not exist.
+    Missing(PathBuf),
+
+    /// The item file path of the entry is not selected, along with the unused
+    /// meta block and its key.
+    Unused(Block, String),
+}
+
+impl From<StaleEntry> for Error {
+    fn from(stale_entry: StaleEntry) -> Self {
+        match stale_entry {
+            StaleEntry::Missing(item_path) => Self::MissingItemPath(item_path),
+            StaleEntry::Unused(block, rel_path_tag) => Self::UnusedTaggedBlock(block, rel_path_tag),
+        }
+    }
 }
 
 type PlexInItem<'a> = IoResult<Cow<'a, Path>>;
@@ -133,6 +163,95 @@ where
     }
 }
 
+/// Assigns the meta blocks of an index meta file to the item file paths given
+/// by their keys, which are relative to the directory of the index.
+/// Since the item file paths are not known before the meta file is parsed,
+/// each one is checked for existence and selection as it is produced.
+pub struct PlexIndex<'a> {
+    root_dir: Cow<'a, Path>,
+    selection: &'a Selection,
+    entry_iter: BlockMapIntoIter,
+
+    /// The names of the sidecar meta files in each directory visited so far,
+    /// which are not item files.
+    sidecar_names: HashMap<PathBuf, HashSet<OsString>>,
+}
+
+impl<'a> PlexIndex<'a> {
+    /// Creates a new `PlexIndex`.
+    pub fn new(schema: Schema, root_dir: Cow<'a, Path>, selection: &'a Selection) -> Result<Self, Error> {
+        match schema {
+            Schema::Map(mb_map) => Ok(Self {
+                root_dir,
+                selection,
+                entry_iter: mb_map.into_iter(),
+                sidecar_names: HashMap::new(),
+            }),
+            Schema::One(..) | Schema::Seq(..) => Err(Error::UntaggedIndex),
+        }
+    }
+}
+
+/// Checks that an index key is a plain relative path, so that an index can not
+/// provide metadata for item files outside of its directory.
+fn index_key_path(rel_path_tag: &str) -> Option<&Path> {
+    let rel_path = Path::new(rel_path_tag);
+    let mut components = rel_path.components().peekable();
+
+    if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(..))) {
+        None
+    } else {
+        Some(rel_path)
+    }
+}
+
+impl<'a> Iterator for PlexIndex<'a> {
+    type Item = PlexOutItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (rel_path_tag, block) = self.entry_iter.next()?;
+
+        let rel_path = match index_key_path(&rel_path_tag) {
+            Some(rel_path) => rel_path.to_path_buf(),
+            None => return Some(Err(Error::InvalidIndexKey(rel_path_tag))),
+        };
+
+        // Each directory on the way to the item file path must also be selected,
+        // in order to match the item file paths found by walking the directory.
+        let mut item_path = self.root_dir.to_path_buf();
+
+        for component in rel_path.components() {
+            // Sidecar meta files are excluded, as when walking the directory.
+            if !self.sidecar_names.contains_key(&item_path) {
+                match self.selection.sidecar_names_in_dir(&item_path) {
+                    Ok(names) => { self.sidecar_names.insert(item_path.clone(), names); },
+                    Err(err) if err.kind() == IoErrorKind::NotFound => {
+                        return Some(Err(Error::MissingItemPath(self.root_dir.join(&rel_path))));
+                    },
+                    Err(err) => return Some(Err(Error::Io(err))),
+                }
+            }
+
+            let sidecar_names = &self.sidecar_names[&item_path];
+            item_path.push(component);
+
+            match self.selection.is_selected_excluding(&item_path, sidecar_names) {
+                Ok(true) => {},
+
+                // The item file path is excluded, so this meta block is unused.
+                Ok(false) => return Some(Err(Error::UnusedTaggedBlock(block, rel_path_tag))),
+
+                Err(err) if err.kind() == IoErrorKind::NotFound => {
+                    return Some(Err(Error::MissingItemPath(self.root_dir.join(&rel_path))));
+                },
+                Err(err) => return Some(Err(Error::Io(err))),
+            }
+        }
+
+        Some(Ok((Cow::Owned(item_path), block)))
+    }
+}
+
 pub enum Plexer<'a, I>
 where
     I: Iterator<Item = PlexInItem<'a>>,
@@ -489,4 +608,77 @@ mod tests {
         assert_ok!(plexer, path_c, block_c);
         assert_none!(plexer);
     }
+
+    #[test]
+    fn plex_index() {
+        let temp_dir = TU::create_simple_dir("plex_index", &[] as &[&str]);
+        let root_dir = temp_dir.path();
+
+        let album_path = root_dir.join("Artist").join("Album");
+        std::fs::create_dir_all(&album_path).unwrap();
+        std::fs::write(album_path.join("01.flac"), "").unwrap();
+        std::fs::write(album_path.join("02.flac"), "").unwrap();
+        std::fs::write(album_path.join("cover.png"), "").unwrap();
+
+        let selection = Selection::from_patterns(
+            &["*.flac"],
+            &[] as &[&str],
+            &["*"],
+            &[] as &[&str],
+        ).unwrap();
+
+        let block_a = Block(btreemap![str!("key_a") => TU::s("val_a")]);
+        let block_b = Block(btreemap![str!("key_b") => TU::s("val_b")]);
+        let block_c = Block(btreemap![str!("key_c") => TU::s("val_c")]);
+        let block_d = Block(btreemap![str!("key_d") => TU::s("val_d")]);
+        let block_e = Block(btreemap![str!("key_e") => TU::s("val_e")]);
+        let block_f = Block(btreemap![str!("key_f") => TU::s("val_f")]);
+
+        let schema = Schema::Map(BlockMap(indexmap![
+            str!("Artist/Album/01.flac") => block_a.clone(),
+            str!("Artist/Album/03.flac") => block_b.clone(),
+            str!("Artist/Album/cover.png") => block_c.clone(),
+            str!("../outside.flac") => block_d.clone(),
+            str!("Artist/Album/02.flac") => block_e.clone(),
+            str!("Artist") => block_f.clone(),
+        ]));
+
+        let mut plexer = PlexIndex::new(schema, Cow::Borrowed(root_dir), &selection).unwrap();
+        assert_ok!(plexer, album_path.join("01.flac"), block_a);
+        match plexer.next() {
+            Some(Err(Error::MissingItemPath(p))) => assert_eq!(p, album_path.join("03.flac")),
+            _ => panic!("expected missing item path"),
+        }
+        assert_extra_tagged_block!(plexer, block_c, "Artist/Album/cover.png");
+        match plexer.next() {
+            Some(Err(Error::InvalidIndexKey(k))) => assert_eq!(k, "../outside.flac"),
+            _ => panic!("expected invalid index key"),
+        }
+        assert_ok!(plexer, album_path.join("02.flac"), block_e);
+        assert_ok!(plexer, root_dir.join("Artist"), block_f);
+        assert_none!(plexer);
+
+        // Sidecar meta files are not item files, even if otherwise selected.
+        std::fs::write(album_path.join("01.txt"), "").unwrap();
+
+        let sidecar_selection = Selection::from_patterns(&["*"], &[] as &[&str], &["*"], &[] as &[&str])
+            .unwrap()
+            .with_excluded_sidecar(str!("{stem}.txt"));
+
+        let schema = Schema::Map(BlockMap(indexmap![
+            str!("Artist/Album/01.txt") => block_b.clone(),
+            str!("Artist/Album/cover.png") => block_c.clone(),
+        ]));
+
+        let mut plexer = PlexIndex::new(schema, Cow::Borrowed(root_dir), &sidecar_selection).unwrap();
+        assert_extra_tagged_block!(plexer, block_b, "Artist/Album/01.txt");
+        assert_ok!(plexer, album_path.join("cover.png"), block_c);
+        assert_none!(plexer);
+
+        let schema = Schema::Seq(BlockSeq(vec![block_a]));
+        assert!(matches!(
+            PlexIndex::new(schema, Cow::Borrowed(root_dir), &selection),
+            Err(Error::UntaggedIndex),
+        ));
+    }
 }
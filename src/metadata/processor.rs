@@ -1,20 +1,28 @@
 //! High-level methods for processing meta files and loading item file metadata.
 
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
 
+use serde::Deserialize;
 use thiserror::Error;
 
-use crate::config::{Selection, Sorter, FormatError};
-use crate::metadata::plexer::{Error as PlexerError, Plexer};
-use crate::sources::{SourceError, Source, Sourcer};
-use crate::types::Block;
+use crate::config::{Merger, Selection, Sorter, FormatError};
+use crate::config::merger::is_tombstone;
+use crate::metadata::cache::{MetaCache, Plexed};
+use crate::metadata::plexer::{Error as PlexerError, PlexIndex, Plexer, StaleEntry};
+use crate::metadata::provenance::{Origin, Position, Provenance};
+use crate::metadata::schema::SchemaKind;
+use crate::sources::{Anchor, SourceError, Source, Sourcer};
+use crate::types::{Block, Value};
+use crate::util::file_walker::{ChildFileWalker, ParentFileWalker};
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("cannot read metadata file: {0}")]
-    CannotReadMetadata(#[source] FormatError),
+    #[error(r#"cannot read meta file "{}": {1}"#, .0.display())]
+    CannotReadMetadata(PathBuf, #[source] FormatError),
     #[error("cannot find item file paths: {0}")]
     CannotFindItemPaths(#[source] SourceError),
     #[error("cannot find meta file path: {0}")]
@@ -23,71 +31,579 @@ pub enum Error {
     PlexerError(#[source] PlexerError),
     #[error("missing metadata")]
     MissingMetadata,
+    #[error("item path is not inside of the root directory: {}", .0.display())]
+    OutsideRootDir(PathBuf),
+    #[error("cannot walk item file paths: {0}")]
+    CannotWalk(#[source] IoError),
 }
 
+/// Represents how errors from individual meta files are handled when
+/// processing the metadata of an item file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strictness {
+    /// Any error stops processing and is returned.
+    #[default]
+    Strict,
+
+    /// A meta file that causes an error is skipped, and the error is recorded
+    /// as a warning. Processing continues with the remaining meta files.
+    Lenient,
+}
+
+/// Records an error from a meta file that was skipped during lenient processing.
+#[derive(Debug)]
+pub struct Warning {
+    /// The path of the skipped meta file, if it could be found.
+    pub meta_path: Option<PathBuf>,
+
+    /// The name of the source of the skipped meta file.
+    pub source_name: String,
+
+    /// The anchor of the source of the skipped meta file.
+    pub anchor: Anchor,
+
+    pub error: Error,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self.meta_path {
+            Some(meta_path) => write!(f, "skipped meta file \"{}\": {}", meta_path.display(), self.error),
+            None => write!(f, "skipped source \"{}\": {}", self.source_name, self.error),
+        }
+    }
+}
+
+/// Options for aggregating the values of a key path across the descendants of
+/// an item file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Aggregation {
+    /// The maximum depth of descendants to visit, where the children of the
+    /// origin item file are at a depth of 1. If `None`, all descendants are
+    /// visited.
+    pub max_depth: Option<usize>,
+
+    /// Only visit descendants that are not directories.
+    pub leaves_only: bool,
+
+    /// Skip values that are equal to an already-collected value.
+    pub dedupe: bool,
+}
+
+/// The merged metadata of an item file, along with the keys that were unset
+/// by tombstones, and any warnings for meta files that were skipped.
+#[derive(Debug)]
+pub(crate) struct Processed {
+    pub block: Block,
+    pub unset_keys: BTreeSet<String>,
+    pub warnings: Vec<Warning>,
+}
+
+/// The meta blocks plexed from a meta file, along with their positions in the
+/// meta file, keyed by item file path.
+type MetaPlexed<'a> = HashMap<Cow<'a, Path>, (Position, Block)>;
+
+/// What a meta file provides for a target item file, if anything, along with
+/// any stale index entries of the meta file.
+type LookedUp<T> = (Option<T>, Vec<StaleEntry>);
+
 pub struct Processor;
 
 impl Processor {
     /// Processes the metadata contained in a target meta file.
     /// This loads and plexes metadata, and produces a mapping of item file
-    /// paths to metadata blocks.
+    /// paths to metadata blocks. Stale entries of an index meta file are
+    /// reported as errors.
     pub fn process_meta_file<'a>(
         meta_path: &'a Path,
         source: &'a Source,
         selection: &'a Selection,
         sorter: &'a Sorter,
     ) -> Result<HashMap<Cow<'a, Path>, Block>, Error> {
-        let schema = source.read_schema(meta_path).map_err(Error::CannotReadMetadata)?;
+        let (meta_plexed, stale_entries) = Self::plex_meta_file(meta_path, source, selection, sorter)?;
+
+        if let Some(stale_entry) = stale_entries.into_iter().next() {
+            return Err(Error::PlexerError(stale_entry.into()));
+        }
+
+        let meta_plexed = meta_plexed
+            .into_iter()
+            .map(|(item_path, (_, meta_block))| (item_path, meta_block))
+            .collect();
+
+        Ok(meta_plexed)
+    }
+
+    /// Loads and plexes the metadata contained in a target meta file, along
+    /// with the position of each metadata block in the meta file.
+    /// The entries of an index meta file for item files that are missing or
+    /// not selected are returned separately, since they do not prevent the
+    /// remaining entries from providing metadata.
+    fn plex_meta_file<'a>(
+        meta_path: &'a Path,
+        source: &'a Source,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+    ) -> Result<(MetaPlexed<'a>, Vec<StaleEntry>), Error> {
+        let schema = source
+            .read_schema(meta_path)
+            .map_err(|e| Error::CannotReadMetadata(meta_path.into(), e))?;
+
+        let mut meta_plexed = HashMap::new();
+        let mut stale_entries = Vec::new();
+
+        if let Anchor::Index = source.anchor {
+            // Index meta files provide their own item paths.
+            let root_dir = source
+                .anchor_dir_path(meta_path)
+                .map_err(Error::CannotFindItemPaths)?;
+
+            let meta_plexer = PlexIndex::new(schema, root_dir.clone(), selection)
+                .map_err(Error::PlexerError)?;
+
+            for meta_plex_res in meta_plexer {
+                let (item_path, meta_block) = match meta_plex_res {
+                    Ok(plexed) => plexed,
+
+                    // Keys for item files that are missing or not selected only
+                    // leave their own meta blocks unused, the remaining keys
+                    // still provide metadata.
+                    Err(PlexerError::MissingItemPath(item_path)) => {
+                        stale_entries.push(StaleEntry::Missing(item_path));
+                        continue;
+                    },
+                    Err(PlexerError::UnusedTaggedBlock(block, tag)) => {
+                        stale_entries.push(StaleEntry::Unused(block, tag));
+                        continue;
+                    },
+
+                    Err(err) => return Err(Error::PlexerError(err)),
+                };
+
+                // Item paths are always joined onto the root directory.
+                let tag = item_path.strip_prefix(&root_dir).unwrap_or(&item_path);
+                let position = Position::Map(tag.to_string_lossy().into_owned());
+
+                meta_plexed.insert(item_path, (position, meta_block));
+            }
+
+            return Ok((meta_plexed, stale_entries));
+        }
 
         // LEARN: Since `meta_path` is already a ref, no need to add `&`!
         let sel_item_paths = source
             .selected_item_paths(meta_path, selection)
             .map_err(Error::CannotFindItemPaths)?;
 
-        let mut meta_plexed = HashMap::new();
-
+        let schema_kind = SchemaKind::from(&schema);
         let meta_plexer = Plexer::new(schema, sel_item_paths, &sorter);
 
-        for meta_plex_res in meta_plexer {
+        // Any plexing error ends processing, so sequence blocks are always
+        // emitted in order.
+        for (index, meta_plex_res) in meta_plexer.enumerate() {
             let (item_path, meta_block) = meta_plex_res.map_err(Error::PlexerError)?;
-            meta_plexed.insert(item_path, meta_block);
+
+            let position = match schema_kind {
+                SchemaKind::One => Position::One,
+                SchemaKind::Seq => Position::Seq(index),
+                SchemaKind::Map => Position::Map(
+                    item_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+                ),
+            };
+
+            meta_plexed.insert(item_path, (position, meta_block));
         }
 
-        Ok(meta_plexed)
+        Ok((meta_plexed, stale_entries))
     }
 
     /// Processes metadata for a target item file.
     /// This performs the necessary merging of all metadata across different
-    /// targets that may provide data for this item file. Merging is done in a
-    /// "combine-last" fashion; if a later target produces the same metadata key
-    /// as an earlier target, the later one wins and overwrites the earlier one.
+    /// targets that may provide data for this item file. Merging is done in
+    /// source order, and the merger decides how a value from a later target is
+    /// combined with a value for the same key from an earlier target.
+    /// A later target may also remove a key by providing a tombstone value.
     pub fn process_item_file(
         item_path: &Path,
         sourcer: &Sourcer,
         selection: &Selection,
         sorter: &Sorter,
+        merger: &Merger,
     ) -> Result<Block, Error> {
+        Self::process_item_file_with_warnings(item_path, sourcer, selection, sorter, merger, Strictness::Strict)
+            .map(|(block, _)| block)
+    }
+
+    /// Processes metadata for a target item file, in the same way as
+    /// `process_item_file`, but with a choice of strictness. When lenient, meta
+    /// files that cause errors are skipped and reported as warnings, and the
+    /// metadata from the remaining meta files is still merged and returned.
+    /// When strict, no warnings are produced, and the first error is returned.
+    pub fn process_item_file_with_warnings(
+        item_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        strictness: Strictness,
+    ) -> Result<(Block, Vec<Warning>), Error> {
+        // Each meta file is only processed once, even if it is found through
+        // more than one source.
+        let cache = MetaCache::new();
+
+        Self::process_item_file_cached(item_path, None, sourcer, selection, sorter, merger, strictness, &cache)
+            .map(|processed| (processed.block, processed.warnings))
+    }
+
+    /// Processes metadata for a target item file, in the same way as
+    /// `process_item_file`, while also tracking where the merged value of each
+    /// key came from, and which earlier values it overrode.
+    pub fn process_item_file_with_provenance(
+        item_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+    ) -> Result<(Block, BTreeMap<String, Provenance>), Error> {
+        let mut comp_mb = Block::new();
+        let mut provenances = BTreeMap::<String, Provenance>::new();
+
+        let lookup = |meta_path: &Path, source: &Source| {
+            Self::lookup_item_block(item_path, meta_path, source, selection, sorter)
+        };
+
+        Self::visit_item_blocks(item_path, None, sourcer, None, lookup, |meta_path, source, (position, meta_block)| {
+            let origin = Origin {
+                meta_path: meta_path.into(),
+                source_name: source.name.clone(),
+                anchor: source.anchor,
+                position,
+            };
+
+            for (key, later_val) in meta_block {
+                let outcome = merger.merge_entry(&mut comp_mb, key.clone(), later_val);
+                provenances.entry(key).or_default().record(&origin, outcome);
+            }
+        })?;
+
+        // Keys that were removed by tombstones have no merged value.
+        provenances.retain(|key, _| comp_mb.contains_key(key));
+
+        Ok((comp_mb, provenances))
+    }
+
+    /// Processes metadata for a target item file, in the same way as
+    /// `process_item_file_with_warnings`, while reusing the plexed results of
+    /// meta files that were already processed. Newly processed meta files are
+    /// added to the cache. If a root directory is given, index meta files are
+    /// only looked for inside of it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn process_item_file_cached(
+        item_path: &Path,
+        root_dir: Option<&Path>,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        strictness: Strictness,
+        cache: &MetaCache,
+    ) -> Result<Processed, Error> {
         let mut comp_mb = Block::new();
+        let mut unset_keys = BTreeSet::new();
+        let mut warnings = Vec::new();
 
-        let meta_paths = sourcer.meta_paths(item_path);
+        let opt_warnings = match strictness {
+            Strictness::Strict => None,
+            Strictness::Lenient => Some(&mut warnings),
+        };
+
+        let lookup = |meta_path: &Path, source: &Source| {
+            let meta_plexed = cache.get_or_try_insert_with(meta_path, source, selection, sorter, || {
+                let (meta_plexed, stale_entries) = Self::plex_meta_file(meta_path, source, selection, sorter)?;
+
+                let blocks = meta_plexed
+                    .into_iter()
+                    .map(|(path, (_, block))| (path.into_owned(), block))
+                    .collect();
+
+                Ok(Plexed { blocks, stale_entries })
+            })?;
+
+            Ok((meta_plexed.blocks.get(item_path).cloned(), meta_plexed.stale_entries.clone()))
+        };
+
+        Self::visit_item_blocks(item_path, root_dir, sourcer, opt_warnings, lookup, |_, _, meta_block| {
+            // Tombstones always remove their keys, and any later value for a
+            // removed key is inserted again, so a key stays unset if the last
+            // meta block that has the key has a tombstone for it.
+            for (key, value) in meta_block.iter() {
+                if is_tombstone(value) {
+                    unset_keys.insert(key.clone());
+                } else {
+                    unset_keys.remove(key);
+                }
+            }
+
+            merger.merge(&mut comp_mb, meta_block)
+        })?;
+
+        Ok(Processed { block: comp_mb, unset_keys, warnings })
+    }
+
+    /// Processes a meta file and extracts the metadata block for a target item
+    /// file, along with its position in the meta file, and any stale entries
+    /// of the meta file.
+    fn lookup_item_block(
+        item_path: &Path,
+        meta_path: &Path,
+        source: &Source,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> Result<LookedUp<(Position, Block)>, Error> {
+        // The results of processing a meta file will often return extra
+        // metadata for item files besides the targeted one. Extract the target
+        // item file's metadata, and drop the remaining results.
+        let (mut processed_meta_file, stale_entries) = Self::plex_meta_file(meta_path, source, selection, sorter)?;
+        Ok((processed_meta_file.remove(item_path), stale_entries))
+    }
+
+    /// Calls a function with the metadata block for a target item file from
+    /// each of its meta files, in source order. If a list of warnings is
+    /// given, meta files that cause errors are skipped and recorded in it,
+    /// instead of stopping with an error.
+    /// The metadata block for a meta file is found with a lookup function,
+    /// which returns `None` if the meta file has no block for the item file,
+    /// along with any stale index entries of the meta file. Stale entries are
+    /// errors when strict, and are recorded as warnings otherwise.
+    fn visit_item_blocks<T, L, F>(
+        item_path: &Path,
+        root_dir: Option<&Path>,
+        sourcer: &Sourcer,
+        mut opt_warnings: Option<&mut Vec<Warning>>,
+        mut lookup: L,
+        mut visit: F,
+    ) -> Result<(), Error>
+    where
+        L: FnMut(&Path, &Source) -> Result<LookedUp<T>, Error>,
+        F: FnMut(&Path, &Source, T),
+    {
+        let meta_paths = match root_dir {
+            None => sourcer.meta_paths(item_path),
+            Some(root_dir) => sourcer.meta_paths_within(item_path, root_dir),
+        };
 
         for mps_res in meta_paths {
-            let (meta_path, source) = mps_res.map_err(Error::CannotFindMetaPath)?;
-
-            let mut processed_meta_file =
-                Self::process_meta_file(&meta_path, source, selection, sorter)?;
-
-            // The results of processing a meta file will often return extra
-            // metadata for item files besides the targeted one. Extract the
-            // target item file's metadata, and drop the remaining results.
-            if let Some(meta_block) = processed_meta_file.remove(item_path) {
-                comp_mb.extend(meta_block)
-            } else {
-                Err(Error::MissingMetadata)?
+            let (meta_path, source) = match (mps_res, opt_warnings.as_deref_mut()) {
+                (Ok(found), _) => found,
+                (Err((err, _)), None) => return Err(Error::CannotFindMetaPath(err)),
+                (Err((err, source)), Some(warnings)) => {
+                    warnings.push(Warning {
+                        meta_path: None,
+                        source_name: source.name.clone(),
+                        anchor: source.anchor,
+                        error: Error::CannotFindMetaPath(err),
+                    });
+                    continue;
+                },
+            };
+
+            let res = lookup(&meta_path, source).and_then(|(opt_found, stale_entries)| {
+                let mut stale_errors = stale_entries.into_iter().map(|e| Error::PlexerError(e.into()));
+
+                match opt_warnings.as_deref_mut() {
+                    None => if let Some(err) = stale_errors.next() {
+                        return Err(err);
+                    },
+                    Some(warnings) => warnings.extend(stale_errors.map(|error| Warning {
+                        meta_path: Some(meta_path.clone()),
+                        source_name: source.name.clone(),
+                        anchor: source.anchor,
+                        error,
+                    })),
+                }
+
+                match opt_found {
+                    Some(found) => Ok(Some(found)),
+
+                    // Index meta files are not required to cover every item file.
+                    None if matches!(source.anchor, Anchor::Index) => Ok(None),
+
+                    None => Err(Error::MissingMetadata),
+                }
+            });
+
+            match (res, opt_warnings.as_deref_mut()) {
+                (Ok(Some(found)), _) => visit(&meta_path, source, found),
+                (Ok(None), _) => {},
+                (Err(err), None) => return Err(err),
+                (Err(err), Some(warnings)) => {
+                    warnings.push(Warning {
+                        meta_path: Some(meta_path),
+                        source_name: source.name.clone(),
+                        anchor: source.anchor,
+                        error: err,
+                    });
+                },
             }
         }
 
-        Ok(comp_mb)
+        Ok(())
+    }
+
+    /// Finds the value at a key path in the metadata of the nearest ancestor
+    /// of an item file that defines it, starting with the item file itself.
+    /// Returns the found value along with the path of the ancestor it came
+    /// from, or `None` if no ancestor defines the key path. The walk stops at
+    /// a root directory, which must contain the item file path.
+    /// Each meta file is only processed once during the walk.
+    pub fn find_in_ancestors<'p, S: AsRef<str>>(
+        item_path: &'p Path,
+        key_path: &[S],
+        root_dir: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+    ) -> Result<Option<(Cow<'p, Path>, Value)>, Error> {
+        let cache = MetaCache::new();
+        let process = |path: &Path| {
+            Self::process_item_file_cached(path, Some(root_dir), sourcer, selection, sorter, merger, Strictness::Strict, &cache)
+        };
+
+        Self::find_in_ancestors_with(item_path, key_path, root_dir, process)
+    }
+
+    /// Walks the ancestors of an item file for `find_in_ancestors`, using a
+    /// function to process the metadata of each ancestor.
+    /// The walk stops at an ancestor that unsets the key with a tombstone,
+    /// since the values of further ancestors are not inherited past it.
+    pub(crate) fn find_in_ancestors_with<'p, S, P>(
+        item_path: &'p Path,
+        key_path: &[S],
+        root_dir: &Path,
+        mut process: P,
+    ) -> Result<Option<(Cow<'p, Path>, Value)>, Error>
+    where
+        S: AsRef<str>,
+        P: FnMut(&Path) -> Result<Processed, Error>,
+    {
+        if !item_path.starts_with(root_dir) {
+            return Err(Error::OutsideRootDir(item_path.into()));
+        }
+
+        let (first_key, rest_keys) = match key_path.split_first() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let ancestor_paths = ParentFileWalker::new(item_path)
+            .take_while(|p| p.starts_with(root_dir));
+
+        for ancestor_path in ancestor_paths {
+            let processed = process(&ancestor_path)?;
+
+            let found = processed.block
+                .get(first_key.as_ref())
+                .and_then(|v| v.get_key_path(rest_keys));
+
+            if let Some(value) = found {
+                return Ok(Some((ancestor_path, value.clone())));
+            }
+
+            if processed.unset_keys.contains(first_key.as_ref()) {
+                return Ok(None);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Collects the values at a key path in the metadata of the descendants
+    /// of an item file, visited in sorted depth-first order. Values that are
+    /// sequences have their elements collected individually. Descendants that
+    /// do not define the key path are skipped.
+    /// Each meta file is only processed once during the walk.
+    pub fn aggregate_descendants<S: AsRef<str>>(
+        item_path: &Path,
+        key_path: &[S],
+        aggregation: &Aggregation,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+    ) -> Result<Value, Error> {
+        let cache = MetaCache::new();
+        let process = |path: &Path| {
+            Self::process_item_file_cached(path, None, sourcer, selection, sorter, merger, Strictness::Strict, &cache)
+                .map(|processed| processed.block)
+        };
+
+        Self::aggregate_descendants_with(item_path, key_path, aggregation, selection, sorter, process)
+    }
+
+    /// Walks the descendants of an item file for `aggregate_descendants`,
+    /// using a function to process the metadata of each descendant.
+    pub(crate) fn aggregate_descendants_with<S, P>(
+        item_path: &Path,
+        key_path: &[S],
+        aggregation: &Aggregation,
+        selection: &Selection,
+        sorter: &Sorter,
+        mut process: P,
+    ) -> Result<Value, Error>
+    where
+        S: AsRef<str>,
+        P: FnMut(&Path) -> Result<Block, Error>,
+    {
+        let mut collected = Vec::new();
+
+        let (first_key, rest_keys) = match key_path.split_first() {
+            Some(split) => split,
+            None => return Ok(Value::Sequence(collected)),
+        };
+
+        let mut walker = ChildFileWalker::new(item_path);
+
+        // The origin item file itself is not a descendant, but needs to be
+        // visited in order to start delving.
+        while let Some(path_res) = walker.next() {
+            let path = path_res.map_err(Error::CannotWalk)?;
+
+            // Since the walker only visits descendants of the origin item
+            // file, the number of extra components is the depth.
+            let depth = path.components().count() - item_path.components().count();
+
+            if aggregation.max_depth.map(|md| depth < md).unwrap_or(true) {
+                walker.delve(selection, sorter).map_err(Error::CannotWalk)?;
+            }
+
+            if depth == 0 || (aggregation.leaves_only && path.is_dir()) {
+                continue;
+            }
+
+            let block = process(&path)?;
+
+            let found = block
+                .get(first_key.as_ref())
+                .and_then(|v| v.get_key_path(rest_keys));
+
+            let values = match found {
+                None => continue,
+                Some(Value::Sequence(seq)) => seq.clone(),
+                Some(value) => vec![value.clone()],
+            };
+
+            for value in values {
+                if aggregation.dedupe && collected.iter().any(|c: &Value| c.val_eq(&value)) {
+                    continue;
+                }
+
+                collected.push(value);
+            }
+        }
+
+        Ok(Value::Sequence(collected))
     }
 }
 
@@ -98,8 +614,11 @@ mod tests {
     use maplit::{btreemap, hashmap};
     use str_macro::str;
 
+    use crate::config::MergeStrategy;
     use crate::config::selection::Matcher;
-    use crate::sources::Anchor;
+    use crate::metadata::provenance::Overridden;
+    use crate::metadata::tags;
+    use crate::sources::{Anchor, Layout};
 
     use crate::test_util::TestUtil as TU;
 
@@ -244,9 +763,787 @@ mod tests {
                 &sourcer,
                 &selection,
                 &sorter,
+                &Merger::default(),
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn process_item_file_combined() {
+        let temp_dir = TU::create_simple_dir("process_item_file_combined", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        let album_path = path.join("ALBUM");
+        std::fs::create_dir(&album_path).unwrap();
+        std::fs::write(album_path.join("01.flac"), "").unwrap();
+        std::fs::write(album_path.join("02.flac"), "").unwrap();
+        std::fs::write(
+            album_path.join("album.toml"),
+            indoc::indoc! {"
+                [album]
+                artist = 'album_artist'
+                title = 'album_title'
+
+                [[tracks]]
+                title = 'track_01_title'
+
+                [[tracks]]
+                artist = 'track_02_artist'
+                title = 'track_02_title'
+            "},
+        ).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.toml"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(
+                Source::from_name(str!("album.toml"), Anchor::External).unwrap()
+                    .with_layout(Layout::Combined)
+            )
+            .source(
+                Source::from_name(str!("album.toml"), Anchor::Internal).unwrap()
+                    .with_layout(Layout::Combined)
+            );
+
+        let inputs_and_expected = vec![
+            (
+                album_path.clone(),
+                Block(btreemap![
+                    str!("artist") => TU::s("album_artist"),
+                    str!("title") => TU::s("album_title"),
+                ]),
+            ),
+            (
+                album_path.join("01.flac"),
+                Block(btreemap![
+                    str!("title") => TU::s("track_01_title"),
+                ]),
+            ),
+            (
+                album_path.join("02.flac"),
+                Block(btreemap![
+                    str!("artist") => TU::s("track_02_artist"),
+                    str!("title") => TU::s("track_02_title"),
+                ]),
+            ),
+        ];
+
+        for (item_path, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file(
+                &item_path,
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
             )
             .unwrap();
             assert_eq!(expected, produced);
         }
     }
+
+    #[test]
+    fn find_in_ancestors() {
+        let temp_dir = TU::create_simple_dir("find_in_ancestors", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        let album_path = path.join("ALBUM");
+        let disc_path = album_path.join("DISC");
+        std::fs::create_dir_all(&disc_path).unwrap();
+        std::fs::write(disc_path.join("01.flac"), "").unwrap();
+        std::fs::write(path.join("album.json"), r#"{"catalog": "C-01"}"#).unwrap();
+        std::fs::write(
+            album_path.join("album.json"),
+            r#"{"genre": "jazz", "label": {"name": "Aiguille Records"}}"#,
+        ).unwrap();
+        std::fs::write(disc_path.join("album.json"), r#"{"genre": "funk"}"#).unwrap();
+        std::fs::write(disc_path.join("track.json"), r#"[{"title": "Daylight"}]"#).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let merger = Merger::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("track.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("album.json"), Anchor::Internal).unwrap());
+
+        let item_path = disc_path.join("01.flac");
+
+        let inputs_and_expected = vec![
+            ((vec!["title"], path), Some((item_path.clone(), TU::s("Daylight")))),
+            ((vec!["genre"], path), Some((disc_path.clone(), TU::s("funk")))),
+            (
+                (vec!["label", "name"], path),
+                Some((album_path.clone(), TU::s("Aiguille Records"))),
+            ),
+            ((vec!["catalog"], path), Some((path.to_path_buf(), TU::s("C-01")))),
+            // The walk does not go above the root directory.
+            ((vec!["catalog"], album_path.as_path()), None),
+            ((vec!["label", "missing"], path), None),
+            ((vec![], path), None),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (key_path, root_dir) = input;
+
+            let produced = Processor::find_in_ancestors(
+                &item_path,
+                &key_path,
+                root_dir,
+                &sourcer,
+                &selection,
+                &sorter,
+                &merger,
+            )
+            .unwrap()
+            .map(|(p, v)| (p.into_owned(), v));
+            assert_eq!(expected, produced);
+        }
+
+        // A tombstone stops the walk, even though an ancestor defines the key.
+        std::fs::write(disc_path.join("track.json"), r#"[{"title": "Daylight", "genre": "$unset"}]"#).unwrap();
+
+        let produced = Processor::find_in_ancestors(
+            &item_path,
+            &["genre"],
+            path,
+            &sourcer,
+            &selection,
+            &sorter,
+            &merger,
+        )
+        .unwrap();
+        assert_eq!(None, produced);
+
+        let res = Processor::find_in_ancestors(
+            &item_path,
+            &["genre"],
+            &path.join("OTHER"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &merger,
+        );
+        assert!(matches!(res, Err(Error::OutsideRootDir(_))));
+    }
+
+    #[test]
+    fn aggregate_descendants() {
+        let temp_dir = TU::create_simple_dir("aggregate_descendants", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        for (disc_name, tracks_json) in [
+            ("DISC_01", r#"[{"artist": "Aiguille"}, {"artist": ["Aiguille", "Evil Needle"]}]"#),
+            ("DISC_02", r#"[{"artist": "Evil Needle"}, {"title": "Untitled"}]"#),
+        ] {
+            let disc_path = path.join(disc_name);
+            std::fs::create_dir(&disc_path).unwrap();
+            std::fs::write(disc_path.join("01.flac"), "").unwrap();
+            std::fs::write(disc_path.join("02.flac"), "").unwrap();
+            std::fs::write(disc_path.join("track.json"), tracks_json).unwrap();
+            std::fs::write(disc_path.join("album.json"), r#"{"artist": "Various"}"#).unwrap();
+        }
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let merger = Merger::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("track.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("album.json"), Anchor::Internal).unwrap());
+
+        let inputs_and_expected = vec![
+            (
+                Aggregation::default(),
+                vec!["Various", "Aiguille", "Aiguille", "Evil Needle", "Various", "Evil Needle"],
+            ),
+            (
+                Aggregation { dedupe: true, ..Default::default() },
+                vec!["Various", "Aiguille", "Evil Needle"],
+            ),
+            (
+                Aggregation { leaves_only: true, ..Default::default() },
+                vec!["Aiguille", "Aiguille", "Evil Needle", "Evil Needle"],
+            ),
+            (
+                Aggregation { max_depth: Some(1), ..Default::default() },
+                vec!["Various", "Various"],
+            ),
+            (
+                Aggregation { max_depth: Some(1), leaves_only: true, ..Default::default() },
+                vec![],
+            ),
+        ];
+
+        for (aggregation, expected) in inputs_and_expected {
+            let expected = Value::Sequence(expected.into_iter().map(TU::s).collect());
+            let produced = Processor::aggregate_descendants(
+                path,
+                &["artist"],
+                &aggregation,
+                &sourcer,
+                &selection,
+                &sorter,
+                &merger,
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn process_item_file_merging() {
+        let temp_dir = TU::create_simple_dir("process_item_file_merging", ["01.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(
+            path.join("item.json"),
+            r#"[{"genre": ["jazz"], "title": "Daylight"}]"#,
+        ).unwrap();
+        std::fs::write(
+            path.join("01.flac.json"),
+            r#"{"genre": ["funk", "jazz"], "title": "Nightfall"}"#,
+        ).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("{name}.json"), Anchor::Sidecar).unwrap());
+
+        let inputs_and_expected = vec![
+            (
+                Merger::default(),
+                Block(btreemap![
+                    str!("genre") => Value::Sequence(vec![TU::s("funk"), TU::s("jazz")]),
+                    str!("title") => TU::s("Nightfall"),
+                ]),
+            ),
+            (
+                Merger::build(
+                    MergeStrategy::KeepFirst,
+                    vec![("genre", MergeStrategy::Union)],
+                ).unwrap(),
+                Block(btreemap![
+                    str!("genre") => Value::Sequence(vec![TU::s("jazz"), TU::s("funk")]),
+                    str!("title") => TU::s("Daylight"),
+                ]),
+            ),
+        ];
+
+        for (merger, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file(
+                &path.join("01.flac"),
+                &sourcer,
+                &selection,
+                &sorter,
+                &merger,
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn process_item_file_with_provenance() {
+        let temp_dir = TU::create_simple_dir("process_item_file_with_provenance", ["02.flac", "01.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(
+            path.join("item.json"),
+            r#"[
+                {"artist": "artist_a1", "genre": ["jazz"]},
+                {"artist": "artist_a2", "genre": ["jazz"], "mood": "calm"}
+            ]"#,
+        ).unwrap();
+        std::fs::write(
+            path.join("tags.json"),
+            r#"{
+                "01.flac": {"artist": "artist_b1"},
+                "02.flac": {"artist": "artist_b2", "genre": ["funk"]}
+            }"#,
+        ).unwrap();
+        std::fs::write(
+            path.join("02.flac.json"),
+            r#"{"artist": "artist_c2", "mood": "$unset"}"#,
+        ).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let merger = Merger::build(MergeStrategy::Overwrite, vec![("genre", MergeStrategy::Union)]).unwrap();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("tags.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("{name}.json"), Anchor::Sidecar).unwrap());
+
+        let (produced_block, produced_provenances) = Processor::process_item_file_with_provenance(
+            &path.join("02.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &merger,
+        )
+        .unwrap();
+
+        // The block is the same as without tracking provenance.
+        let expected_block = Processor::process_item_file(
+            &path.join("02.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &merger,
+        )
+        .unwrap();
+        assert_eq!(expected_block, produced_block);
+        assert_eq!(
+            Block(btreemap![
+                str!("artist") => TU::s("artist_c2"),
+                str!("genre") => Value::Sequence(vec![TU::s("jazz"), TU::s("funk")]),
+            ]),
+            produced_block,
+        );
+
+        let seq_origin = Origin {
+            meta_path: path.join("item.json"),
+            source_name: str!("item.json"),
+            anchor: Anchor::External,
+            position: Position::Seq(1),
+        };
+        let map_origin = Origin {
+            meta_path: path.join("tags.json"),
+            source_name: str!("tags.json"),
+            anchor: Anchor::External,
+            position: Position::Map(str!("02.flac")),
+        };
+        let one_origin = Origin {
+            meta_path: path.join("02.flac.json"),
+            source_name: str!("{name}.json"),
+            anchor: Anchor::Sidecar,
+            position: Position::One,
+        };
+
+        // Removed keys have no provenance.
+        let expected_provenances = btreemap![
+            str!("artist") => Provenance {
+                origins: vec![one_origin],
+                overridden: vec![
+                    Overridden { value: TU::s("artist_a2"), origins: vec![seq_origin.clone()] },
+                    Overridden { value: TU::s("artist_b2"), origins: vec![map_origin.clone()] },
+                ],
+            },
+            str!("genre") => Provenance {
+                origins: vec![seq_origin, map_origin],
+                overridden: vec![],
+            },
+        ];
+        assert_eq!(expected_provenances, produced_provenances);
+    }
+
+    #[test]
+    fn process_item_file_with_warnings() {
+        let temp_dir = TU::create_simple_dir("process_item_file_with_warnings", ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(
+            path.join("item.json"),
+            r#"[{"title": "Daylight"}, {"title": "Nightfall"}]"#,
+        ).unwrap();
+        std::fs::write(
+            path.join("tags.json"),
+            r#"{"02.flac": {"artist": "artist_b2"}}"#,
+        ).unwrap();
+        std::fs::write(path.join("01.flac.json"), r#"{"artist": "#).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("tags.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("{name}.json"), Anchor::Sidecar).unwrap());
+
+        let res = Processor::process_item_file_with_warnings(
+            &path.join("01.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Strict,
+        );
+        assert!(matches!(res, Err(Error::PlexerError(PlexerError::UnusedItemPath(_)))));
+
+        let (produced, warnings) = Processor::process_item_file_with_warnings(
+            &path.join("01.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Lenient,
+        )
+        .unwrap();
+
+        assert_eq!(Block(btreemap![str!("title") => TU::s("Daylight")]), produced);
+        assert_eq!(warnings.len(), 2);
+
+        assert_eq!(warnings[0].meta_path, Some(path.join("tags.json")));
+        assert_eq!(warnings[0].source_name, "tags.json");
+        assert_eq!(warnings[0].anchor, Anchor::External);
+        assert!(matches!(warnings[0].error, Error::PlexerError(PlexerError::UnusedItemPath(_))));
+
+        assert_eq!(warnings[1].meta_path, Some(path.join("01.flac.json")));
+        assert_eq!(warnings[1].source_name, "{name}.json");
+        assert_eq!(warnings[1].anchor, Anchor::Sidecar);
+        assert!(matches!(warnings[1].error, Error::CannotReadMetadata(..)));
+
+        // An error in a meta file affects all of the item files it covers.
+        let (produced, warnings) = Processor::process_item_file_with_warnings(
+            &path.join("02.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Lenient,
+        )
+        .unwrap();
+
+        assert_eq!(Block(btreemap![str!("title") => TU::s("Nightfall")]), produced);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].meta_path, Some(path.join("tags.json")));
+
+        // Sources whose meta file paths cannot be found are still reported.
+        let temp_dir = TU::create_simple_dir("process_item_file_with_warnings_source", ["01.flac"]);
+        let path = temp_dir.path();
+        std::fs::create_dir(path.join("01.flac.json")).unwrap();
+
+        let mut sourcer = Sourcer::new();
+        sourcer.source(Source::from_name(str!("{name}.json"), Anchor::Sidecar).unwrap());
+
+        let (produced, warnings) = Processor::process_item_file_with_warnings(
+            &path.join("01.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Lenient,
+        )
+        .unwrap();
+
+        assert_eq!(Block::new(), produced);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].meta_path, None);
+        assert_eq!(warnings[0].source_name, "{name}.json");
+        assert_eq!(warnings[0].anchor, Anchor::Sidecar);
+        assert!(matches!(warnings[0].error, Error::CannotFindMetaPath(SourceError::NotAFile(_))));
+    }
+
+    #[test]
+    fn process_item_file_table() {
+        let temp_dir = TU::create_simple_dir("process_item_file_table", ["02.flac", "01.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("tracks.csv"), "title,rating\nDaylight,5\nNightfall,\n").unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.csv"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer.source(Source::from_name(str!("tracks.csv"), Anchor::External).unwrap());
+
+        // Rows are assigned to items in sorted order.
+        let inputs_and_expected = vec![
+            (
+                path.join("01.flac"),
+                Block(btreemap![
+                    str!("rating") => TU::i(5),
+                    str!("title") => TU::s("Daylight"),
+                ]),
+            ),
+            (
+                path.join("02.flac"),
+                Block(btreemap![
+                    str!("title") => TU::s("Nightfall"),
+                ]),
+            ),
+        ];
+
+        for (item_path, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file(
+                &item_path,
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn process_item_file_index() {
+        let temp_dir = TU::create_simple_dir("process_item_file_index", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        let album_path = path.join("Artist").join("Album");
+        std::fs::create_dir_all(&album_path).unwrap();
+        std::fs::write(album_path.join("01.flac"), "").unwrap();
+        std::fs::write(album_path.join("02.flac"), "").unwrap();
+        std::fs::write(
+            path.join("catalog.json"),
+            r#"{
+                "Artist/Album": {"title": "album_title"},
+                "Artist/Album/01.flac": {"title": "track_01_title"}
+            }"#,
+        ).unwrap();
+        std::fs::write(album_path.join("album.json"), r#"{"artist": "album_artist"}"#).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("catalog.json"), Anchor::Index).unwrap())
+            .source(Source::from_name(str!("album.json"), Anchor::Internal).unwrap());
+
+        let inputs_and_expected = vec![
+            (
+                album_path.clone(),
+                Block(btreemap![
+                    str!("artist") => TU::s("album_artist"),
+                    str!("title") => TU::s("album_title"),
+                ]),
+            ),
+            (
+                album_path.join("01.flac"),
+                Block(btreemap![
+                    str!("title") => TU::s("track_01_title"),
+                ]),
+            ),
+            // Items not in the index are not an error.
+            (album_path.join("02.flac"), Block::new()),
+        ];
+
+        for (item_path, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file(
+                &item_path,
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+
+        // Stale entries for item paths that do not exist or are not selected
+        // are errors when strict.
+        std::fs::write(album_path.join("notes.json"), "").unwrap();
+        std::fs::write(
+            path.join("catalog.json"),
+            r#"{
+                "Artist/Album/03.flac": {"title": "track_03_title"},
+                "Artist/Album/notes.json": {"title": "notes_title"},
+                "Artist/Album/01.flac": {"title": "track_01_title"}
+            }"#,
+        ).unwrap();
+
+        let res = Processor::process_item_file(
+            &album_path.join("01.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+        );
+        assert!(matches!(
+            res,
+            Err(Error::PlexerError(PlexerError::MissingItemPath(p))) if p == album_path.join("03.flac"),
+        ));
+
+        let source = Source::from_name(str!("catalog.json"), Anchor::Index).unwrap();
+        let meta_path = path.join("catalog.json");
+        assert!(matches!(
+            Processor::process_meta_file(&meta_path, &source, &selection, &sorter),
+            Err(Error::PlexerError(PlexerError::MissingItemPath(_))),
+        ));
+
+        // When lenient, stale entries are reported as warnings, and only leave
+        // their own meta blocks unused.
+        let (produced, warnings) = Processor::process_item_file_with_warnings(
+            &album_path.join("01.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Lenient,
+        )
+        .unwrap();
+        assert_eq!(Block(btreemap![str!("title") => TU::s("track_01_title")]), produced);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|w| w.meta_path.as_deref() == Some(meta_path.as_path())));
+        assert!(matches!(
+            &warnings[0].error,
+            Error::PlexerError(PlexerError::MissingItemPath(p)) if *p == album_path.join("03.flac"),
+        ));
+        assert!(matches!(
+            &warnings[1].error,
+            Error::PlexerError(PlexerError::UnusedTaggedBlock(_, tag)) if tag == "Artist/Album/notes.json",
+        ));
+
+        // The index is only parsed once for all of the item files in a cache.
+        let cache = MetaCache::new();
+        for item_path in [album_path.join("01.flac"), album_path.join("02.flac")] {
+            let processed = Processor::process_item_file_cached(
+                &item_path,
+                Some(path),
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
+                Strictness::Lenient,
+                &cache,
+            )
+            .unwrap();
+            assert_eq!(processed.warnings.len(), 2);
+        }
+        assert_eq!(cache.len(), 1);
+
+        // Index meta files outside of the root directory are not used.
+        let processed = Processor::process_item_file_cached(
+            &album_path.join("01.flac"),
+            Some(&album_path),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+            Strictness::Strict,
+            &MetaCache::new(),
+        )
+        .unwrap();
+        assert_eq!(Block::new(), processed.block);
+        assert!(processed.warnings.is_empty());
+
+        // Invalid keys are still an error for the whole index.
+        std::fs::write(path.join("catalog.json"), r#"{"../01.flac": {}}"#).unwrap();
+
+        let res = Processor::process_item_file(
+            &album_path,
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+        );
+        assert!(matches!(res, Err(Error::PlexerError(PlexerError::InvalidIndexKey(_)))));
+    }
+
+    #[test]
+    fn process_item_file_embedded() {
+        let temp_dir = TU::create_simple_dir("process_item_file_embedded", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        std::fs::write(
+            path.join("01.flac"),
+            tags::sample_flac(&["TITLE=Daylight", "ARTIST=Aiguille"]),
+        ).unwrap();
+        std::fs::write(
+            path.join("02.mp3"),
+            tags::sample_mp3(4, &[("TIT2", tags::utf8_frame("Nightfall"))]),
+        ).unwrap();
+        std::fs::write(path.join("03.wav"), "").unwrap();
+        std::fs::write(path.join("01.flac.json"), r#"{"title": "Daybreak"}"#).unwrap();
+
+        let selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+        let sorter = Sorter::default();
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::embedded())
+            .source(Source::from_name(str!("{name}.json"), Anchor::Sidecar).unwrap());
+
+        let inputs_and_expected = vec![
+            (
+                path.join("01.flac"),
+                Block(btreemap![
+                    str!("artist") => TU::s("Aiguille"),
+                    str!("title") => TU::s("Daybreak"),
+                ]),
+            ),
+            (
+                path.join("02.mp3"),
+                Block(btreemap![
+                    str!("title") => TU::s("Nightfall"),
+                ]),
+            ),
+            // Files that cannot contain tags are skipped over.
+            (path.join("03.wav"), Block::new()),
+        ];
+
+        for (item_path, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file(
+                &item_path,
+                &sourcer,
+                &selection,
+                &sorter,
+                &Merger::default(),
+            )
+            .unwrap();
+            assert_eq!(expected, produced);
+        }
+
+        // Files that claim to contain tags but do not are reported.
+        std::fs::write(path.join("04.flac"), "").unwrap();
+        let res = Processor::process_item_file(
+            &path.join("04.flac"),
+            &sourcer,
+            &selection,
+            &sorter,
+            &Merger::default(),
+        );
+        assert!(matches!(res, Err(Error::CannotReadMetadata(_, FormatError::TagsRead(_)))));
+    }
 }
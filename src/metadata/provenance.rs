@@ -0,0 +1,71 @@
+//! Records of where the values in the merged metadata of an item file came from.
+
+use std::mem;
+use std::path::PathBuf;
+
+use crate::config::merger::MergeOutcome;
+use crate::sources::Anchor;
+use crate::types::Value;
+
+/// The position of a meta block within the schema of its meta file, which also
+/// reflects the shape of that schema.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Position {
+    /// The meta block is the entire contents of a `Schema::One`.
+    One,
+
+    /// The meta block is at this index of a `Schema::Seq`, which corresponds
+    /// to the position of its item file in sorted order.
+    Seq(usize),
+
+    /// The meta block has this tag in a `Schema::Map`. Tags are item file
+    /// names, or relative item file paths for index meta files.
+    Map(String),
+}
+
+/// Identifies the meta block that supplied a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub meta_path: PathBuf,
+    pub source_name: String,
+    pub anchor: Anchor,
+    pub position: Position,
+}
+
+/// An earlier value that was replaced or removed by a later meta block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overridden {
+    pub value: Value,
+    pub origins: Vec<Origin>,
+}
+
+/// Describes where the merged value for a key came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// The meta blocks that supplied the merged value, in source order.
+    /// There is more than one origin if values from multiple meta blocks were
+    /// combined by a merge strategy.
+    pub origins: Vec<Origin>,
+
+    /// The earlier values that were replaced or removed, in source order.
+    pub overridden: Vec<Overridden>,
+}
+
+impl Provenance {
+    /// Updates this provenance after a value from a meta block was merged.
+    pub(crate) fn record(&mut self, origin: &Origin, outcome: MergeOutcome) {
+        match outcome {
+            MergeOutcome::Inserted => self.origins = vec![origin.clone()],
+            MergeOutcome::Unchanged => {},
+            MergeOutcome::Combined => self.origins.push(origin.clone()),
+            MergeOutcome::Replaced(value) => {
+                let origins = mem::replace(&mut self.origins, vec![origin.clone()]);
+                self.overridden.push(Overridden { value, origins });
+            },
+            MergeOutcome::Removed(value) => {
+                let origins = mem::take(&mut self.origins);
+                self.overridden.push(Overridden { value, origins });
+            },
+        }
+    }
+}
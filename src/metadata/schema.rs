@@ -33,8 +33,8 @@ pub(crate) enum SchemaRepr {
 impl From<Anchor> for Arity {
     fn from(value: Anchor) -> Self {
         match value {
-            Anchor::Internal => Arity::Unit,
-            Anchor::External => Arity::Many,
+            Anchor::Internal | Anchor::Sidecar | Anchor::Embedded => Arity::Unit,
+            Anchor::External | Anchor::Index => Arity::Many,
         }
     }
 }
@@ -42,22 +42,36 @@ impl From<Anchor> for Arity {
 impl<'a> From<&'a Anchor> for &'a Arity {
     fn from(value: &'a Anchor) -> Self {
         match value {
-            Anchor::Internal => &Arity::Unit,
-            Anchor::External => &Arity::Many,
+            Anchor::Internal | Anchor::Sidecar | Anchor::Embedded => &Arity::Unit,
+            Anchor::External | Anchor::Index => &Arity::Many,
         }
     }
 }
 
 /// A data structure-level representation of all metadata structures.
 /// This is intended to be agnostic to the text-level format of the metadata.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, EnumDiscriminants)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
 #[serde(untagged)]
+#[strum_discriminants(name(SchemaKind), vis(pub), derive(Hash))]
 pub enum Schema {
     One(Block),
     Seq(BlockSeq),
     Map(BlockMap),
 }
 
+impl Schema {
+    /// Converts the strings in the blocks of this schema that are dates or
+    /// datetimes into datetimes.
+    pub(crate) fn coerce_dates(&mut self) {
+        match self {
+            Self::One(block) => block.coerce_dates(),
+            Self::Seq(block_seq) => block_seq.iter_mut().for_each(Block::coerce_dates),
+            Self::Map(block_map) => block_map.values_mut().for_each(Block::coerce_dates),
+        }
+    }
+}
+
 impl From<SchemaRepr> for Schema {
     fn from(msr: SchemaRepr) -> Self {
         match msr {
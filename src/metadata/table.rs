@@ -0,0 +1,256 @@
+//! Reading of tabular meta files, where each row provides the metadata for one
+//! item file, and the header row provides the metadata keys.
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::metadata::Schema;
+use crate::types::{Block, BlockMap, BlockSeq, DateTime, Decimal, Value};
+
+/// The name of the column that tags each row with the file name of its item.
+pub const FILE_COLUMN: &str = "file";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Csv(#[from] csv::Error),
+    #[error("duplicate column: {0}")]
+    DuplicateColumn(String),
+    #[error("missing file name on line {0}")]
+    MissingFileName(u64),
+    #[error("duplicate file name: {0}")]
+    DuplicateFileName(String),
+}
+
+/// Represents how the cells of a tabular meta file are converted into values.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Hash)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[serde(rename_all = "snake_case")]
+pub enum CellTyping {
+    /// Cells are converted into integers, decimals, and booleans, as long as
+    /// that conversion is unambiguous. All other cells are kept as strings.
+    #[default]
+    Infer,
+
+    /// Like `Infer`, but cells that are dates or datetimes are also converted
+    /// into datetimes. Cells with only a year are still converted into
+    /// integers, unless they are written with leading zeros.
+    InferWithDates,
+
+    /// All cells are kept as strings.
+    Strings,
+}
+
+impl CellTyping {
+    fn convert(&self, cell: &str) -> Value {
+        match self {
+            Self::Strings => Value::String(cell.into()),
+            Self::Infer => infer_cell(cell, false),
+            Self::InferWithDates => infer_cell(cell, true),
+        }
+    }
+}
+
+/// Converts a cell into a typed value, if the cell text is exactly how that
+/// value would be written. This keeps cells such as "01" or "1e3" as strings,
+/// since converting them would lose their original form.
+fn infer_cell(cell: &str, dates: bool) -> Value {
+    match cell {
+        "true" => return Value::Boolean(true),
+        "false" => return Value::Boolean(false),
+        _ => {},
+    }
+
+    if let Ok(i) = cell.parse::<i64>() {
+        if i.to_string() == cell {
+            return Value::Integer(i);
+        }
+    }
+
+    if cell.contains('.') {
+        if let Ok(d) = Decimal::from_str(cell) {
+            if d.to_string() == cell {
+                return Value::Decimal(d);
+            }
+        }
+    }
+
+    if dates {
+        if let Some(dt) = DateTime::coerce(cell) {
+            return Value::DateTime(dt);
+        }
+    }
+
+    Value::String(cell.into())
+}
+
+/// Reads a table with the given field delimiter.
+/// If the table has a file name column, each row is keyed by its file name,
+/// and a `Schema::Map` is produced. Otherwise, a `Schema::Seq` is produced,
+/// with one block per row in order. Empty cells are omitted from the blocks.
+pub fn read_schema<R: Read>(reader: R, delimiter: u8, cell_typing: CellTyping) -> Result<Schema, Error> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(reader);
+
+    let headers = csv_reader.headers()?.clone();
+
+    let mut seen = HashSet::new();
+    for header in headers.iter() {
+        if !seen.insert(header) {
+            return Err(Error::DuplicateColumn(header.into()));
+        }
+    }
+
+    let file_index = headers.iter().position(|h| h == FILE_COLUMN);
+
+    let mut block_seq = BlockSeq::new();
+    let mut block_map = BlockMap::new();
+
+    for record in csv_reader.records() {
+        let record = record?;
+
+        let mut block = Block::new();
+
+        for (index, (header, cell)) in headers.iter().zip(record.iter()).enumerate() {
+            if Some(index) == file_index || cell.is_empty() {
+                continue;
+            }
+
+            block.insert(header.into(), cell_typing.convert(cell));
+        }
+
+        match file_index {
+            None => block_seq.push(block),
+            Some(index) => {
+                let file_name = record.get(index).filter(|f| !f.is_empty()).ok_or_else(|| {
+                    Error::MissingFileName(record.position().map(|p| p.line()).unwrap_or_default())
+                })?;
+
+                if block_map.contains_key(file_name) {
+                    return Err(Error::DuplicateFileName(file_name.into()));
+                }
+
+                block_map.insert(file_name.into(), block);
+            },
+        }
+    }
+
+    Ok(match file_index {
+        None => Schema::Seq(block_seq),
+        Some(_) => Schema::Map(block_map),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indexmap::indexmap;
+    use maplit::btreemap;
+    use rust_decimal_macros::dec;
+    use str_macro::str;
+
+    #[test]
+    fn infer_cell() {
+        let inputs_and_expected = vec![
+            ("27", Value::Integer(27)),
+            ("-27", Value::Integer(-27)),
+            ("3.14", Value::Decimal(dec!(3.14))),
+            ("-0.50", Value::Decimal(dec!(-0.50))),
+            ("true", Value::Boolean(true)),
+            ("false", Value::Boolean(false)),
+            ("01", Value::String(str!("01"))),
+            ("+27", Value::String(str!("+27"))),
+            (".5", Value::String(str!(".5"))),
+            ("1e3", Value::String(str!("1e3"))),
+            ("True", Value::String(str!("True"))),
+            ("1/12", Value::String(str!("1/12"))),
+            ("2019", Value::Integer(2019)),
+            ("2019-08-20", Value::String(str!("2019-08-20"))),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, super::infer_cell(input, false));
+        }
+
+        // Dates are only converted when asked for, and never for years alone.
+        let inputs_and_expected = vec![
+            ("2019", Value::Integer(2019)),
+            ("2019-08-20", Value::DateTime(DateTime::parse("2019-08-20").unwrap())),
+            ("2019-8-20", Value::String(str!("2019-8-20"))),
+            ("Summer 2019", Value::String(str!("Summer 2019"))),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            assert_eq!(expected, super::infer_cell(input, true));
+        }
+    }
+
+    #[test]
+    fn read_schema() {
+        let text = "title,track,rating\nDaylight,1,4.5\nNightfall,2,\n";
+
+        let expected = Schema::Seq(BlockSeq(vec![
+            Block(btreemap![
+                str!("title") => Value::String(str!("Daylight")),
+                str!("track") => Value::Integer(1),
+                str!("rating") => Value::Decimal(dec!(4.5)),
+            ]),
+            Block(btreemap![
+                str!("title") => Value::String(str!("Nightfall")),
+                str!("track") => Value::Integer(2),
+            ]),
+        ]));
+        let produced = super::read_schema(text.as_bytes(), b',', CellTyping::Infer).unwrap();
+        assert_eq!(expected, produced);
+
+        let text = "track\tfile\n01\tb.flac\n02\ta.flac\n";
+
+        let expected = Schema::Map(BlockMap(indexmap![
+            str!("b.flac") => Block(btreemap![str!("track") => Value::String(str!("01"))]),
+            str!("a.flac") => Block(btreemap![str!("track") => Value::String(str!("02"))]),
+        ]));
+        let produced = super::read_schema(text.as_bytes(), b'\t', CellTyping::Infer).unwrap();
+        assert_eq!(expected, produced);
+
+        let text = "title,track\n\"Daylight, Pt. 1\",1\n";
+
+        let expected = Schema::Seq(BlockSeq(vec![
+            Block(btreemap![
+                str!("title") => Value::String(str!("Daylight, Pt. 1")),
+                str!("track") => Value::String(str!("1")),
+            ]),
+        ]));
+        let produced = super::read_schema(text.as_bytes(), b',', CellTyping::Strings).unwrap();
+        assert_eq!(expected, produced);
+
+        let text = "file,title\na.flac,Daylight\n,Nightfall\n";
+        assert!(matches!(
+            super::read_schema(text.as_bytes(), b',', CellTyping::Infer),
+            Err(Error::MissingFileName(3)),
+        ));
+
+        let text = "file,title\na.flac,Daylight\na.flac,Nightfall\n";
+        assert!(matches!(
+            super::read_schema(text.as_bytes(), b',', CellTyping::Infer),
+            Err(Error::DuplicateFileName(_)),
+        ));
+
+        let text = "title,title\nDaylight,Nightfall\n";
+        assert!(matches!(
+            super::read_schema(text.as_bytes(), b',', CellTyping::Infer),
+            Err(Error::DuplicateColumn(_)),
+        ));
+
+        let text = "title,track\nDaylight\n";
+        assert!(matches!(
+            super::read_schema(text.as_bytes(), b',', CellTyping::Infer),
+            Err(Error::Csv(_)),
+        ));
+    }
+}
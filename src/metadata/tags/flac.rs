@@ -0,0 +1,179 @@
+//! Reading of Vorbis comments from FLAC metadata blocks.
+
+use std::convert::TryInto;
+use std::io::Read;
+
+use crate::metadata::tags::{Error, TagCollector};
+
+pub(super) const MAGIC: &[u8; 4] = b"fLaC";
+
+const VORBIS_COMMENT_BLOCK_TYPE: u8 = 4;
+
+/// Maps Vorbis comment field names onto metadata keys, for those fields whose
+/// lowercased name is not already a good key.
+fn map_key(field_name: &str) -> String {
+    let lower = field_name.to_lowercase();
+
+    match lower.as_str() {
+        "albumartist" => String::from("album_artist"),
+        "tracknumber" => String::from("track_number"),
+        "tracktotal" | "totaltracks" => String::from("track_total"),
+        "discnumber" => String::from("disc_number"),
+        "disctotal" | "totaldiscs" => String::from("disc_total"),
+        _ => lower,
+    }
+}
+
+fn take_u32_le(buffer: &mut &[u8]) -> Result<u32, Error> {
+    if buffer.len() < 4 {
+        return Err(Error::Malformed("Vorbis comment"));
+    }
+
+    let (head, tail) = buffer.split_at(4);
+    *buffer = tail;
+
+    // NOTE: This cannot fail, since the slice is exactly 4 bytes long.
+    Ok(u32::from_le_bytes(head.try_into().unwrap()))
+}
+
+fn take_bytes<'a>(buffer: &mut &'a [u8], len: u32) -> Result<&'a [u8], Error> {
+    let len = len as usize;
+
+    if buffer.len() < len {
+        return Err(Error::Malformed("Vorbis comment"));
+    }
+
+    let (head, tail) = buffer.split_at(len);
+    *buffer = tail;
+
+    Ok(head)
+}
+
+fn read_vorbis_comments(mut buffer: &[u8], collector: &mut TagCollector) -> Result<(), Error> {
+    // Skip over the vendor string.
+    let vendor_len = take_u32_le(&mut buffer)?;
+    take_bytes(&mut buffer, vendor_len)?;
+
+    let num_comments = take_u32_le(&mut buffer)?;
+
+    for _ in 0..num_comments {
+        let comment_len = take_u32_le(&mut buffer)?;
+        let comment = String::from_utf8_lossy(take_bytes(&mut buffer, comment_len)?);
+
+        // Comments without a separator are not valid, and are skipped.
+        if let Some((field_name, value)) = comment.split_once('=') {
+            collector.push(map_key(field_name), value.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the metadata blocks of a FLAC stream, positioned just after the magic
+/// bytes, until the Vorbis comment block is found.
+pub(super) fn read_tags<R: Read>(reader: &mut R, collector: &mut TagCollector) -> Result<(), Error> {
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let block_len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+        if block_type == VORBIS_COMMENT_BLOCK_TYPE {
+            let mut buffer = vec![0u8; block_len as usize];
+            reader.read_exact(&mut buffer)?;
+
+            // There is at most one Vorbis comment block in a FLAC stream.
+            return read_vorbis_comments(&buffer, collector);
+        }
+
+        let skipped = std::io::copy(&mut reader.take(block_len.into()), &mut std::io::sink())?;
+
+        if skipped != u64::from(block_len) {
+            return Err(Error::Malformed("FLAC"));
+        }
+
+        if is_last {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::metadata::tags::read_block;
+    use crate::types::{Block, Value};
+
+    /// Builds a minimal FLAC stream with a stream info block and a Vorbis
+    /// comment block containing the given comments.
+    pub(crate) fn sample_flac(comments: &[&str]) -> Vec<u8> {
+        let mut vc = Vec::new();
+        vc.extend_from_slice(&6u32.to_le_bytes());
+        vc.extend_from_slice(b"vendor");
+        vc.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            vc.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            vc.extend_from_slice(comment.as_bytes());
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+
+        // Stream info block, with dummy contents.
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x22]);
+        bytes.extend_from_slice(&[0u8; 0x22]);
+
+        // Vorbis comment block, marked as the last block.
+        let len = (vc.len() as u32).to_be_bytes();
+        bytes.extend_from_slice(&[0x80 | VORBIS_COMMENT_BLOCK_TYPE, len[1], len[2], len[3]]);
+        bytes.extend_from_slice(&vc);
+
+        // Audio frames would follow.
+        bytes.extend_from_slice(&[0xFF, 0xF8, 0x00, 0x00]);
+
+        bytes
+    }
+
+    #[test]
+    fn read_tags() {
+        let bytes = sample_flac(&[
+            "TITLE=Daylight",
+            "ARTIST=Aiguille",
+            "artist=Evil Needle",
+            "TRACKNUMBER=1",
+            "ALBUMARTIST=Aiguille",
+            "INVALID",
+            "EMPTY=",
+        ]);
+
+        let expected = Block(btreemap![
+            str!("title") => Value::from("Daylight"),
+            str!("artist") => Value::Sequence(vec![
+                Value::from("Aiguille"),
+                Value::from("Evil Needle"),
+            ]),
+            str!("track_number") => Value::from("1"),
+            str!("album_artist") => Value::from("Aiguille"),
+            str!("empty") => Value::from(""),
+        ]);
+        assert_eq!(expected, read_block(bytes.as_slice()).unwrap());
+
+        // A FLAC stream without a Vorbis comment block has no tags.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        bytes.extend_from_slice(&[0u8; 0x22]);
+        assert_eq!(Block::new(), read_block(bytes.as_slice()).unwrap());
+
+        // Truncated blocks are reported.
+        let mut bytes = sample_flac(&["TITLE=Daylight"]);
+        bytes.truncate(50);
+        assert!(read_block(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Reading of text frames from ID3v2.3 and ID3v2.4 tags.
+
+use std::convert::TryInto;
+use std::io::Read;
+
+use crate::metadata::tags::{Error, TagCollector};
+
+pub(super) const MAGIC: &[u8; 3] = b"ID3";
+
+const HEADER_FLAG_UNSYNC: u8 = 0x80;
+const HEADER_FLAG_EXT_HEADER: u8 = 0x40;
+
+/// Maps ID3v2 frame IDs onto metadata keys. Unmapped text frames are keyed by
+/// their lowercased frame ID.
+fn map_key(frame_id: &str) -> String {
+    let key = match frame_id {
+        "TIT2" => "title",
+        "TIT3" => "subtitle",
+        "TPE1" => "artist",
+        "TPE2" => "album_artist",
+        "TPE3" => "conductor",
+        "TALB" => "album",
+        "TRCK" => "track_number",
+        "TPOS" => "disc_number",
+        "TCON" => "genre",
+        "TCOM" => "composer",
+        "TEXT" => "lyricist",
+        "TPUB" => "label",
+        "TSRC" => "isrc",
+        "TBPM" => "bpm",
+        "TDRC" | "TYER" => "date",
+        _ => return frame_id.to_lowercase(),
+    };
+
+    String::from(key)
+}
+
+fn decode_syncsafe(bytes: [u8; 4]) -> u32 {
+    bytes.iter().fold(0, |acc, b| (acc << 7) | u32::from(b & 0x7F))
+}
+
+/// Removes the unsynchronisation scheme, which inserts a zero byte after every
+/// `0xFF` byte in the original data.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut prev_was_ff = false;
+
+    for &b in data {
+        if !(prev_was_ff && b == 0x00) {
+            output.push(b);
+        }
+
+        prev_was_ff = b == 0xFF;
+    }
+
+    output
+}
+
+/// Decodes a text frame payload into its individual, null-separated strings.
+fn decode_text(encoding: u8, data: &[u8]) -> Result<Vec<String>, Error> {
+    let mut strings: Vec<String> = match encoding {
+        // ISO-8859-1, which maps directly onto the first 256 code points.
+        0 => data
+            .split(|&b| b == 0)
+            .map(|s| s.iter().map(|&b| char::from(b)).collect())
+            .collect(),
+        // UTF-16, with either a BOM on each string or big endian.
+        1 | 2 => {
+            let chunks = data.chunks_exact(2);
+
+            if !chunks.remainder().is_empty() {
+                return Err(Error::Malformed("ID3v2 text frame"));
+            }
+
+            let units = chunks
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>();
+
+            units
+                .split(|&u| u == 0)
+                .map(|s| match s.split_first() {
+                    Some((0xFEFF, rest)) => String::from_utf16_lossy(rest),
+                    Some((0xFFFE, rest)) => {
+                        let swapped = rest.iter().map(|u| u.swap_bytes()).collect::<Vec<_>>();
+                        String::from_utf16_lossy(&swapped)
+                    }
+                    _ => String::from_utf16_lossy(s),
+                })
+                .collect()
+        }
+        3 => data
+            .split(|&b| b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect(),
+        _ => return Err(Error::Malformed("ID3v2 text frame")),
+    };
+
+    // Drop the empty string produced by a trailing terminator.
+    if strings.len() > 1 && strings.last().map(String::is_empty).unwrap_or(false) {
+        strings.pop();
+    }
+
+    Ok(strings)
+}
+
+fn read_frame(frame_id: &str, data: &[u8], collector: &mut TagCollector) -> Result<(), Error> {
+    // Only text frames are converted into metadata.
+    if !frame_id.starts_with('T') || data.is_empty() {
+        return Ok(());
+    }
+
+    let mut strings = decode_text(data[0], &data[1..])?.into_iter();
+
+    if frame_id == "TXXX" {
+        // User-defined text frames store their key as the first string.
+        if let Some(description) = strings.next() {
+            let key = description.to_lowercase();
+
+            for value in strings {
+                collector.push(key.clone(), value);
+            }
+        }
+    } else {
+        let key = map_key(frame_id);
+
+        for value in strings {
+            collector.push(key.clone(), value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the frames of an ID3v2 tag, positioned just after the magic bytes.
+/// Only versions 2.3 and 2.4 are supported, tags of other versions are skipped
+/// without reading any frames.
+pub(super) fn read_tags<R: Read>(
+    major_version: u8,
+    reader: &mut R,
+    collector: &mut TagCollector,
+) -> Result<(), Error> {
+    if major_version != 3 && major_version != 4 {
+        return Ok(());
+    }
+
+    // Revision byte, flags byte, and syncsafe tag size.
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header)?;
+
+    let flags = header[1];
+    // NOTE: This cannot fail, since the slice is exactly 4 bytes long.
+    let tag_size = decode_syncsafe(header[2..6].try_into().unwrap());
+
+    let mut data = vec![0u8; tag_size as usize];
+    reader.read_exact(&mut data)?;
+
+    // Version 2.3 applies unsynchronisation to the whole tag, while version 2.4
+    // applies it per frame.
+    if major_version == 3 && flags & HEADER_FLAG_UNSYNC != 0 {
+        data = remove_unsync(&data);
+    }
+
+    let mut pos = 0;
+
+    if flags & HEADER_FLAG_EXT_HEADER != 0 {
+        let size_bytes: [u8; 4] = data
+            .get(0..4)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(Error::Malformed("ID3v2"))?;
+
+        // The size in version 2.3 excludes the size field itself.
+        pos = match major_version {
+            3 => u32::from_be_bytes(size_bytes) as usize + 4,
+            _ => decode_syncsafe(size_bytes) as usize,
+        };
+    }
+
+    while pos + 10 <= data.len() {
+        let frame_header = &data[pos..pos + 10];
+
+        // Padding has been reached, there are no more frames.
+        if frame_header[0] == 0 {
+            break;
+        }
+
+        let frame_id = String::from_utf8_lossy(&frame_header[0..4]).into_owned();
+        // NOTE: This cannot fail, since the slice is exactly 4 bytes long.
+        let size_bytes: [u8; 4] = frame_header[4..8].try_into().unwrap();
+        let frame_size = match major_version {
+            3 => u32::from_be_bytes(size_bytes),
+            _ => decode_syncsafe(size_bytes),
+        } as usize;
+        let format_flags = frame_header[9];
+
+        let start = pos + 10;
+        let end = start + frame_size;
+        let mut frame_data = data.get(start..end).ok_or(Error::Malformed("ID3v2 frame"))?;
+        pos = end;
+
+        let unsynced;
+
+        match major_version {
+            3 => {
+                // Compressed and encrypted frames are skipped.
+                if format_flags & 0xC0 != 0 {
+                    continue;
+                }
+
+                // Skip the group identifier.
+                if format_flags & 0x20 != 0 {
+                    frame_data = frame_data.get(1..).unwrap_or_default();
+                }
+            }
+            _ => {
+                // Compressed and encrypted frames are skipped.
+                if format_flags & 0x0C != 0 {
+                    continue;
+                }
+
+                // Skip the group identifier.
+                if format_flags & 0x40 != 0 {
+                    frame_data = frame_data.get(1..).unwrap_or_default();
+                }
+
+                if format_flags & 0x02 != 0 || flags & HEADER_FLAG_UNSYNC != 0 {
+                    unsynced = remove_unsync(frame_data);
+                    frame_data = &unsynced;
+                }
+
+                // Skip the data length indicator.
+                if format_flags & 0x01 != 0 {
+                    frame_data = frame_data.get(4..).unwrap_or_default();
+                }
+            }
+        }
+
+        read_frame(&frame_id, frame_data, collector)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::metadata::tags::read_block;
+    use crate::types::{Block, Value};
+
+    fn encode_syncsafe(n: u32) -> [u8; 4] {
+        [
+            ((n >> 21) & 0x7F) as u8,
+            ((n >> 14) & 0x7F) as u8,
+            ((n >> 7) & 0x7F) as u8,
+            (n & 0x7F) as u8,
+        ]
+    }
+
+    /// Builds a minimal MP3 stream with an ID3v2 tag of the given version,
+    /// containing the given frames.
+    pub(crate) fn sample_mp3(major_version: u8, frames: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (frame_id, data) in frames {
+            let size = match major_version {
+                3 => (data.len() as u32).to_be_bytes(),
+                _ => encode_syncsafe(data.len() as u32),
+            };
+
+            body.extend_from_slice(frame_id.as_bytes());
+            body.extend_from_slice(&size);
+            body.extend_from_slice(&[0x00, 0x00]);
+            body.extend_from_slice(data);
+        }
+
+        // Padding.
+        body.extend_from_slice(&[0u8; 16]);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&[major_version, 0x00, 0x00]);
+        bytes.extend_from_slice(&encode_syncsafe(body.len() as u32));
+        bytes.extend_from_slice(&body);
+
+        // Audio frames would follow.
+        bytes.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+
+        bytes
+    }
+
+    pub(crate) fn utf8_frame(text: &str) -> Vec<u8> {
+        let mut data = vec![3];
+        data.extend_from_slice(text.as_bytes());
+        data
+    }
+
+    fn utf16_frame(text: &str) -> Vec<u8> {
+        let mut data = vec![1, 0xFF, 0xFE];
+        for unit in text.encode_utf16() {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        data.extend_from_slice(&[0x00, 0x00]);
+        data
+    }
+
+    #[test]
+    fn remove_unsync() {
+        assert_eq!(super::remove_unsync(&[0xFF, 0x00, 0xE0]), vec![0xFF, 0xE0]);
+        assert_eq!(super::remove_unsync(&[0x00, 0xFF, 0x01]), vec![0x00, 0xFF, 0x01]);
+    }
+
+    #[test]
+    fn read_tags() {
+        let bytes = sample_mp3(
+            4,
+            &[
+                ("TIT2", utf8_frame("Daylight")),
+                ("TPE1", utf8_frame("Aiguille\0Evil Needle")),
+                ("TRCK", vec![0, b'0', b'1', 0]),
+                ("TXXX", utf8_frame("CATALOG\0ABC-123")),
+                ("TMOO", utf16_frame("Calm")),
+                ("APIC", vec![0, 1, 2, 3]),
+            ],
+        );
+
+        let expected = Block(btreemap![
+            str!("title") => Value::from("Daylight"),
+            str!("artist") => Value::Sequence(vec![
+                Value::from("Aiguille"),
+                Value::from("Evil Needle"),
+            ]),
+            str!("track_number") => Value::from("01"),
+            str!("catalog") => Value::from("ABC-123"),
+            str!("tmoo") => Value::from("Calm"),
+        ]);
+        assert_eq!(expected, read_block(bytes.as_slice()).unwrap());
+
+        let bytes = sample_mp3(3, &[("TYER", vec![0, b'2', b'0', b'2', b'0'])]);
+        let expected = Block(btreemap![str!("date") => Value::from("2020")]);
+        assert_eq!(expected, read_block(bytes.as_slice()).unwrap());
+
+        let bytes = sample_mp3(2, &[]);
+        assert_eq!(Block::new(), read_block(bytes.as_slice()).unwrap());
+
+        // Frames that overrun the tag are reported.
+        let mut bytes = sample_mp3(4, &[("TIT2", utf8_frame("Daylight"))]);
+        bytes[17] = 0x7F;
+        assert!(matches!(read_block(bytes.as_slice()), Err(Error::Malformed(_))));
+    }
+}
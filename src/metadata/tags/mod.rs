@@ -0,0 +1,92 @@
+//! Reading of metadata embedded in item files as audio tags.
+//! Only pure-Rust parsing of the tag containers is done; the audio data itself
+//! is never read.
+
+mod flac;
+mod id3;
+
+#[cfg(test)]
+pub(crate) use self::flac::tests::sample_flac;
+#[cfg(test)]
+pub(crate) use self::id3::tests::{sample_mp3, utf8_frame};
+
+use std::io::{Error as IoError, Read};
+
+use thiserror::Error;
+
+use crate::types::{Block, Value};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] IoError),
+    #[error("malformed {0} tag data")]
+    Malformed(&'static str),
+}
+
+/// File extensions of item files that may contain embedded tags.
+pub(crate) const TAGGED_EXTS: &[&str] = &["flac", "mp3"];
+
+/// Accumulates tag values for keys in encounter order, collapsing keys with a
+/// single value into plain strings.
+#[derive(Default)]
+struct TagCollector(Vec<(String, Vec<String>)>);
+
+impl TagCollector {
+    fn push(&mut self, key: String, value: String) {
+        match self.0.iter_mut().find(|(k, _)| k == &key) {
+            Some((_, values)) => values.push(value),
+            None => self.0.push((key, vec![value])),
+        }
+    }
+
+    fn into_block(self) -> Block {
+        self.0
+            .into_iter()
+            .map(|(key, mut values)| {
+                let value = if values.len() == 1 {
+                    Value::String(values.remove(0))
+                } else {
+                    Value::Sequence(values.into_iter().map(Value::String).collect())
+                };
+
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+/// Reads the embedded tags from the start of an item file, and converts them
+/// into a metadata block. The kind of tag container is detected from its
+/// leading magic bytes: FLAC files provide Vorbis comments, while MP3 files
+/// provide ID3v2 frames. Files without a recognized tag container, such as MP3
+/// files that start directly with audio frames, have an empty block.
+pub fn read_block<R: Read>(mut reader: R) -> Result<Block, Error> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    let mut collector = TagCollector::default();
+
+    if &magic == flac::MAGIC {
+        flac::read_tags(&mut reader, &mut collector)?;
+    } else if &magic[..3] == id3::MAGIC {
+        id3::read_tags(magic[3], &mut reader, &mut collector)?;
+    }
+
+    Ok(collector.into_block())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_block() {
+        assert_eq!(Block::new(), super::read_block(&b"RIFF0000"[..]).unwrap());
+
+        // An MP3 file without an ID3v2 tag starts with an audio frame sync.
+        assert_eq!(Block::new(), super::read_block(&[0xFF, 0xFB, 0x90, 0x64, 0x00][..]).unwrap());
+
+        assert!(matches!(super::read_block(&b"fL"[..]), Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,95 @@
+//! Translation of directory paths between a tree of item files and a parallel
+//! tree of meta files.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::sources::SourceError;
+
+/// Maps directories in a tree of item files (the "media root") onto the same
+/// relative directories in a separate tree of meta files (the "meta root").
+/// This allows meta files to be stored away from the item files they provide
+/// metadata for, e.g. when the item files are on a read-only mount.
+#[derive(Debug, Clone, Deserialize, Hash)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+#[serde(deny_unknown_fields)]
+pub struct Mirror {
+    media_root: PathBuf,
+    meta_root: PathBuf,
+}
+
+impl Mirror {
+    pub fn new(media_root: PathBuf, meta_root: PathBuf) -> Self {
+        Self { media_root, meta_root, }
+    }
+
+    pub fn media_root(&self) -> &Path {
+        &self.media_root
+    }
+
+    pub fn meta_root(&self) -> &Path {
+        &self.meta_root
+    }
+
+    /// Translates a path inside the media root into the corresponding path
+    /// inside the meta root. This is a purely lexical operation, and does not
+    /// access the filesystem.
+    pub fn to_meta(&self, media_path: &Path) -> Result<PathBuf, SourceError> {
+        let rel_path = media_path
+            .strip_prefix(&self.media_root)
+            .map_err(|_| SourceError::OutsideMediaRoot(media_path.into()))?;
+
+        Ok(self.meta_root.join(rel_path))
+    }
+
+    /// Translates a path inside the meta root into the corresponding path
+    /// inside the media root. This is a purely lexical operation, and does not
+    /// access the filesystem.
+    pub fn to_media(&self, meta_path: &Path) -> Result<PathBuf, SourceError> {
+        let rel_path = meta_path
+            .strip_prefix(&self.meta_root)
+            .map_err(|_| SourceError::OutsideMetaRoot(meta_path.into()))?;
+
+        Ok(self.media_root.join(rel_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_meta() {
+        let mirror = Mirror::new(PathBuf::from("/media"), PathBuf::from("/meta"));
+
+        assert_eq!(
+            mirror.to_meta(Path::new("/media/Artist/Album")).unwrap(),
+            Path::new("/meta/Artist/Album"),
+        );
+        assert_eq!(mirror.to_meta(Path::new("/media")).unwrap(), Path::new("/meta"));
+        assert!(matches!(
+            mirror.to_meta(Path::new("/")),
+            Err(SourceError::OutsideMediaRoot(_)),
+        ));
+        assert!(matches!(
+            mirror.to_meta(Path::new("/mediate/Artist")),
+            Err(SourceError::OutsideMediaRoot(_)),
+        ));
+    }
+
+    #[test]
+    fn to_media() {
+        let mirror = Mirror::new(PathBuf::from("/media"), PathBuf::from("/meta"));
+
+        assert_eq!(
+            mirror.to_media(Path::new("/meta/Artist/Album")).unwrap(),
+            Path::new("/media/Artist/Album"),
+        );
+        assert_eq!(mirror.to_media(Path::new("/meta")).unwrap(), Path::new("/media"));
+        assert!(matches!(
+            mirror.to_media(Path::new("/media/Artist")),
+            Err(SourceError::OutsideMetaRoot(_)),
+        ));
+    }
+}
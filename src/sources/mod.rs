@@ -1,5 +1,7 @@
+pub mod mirror;
 pub mod source;
 pub mod sourcer;
 
+pub use self::mirror::Mirror;
 pub use self::source::*;
 pub use self::sourcer::*;
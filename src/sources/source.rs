@@ -1,12 +1,19 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::vec::IntoIter as VecIntoIter;
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::config::{Format, FormatError, Selection};
 use crate::metadata::Schema;
+use crate::metadata::table::CellTyping;
+use crate::metadata::tags::TAGGED_EXTS;
+use crate::sources::Mirror;
 use crate::util::{InvalidNameKind, Util};
 
 #[derive(Debug, Error)]
@@ -17,6 +24,8 @@ pub enum CreateError {
     MissingExt(String),
     #[error("unknown extension: {0}")]
     UnknownExt(String),
+    #[error("missing placeholder in sidecar template: {0}")]
+    MissingPlaceholder(String),
 }
 
 #[derive(Debug, Error)]
@@ -33,8 +42,22 @@ pub enum SourceError {
 
     #[error("item path does not have a parent: {}", .0.display())]
     NoItemParentDir(PathBuf),
+    #[error("item path does not have a file name: {}", .0.display())]
+    NoItemFileName(PathBuf),
+    #[error("no index meta file found for item path: {}", .0.display())]
+    NoIndex(PathBuf),
     #[error("meta path does not have a parent: {}", .0.display())]
     NoMetaParentDir(PathBuf),
+    #[error("meta path is not inside of a meta directory: {}", .0.display())]
+    NotInMetaDir(PathBuf),
+    #[error("path is not inside of the media root: {}", .0.display())]
+    OutsideMediaRoot(PathBuf),
+    #[error("path is not inside of the meta root: {}", .0.display())]
+    OutsideMetaRoot(PathBuf),
+    #[error("item path cannot contain embedded tags: {}", .0.display())]
+    NoEmbeddedTags(PathBuf),
+    #[error("sidecar meta path is shared by multiple item paths: {}", .0.display())]
+    SidecarCollision(PathBuf),
 
     #[error("unable to read item directory: {0}")]
     IterDir(#[source] IoError),
@@ -49,7 +72,11 @@ impl SourceError {
                 IoErrorKind::NotFound => false,
                 _ => true,
             },
-            Self::NotADir(..) | Self::NoItemParentDir(..) => false,
+            Self::NotADir(..)
+            | Self::NoItemParentDir(..)
+            | Self::OutsideMediaRoot(..)
+            | Self::NoIndex(..)
+            | Self::NoEmbeddedTags(..) => false,
             _ => true,
         }
     }
@@ -57,8 +84,8 @@ impl SourceError {
 
 /// Represents a method of finding the location of a meta file given an item
 /// file path.
-#[derive(Debug, Clone, Copy)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Anchor {
     /// The meta file is located in the same directory as the item file path.
     External,
@@ -66,16 +93,87 @@ pub enum Anchor {
     /// The meta file is located inside the item file path.
     /// Implies that the the item file path is a directory.
     Internal,
+
+    /// The meta file is located in the nearest ancestor directory of the item
+    /// file path that contains it, and provides metadata for any item files
+    /// under that directory, keyed by their paths relative to that directory.
+    Index,
+
+    /// The meta file is located in the same directory as the item file path,
+    /// and its name is derived from the name of the item file path.
+    /// The source name is used as a template, where `{name}` is replaced with
+    /// the item file name and `{stem}` is replaced with the item file stem.
+    Sidecar,
+
+    /// The meta file is the item file path itself, with metadata read from the
+    /// audio tags embedded in it.
+    /// Implies that the item file path is a file of a supported audio format.
+    Embedded,
+}
+
+const NAME_PLACEHOLDER: &str = "{name}";
+const STEM_PLACEHOLDER: &str = "{stem}";
+
+/// Renders a sidecar template using the file name of an item path.
+pub(crate) fn render_sidecar_name(template: &str, item_name: &OsStr) -> OsString {
+    let item_stem = Path::new(item_name).file_stem().unwrap_or(item_name);
+
+    let mut rendered = OsString::new();
+    let mut rest = template;
+
+    // Find the earliest placeholder in the remaining template text, and
+    // replace it with the corresponding part of the item file name.
+    loop {
+        let next = [(NAME_PLACEHOLDER, item_name), (STEM_PLACEHOLDER, item_stem)]
+            .iter()
+            .filter_map(|(ph, rep)| rest.find(ph).map(|i| (i, *ph, *rep)))
+            .min_by_key(|(i, ..)| *i);
+
+        match next {
+            Some((i, ph, rep)) => {
+                rendered.push(&rest[..i]);
+                rendered.push(rep);
+                rest = &rest[i + ph.len()..];
+            },
+            None => {
+                rendered.push(rest);
+                break;
+            },
+        }
+    }
+
+    rendered
+}
+
+/// Represents how the metadata inside of a meta file is laid out.
+#[derive(Debug, Clone, Copy, Default, Hash)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum Layout {
+    /// The meta file only contains the metadata for the item files covered by
+    /// its anchor.
+    #[default]
+    Plain,
+
+    /// The meta file contains both the metadata for its containing directory
+    /// (under the `album` key) and the metadata for the item files in that
+    /// directory (under the `tracks` key). The anchor decides which of the two
+    /// parts is used.
+    Combined,
 }
 
 /// Defines a meta file source, consisting of an anchor (the target directory
 /// to look in) and a file name (the meta file name in that target directory).
-#[derive(Debug)]
+#[derive(Debug, Hash)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct Source {
     pub(crate) name: String,
     pub(crate) anchor: Anchor,
     pub(crate) format: Format,
+    pub(crate) layout: Layout,
+    pub(crate) meta_dir: Option<String>,
+    pub(crate) mirror: Option<Mirror>,
+    pub(crate) cell_typing: CellTyping,
+    pub(crate) coerce_dates: bool,
 }
 
 impl Source {
@@ -96,12 +194,154 @@ impl Source {
             Err(_) => { return Err(CreateError::UnknownExt(name)); },
         };
 
-        Ok(Self { name, anchor, format, })
+        if let Anchor::Sidecar = anchor {
+            if !name.contains(NAME_PLACEHOLDER) && !name.contains(STEM_PLACEHOLDER) {
+                return Err(CreateError::MissingPlaceholder(name));
+            }
+        }
+
+        Ok(Self {
+            name,
+            anchor,
+            format,
+            layout: Layout::default(),
+            meta_dir: None,
+            mirror: None,
+            cell_typing: CellTyping::default(),
+            coerce_dates: false,
+        })
+    }
+
+    /// Creates a source that reads the tags embedded in item files.
+    /// Meta directories and mirrors do not apply to this source, since the
+    /// item files act as their own meta files.
+    pub fn embedded() -> Self {
+        Self {
+            name: String::new(),
+            anchor: Anchor::Embedded,
+            format: Format::Tags,
+            layout: Layout::default(),
+            meta_dir: None,
+            mirror: None,
+            cell_typing: CellTyping::default(),
+            coerce_dates: false,
+        }
+    }
+
+    /// Sets the name of a subdirectory to look for meta files in. Instead of
+    /// being located directly in the directory given by the anchor, meta files
+    /// will be located in this subdirectory of that directory.
+    pub fn with_meta_dir(mut self, meta_dir: String) -> Result<Self, CreateError> {
+        match Util::validate_item_name(&meta_dir) {
+            Ok(()) => {},
+            Err(kind) => return Err(CreateError::InvalidName(kind, meta_dir)),
+        };
+
+        self.meta_dir = Some(meta_dir);
+        Ok(self)
+    }
+
+    /// Sets a mirror to translate directories with. Instead of being located
+    /// in the directory given by the anchor, meta files will be located in the
+    /// corresponding directory inside the meta root of the mirror.
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// Sets how the cells of tabular meta files read by this source are
+    /// converted into values.
+    pub fn with_cell_typing(mut self, cell_typing: CellTyping) -> Self {
+        self.cell_typing = cell_typing;
+        self
+    }
+
+    /// Sets whether strings in the meta files read by this source that are
+    /// dates or datetimes, such as `2019` or `2019-08-20`, are converted into
+    /// datetimes. Otherwise, only native TOML datetimes are read as datetimes.
+    pub fn with_coerce_dates(mut self, coerce_dates: bool) -> Self {
+        self.coerce_dates = coerce_dates;
+        self
+    }
+
+    /// Sets the layout of the meta files read by this source.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Returns a glob pattern that matches the names of the meta files for
+    /// this source, if they are distinct from the item files and do not depend
+    /// on the names of the item files.
+    pub(crate) fn name_pattern(&self) -> Option<&str> {
+        match self.anchor {
+            Anchor::External | Anchor::Internal | Anchor::Index => Some(&self.name),
+            Anchor::Sidecar | Anchor::Embedded => None,
+        }
+    }
+
+    /// Returns the template used to derive the names of the meta files for
+    /// this source from the names of their item files, if this is a sidecar
+    /// source.
+    pub(crate) fn sidecar_template(&self) -> Option<&str> {
+        match self.anchor {
+            Anchor::Sidecar => Some(&self.name),
+            _ => None,
+        }
+    }
+
+    /// Given the directory designated by the anchor, returns the directory that
+    /// contains the meta files for this source.
+    pub(crate) fn meta_parent_dir<'p>(&self, anchor_dir: &'p Path) -> Result<Cow<'p, Path>, SourceError> {
+        let mirrored_dir = match &self.mirror {
+            None => Cow::Borrowed(anchor_dir),
+            Some(mirror) => Cow::Owned(mirror.to_meta(anchor_dir)?),
+        };
+
+        Ok(match &self.meta_dir {
+            None => mirrored_dir,
+            Some(meta_dir) => Cow::Owned(mirrored_dir.join(meta_dir)),
+        })
+    }
+
+    /// The inverse of `meta_parent_dir`, returns the directory designated by
+    /// the anchor, given the directory that contains a meta file.
+    fn anchor_dir<'p>(&self, meta_parent_dir: &'p Path) -> Result<Cow<'p, Path>, SourceError> {
+        let mirrored_dir = match &self.meta_dir {
+            None => meta_parent_dir,
+            Some(meta_dir) => {
+                if meta_parent_dir.file_name() != Some(meta_dir.as_ref()) {
+                    return Err(SourceError::NotInMetaDir(meta_parent_dir.into()));
+                }
+
+                meta_parent_dir
+                    .parent()
+                    .ok_or_else(|| SourceError::NoMetaParentDir(meta_parent_dir.into()))?
+            },
+        };
+
+        Ok(match &self.mirror {
+            None => Cow::Borrowed(mirrored_dir),
+            Some(mirror) => Cow::Owned(mirror.to_media(mirrored_dir)?),
+        })
     }
 
     /// Given a concrete item file path, returns the meta file path that would
     /// provide metadata for that item path, according to the source rules.
+    /// Index meta files are looked for in any ancestor directory of the item
+    /// path, use `meta_path_within` to limit this to a root directory.
     pub fn meta_path(&self, item_path: &Path) -> Result<PathBuf, SourceError> {
+        self.find_meta_path(item_path, None)
+    }
+
+    /// Similar to `meta_path`, but index meta files are only looked for in
+    /// directories inside of a root directory, such as the root directory of
+    /// a library.
+    pub fn meta_path_within(&self, item_path: &Path, root_dir: &Path) -> Result<PathBuf, SourceError> {
+        self.find_meta_path(item_path, Some(root_dir))
+    }
+
+    fn find_meta_path(&self, item_path: &Path, root_dir: Option<&Path>) -> Result<PathBuf, SourceError> {
         // Get filesystem stat for item path.
         // This step is always done, even if the file/directory status does not
         // need to be checked, as it provides useful error information about
@@ -109,12 +349,24 @@ impl Source {
         let item_fs_stat = std::fs::metadata(&item_path)
             .map_err(|io| SourceError::ItemAccess(item_path.into(), io))?;
 
-        // Create the path of the directory that should contain the meta file.
-        let meta_path_parent_dir = match self.anchor {
+        match self.anchor {
             // The meta parent dir is the same as the item's parent dir.
-            Anchor::External => item_path
-                .parent()
-                .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))?,
+            Anchor::External => {
+                let meta_parent_dir = self.meta_parent_dir(item_parent_dir(item_path)?)?;
+                existing_meta_file(meta_parent_dir.join(&self.name))
+            },
+
+            // The meta parent dir is the same as the item's parent dir, and
+            // the meta file name is rendered from the item file name.
+            Anchor::Sidecar => {
+                let meta_parent_dir = self.meta_parent_dir(item_parent_dir(item_path)?)?;
+
+                let item_name = item_path
+                    .file_name()
+                    .ok_or_else(|| SourceError::NoItemFileName(item_path.into()))?;
+
+                existing_meta_file(meta_parent_dir.join(render_sidecar_name(&self.name, item_name)))
+            },
 
             // The meta parent dir is the item path itself, as long as it is
             // actually a dir.
@@ -123,33 +375,76 @@ impl Source {
                     return Err(SourceError::NotADir(item_path.into()));
                 }
 
-                item_path
-            }
-        };
+                let meta_parent_dir = self.meta_parent_dir(item_path)?;
+                existing_meta_file(meta_parent_dir.join(&self.name))
+            },
 
-        // Create the target meta file path.
-        let meta_path = meta_path_parent_dir.join(&self.name);
+            Anchor::Index => self.index_meta_path(item_path, root_dir),
+
+            Anchor::Embedded => {
+                // The item path is its own meta path, as long as it is a file
+                // that can contain embedded tags.
+                let has_tags = item_fs_stat.is_file()
+                    && item_path
+                        .extension()
+                        .and_then(OsStr::to_str)
+                        .map(|ext| TAGGED_EXTS.iter().any(|t| t.eq_ignore_ascii_case(ext)))
+                        .unwrap_or(false);
+
+                if has_tags {
+                    Ok(item_path.into())
+                } else {
+                    Err(SourceError::NoEmbeddedTags(item_path.into()))
+                }
+            },
+        }
+    }
 
-        // Get filesystem stat for meta path.
-        // NOTE: Using `match` in order to avoid a clone in the error case.
-        let meta_fs_stat = match std::fs::metadata(&meta_path) {
-            Ok(o) => o,
-            Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
-        };
+    /// Finds the index meta file in the nearest ancestor directory of an item
+    /// file path, stopping at the root directory if one is given.
+    fn index_meta_path(&self, item_path: &Path, root_dir: Option<&Path>) -> Result<PathBuf, SourceError> {
+        let anchor_dirs = item_path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| root_dir.is_none_or(|root_dir| dir.starts_with(root_dir)));
+
+        for anchor_dir in anchor_dirs {
+            let meta_path = match self.meta_parent_dir(anchor_dir) {
+                Ok(meta_parent_dir) => meta_parent_dir.join(&self.name),
+
+                // Stop once the ancestors leave the media root.
+                Err(SourceError::OutsideMediaRoot(..)) => break,
+                Err(err) => return Err(err),
+            };
 
-        // Ensure that the meta path is indeed a file.
-        if !meta_fs_stat.is_file() {
-            // Found a directory with the meta file name.
-            Err(SourceError::NotAFile(meta_path))
-        } else {
-            Ok(meta_path)
+            match std::fs::metadata(&meta_path) {
+                Ok(meta_fs_stat) if meta_fs_stat.is_file() => return Ok(meta_path),
+                Ok(_) => continue,
+                Err(io_err) if io_err.kind() == IoErrorKind::NotFound => continue,
+                Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
+            }
         }
+
+        Err(SourceError::NoIndex(item_path.into()))
+    }
+
+    /// Returns the directory designated by the anchor for a meta file.
+    /// For index meta files, this is the directory that the keys of the meta
+    /// file are relative to.
+    pub fn anchor_dir_path<'a>(&self, meta_path: &'a Path) -> Result<Cow<'a, Path>, SourceError> {
+        let meta_parent_dir_path = meta_path
+            .parent()
+            .ok_or_else(|| SourceError::NoMetaParentDir(meta_path.into()))?;
+
+        self.anchor_dir(meta_parent_dir_path)
     }
 
     /// Provides a listing of the item file paths that this meta target
     /// could/should provide metadata for. Note that this does NOT parse meta
     /// files, it only uses file system locations and presence. In addition, no
     /// filtering or sorting of the returned item paths is performed.
+    /// Since the item file paths of index meta files are only known once they
+    /// are parsed, no item file paths are returned for `Anchor::Index`.
     pub fn item_paths<'a>(&self, meta_path: &'a Path) -> Result<ItemPaths<'a>, SourceError> {
         let meta_fs_stat = std::fs::metadata(&meta_path)
             .map_err(|io| SourceError::MetaAccess(meta_path.into(), io))?;
@@ -158,28 +453,63 @@ impl Source {
             return Err(SourceError::NotAFile(meta_path.into()));
         }
 
-        // Get the parent directory of the meta file.
-        if let Some(meta_parent_dir_path) = meta_path.parent() {
-            let ipi = match self.anchor {
-                Anchor::External => {
-                    // Return all children of the parent directory of this meta file.
-                    let read_dir =
-                        std::fs::read_dir(&meta_parent_dir_path).map_err(SourceError::IterDir)?;
+        match self.anchor {
+            // A file with embedded tags only provides metadata for itself.
+            Anchor::Embedded => Ok(ItemPaths::single(Some(Cow::Borrowed(meta_path)))),
 
-                    ItemPathsInner::ReadDir(read_dir)
-                }
-                Anchor::Internal => {
-                    // This is just the passed-in path, just push it on unchanged.
-                    ItemPathsInner::Single(Some(meta_parent_dir_path))
+            // Return all children of the anchor directory.
+            Anchor::External => {
+                let anchor_dir = self.anchor_dir_path(meta_path)?;
+                Ok(ItemPaths::from_listing(read_listing(&anchor_dir)?))
+            },
+
+            // This is just the anchor directory, push it on unchanged.
+            Anchor::Internal => Ok(ItemPaths::single(Some(self.anchor_dir_path(meta_path)?))),
+
+            // The keys of the index are relative to the anchor directory, which
+            // still needs to be valid.
+            Anchor::Index => {
+                self.anchor_dir_path(meta_path)?;
+                Ok(ItemPaths::single(None))
+            },
+
+            // Return the child of the anchor directory whose sidecar name
+            // matches this meta file.
+            Anchor::Sidecar => {
+                let anchor_dir = self.anchor_dir_path(meta_path)?;
+                let listing = read_listing(&anchor_dir)?;
+
+                // This is never empty, since the meta file was found above.
+                let meta_name = meta_path.file_name().unwrap_or_default();
+
+                let mut matching = listing.iter().filter_map(|res| match res {
+                    Ok(path) => {
+                        // The meta file itself is never one of its items.
+                        let item_name = path.file_name()?;
+
+                        if item_name != meta_name
+                            && render_sidecar_name(&self.name, item_name) == meta_name
+                        {
+                            Some(path.clone())
+                        } else {
+                            None
+                        }
+                    },
+                    Err(_) => None,
+                });
+
+                let item_path = matching.next();
+
+                // Sidecar meta files provide metadata for exactly one item
+                // file, so a template that renders the same name for multiple
+                // item files is ambiguous.
+                if matching.next().is_some() {
+                    return Err(SourceError::SidecarCollision(meta_path.into()));
                 }
-            };
 
-            Ok(ItemPaths(ipi))
-        } else {
-            // This should never happen, since at this point we have a real meta
-            // file and thus, a real parent directory for that file, but making
-            // an error for it anyways.
-            Err(SourceError::NoMetaParentDir(meta_path.into()))
+                let sibling_names = listing_names(&listing);
+                Ok(ItemPaths(ItemPathsInner::Single(item_path.map(Cow::Owned)), sibling_names))
+            },
         }
     }
 
@@ -190,17 +520,77 @@ impl Source {
         meta_path: &'a Path,
         selection: &'a Selection,
     ) -> Result<SelectedItemPaths<'a>, SourceError> {
-        Ok(SelectedItemPaths(self.item_paths(meta_path)?, selection))
+        let item_paths = self.item_paths(meta_path)?;
+
+        // Sidecar meta files are found using the same directory listing as the
+        // item paths, so the directory is only read once.
+        let sidecar_names = selection.sidecar_names(item_paths.1.iter().map(OsString::as_os_str));
+
+        Ok(SelectedItemPaths(item_paths, sidecar_names, selection))
     }
 
     pub fn read_schema(&self, meta_path: &Path) -> Result<Schema, FormatError> {
-        self.format.read_schema_path(meta_path, &self.anchor.into())
+        let arity = self.anchor.into();
+
+        let mut schema = match self.layout {
+            Layout::Plain => self.format.read_schema_path_with(meta_path, &arity, self.cell_typing)?,
+            Layout::Combined => {
+                self.format
+                    .read_metadata_path(meta_path)
+                    .map(|metadata| metadata.into_schema(&arity))?
+            },
+        };
+
+        if self.coerce_dates {
+            schema.coerce_dates();
+        }
+
+        Ok(schema)
+    }
+}
+
+/// Returns the parent directory of an item file path.
+fn item_parent_dir(item_path: &Path) -> Result<&Path, SourceError> {
+    item_path
+        .parent()
+        .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))
+}
+
+/// Ensures that a meta file path exists and is a file.
+fn existing_meta_file(meta_path: PathBuf) -> Result<PathBuf, SourceError> {
+    // NOTE: Using `match` in order to avoid a clone in the error case.
+    let meta_fs_stat = match std::fs::metadata(&meta_path) {
+        Ok(o) => o,
+        Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
+    };
+
+    if !meta_fs_stat.is_file() {
+        // Found a directory with the meta file name.
+        Err(SourceError::NotAFile(meta_path))
+    } else {
+        Ok(meta_path)
     }
 }
 
+/// Reads the paths of the entries in a directory.
+fn read_listing(dir_path: &Path) -> Result<Vec<IoResult<PathBuf>>, SourceError> {
+    let read_dir = std::fs::read_dir(dir_path).map_err(SourceError::IterDir)?;
+
+    Ok(read_dir.map(|res| res.map(|e| e.path())).collect())
+}
+
+/// Returns the file names of the entries in a directory listing.
+fn listing_names(listing: &[IoResult<PathBuf>]) -> Vec<OsString> {
+    listing
+        .iter()
+        .filter_map(|res| res.as_ref().ok()?.file_name())
+        .map(OsStr::to_owned)
+        .collect()
+}
+
 enum ItemPathsInner<'a> {
-    ReadDir(std::fs::ReadDir),
-    Single(Option<&'a Path>),
+    Listing(VecIntoIter<IoResult<PathBuf>>),
+    Single(Option<Cow<'a, Path>>),
 }
 
 impl<'a> Iterator for ItemPathsInner<'a> {
@@ -208,13 +598,26 @@ impl<'a> Iterator for ItemPathsInner<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            Self::ReadDir(rd) => Some(rd.next()?.map(|e| Cow::Owned(e.path()))),
-            Self::Single(o) => o.take().map(|p| Ok(Cow::Borrowed(p))),
+            Self::Listing(it) => Some(it.next()?.map(Cow::Owned)),
+            Self::Single(o) => o.take().map(Ok),
         }
     }
 }
 
-pub struct ItemPaths<'a>(ItemPathsInner<'a>);
+/// The item paths for a meta file, along with the names of the other entries
+/// in their directory.
+pub struct ItemPaths<'a>(ItemPathsInner<'a>, Vec<OsString>);
+
+impl<'a> ItemPaths<'a> {
+    fn from_listing(listing: Vec<IoResult<PathBuf>>) -> Self {
+        let sibling_names = listing_names(&listing);
+        Self(ItemPathsInner::Listing(listing.into_iter()), sibling_names)
+    }
+
+    fn single(item_path: Option<Cow<'a, Path>>) -> Self {
+        Self(ItemPathsInner::Single(item_path), Vec::new())
+    }
+}
 
 impl<'a> Iterator for ItemPaths<'a> {
     type Item = IoResult<Cow<'a, Path>>;
@@ -224,7 +627,7 @@ impl<'a> Iterator for ItemPaths<'a> {
     }
 }
 
-pub struct SelectedItemPaths<'a>(ItemPaths<'a>, &'a Selection);
+pub struct SelectedItemPaths<'a>(ItemPaths<'a>, HashSet<OsString>, &'a Selection);
 
 impl<'a> Iterator for SelectedItemPaths<'a> {
     type Item = IoResult<Cow<'a, Path>>;
@@ -235,7 +638,7 @@ impl<'a> Iterator for SelectedItemPaths<'a> {
                 Err(err) => {
                     return Some(Err(err));
                 }
-                Ok(path) => match self.1.is_selected(&path) {
+                Ok(path) => match self.2.is_selected_excluding(&path, &self.1) {
                     Ok(true) => {
                         return Some(Ok(path));
                     }
@@ -252,3 +655,342 @@ impl<'a> Iterator for SelectedItemPaths<'a> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+    use crate::types::{Block, BlockSeq, DateTime, Value};
+
+    #[test]
+    fn render_sidecar_name() {
+        let inputs_and_expected = vec![
+            (("{name}.yml", "music.flac"), "music.flac.yml"),
+            (("{stem}.meta.json", "music.flac"), "music.meta.json"),
+            (("{stem}.yml", "music"), "music.yml"),
+            ((".{stem}-{name}.yml", "music.flac"), ".music-music.flac.yml"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (template, item_name) = input;
+            let produced = super::render_sidecar_name(template, OsStr::new(item_name));
+            assert_eq!(OsString::from(expected), produced);
+        }
+    }
+
+    #[test]
+    fn from_name_sidecar() {
+        assert!(Source::from_name(str!("{name}.yml"), Anchor::Sidecar).is_ok());
+        assert!(Source::from_name(str!("{stem}.meta.json"), Anchor::Sidecar).is_ok());
+        assert!(matches!(
+            Source::from_name(str!("item.yml"), Anchor::Sidecar),
+            Err(CreateError::MissingPlaceholder(_)),
+        ));
+        assert!(matches!(
+            Source::from_name(str!("{name}.txt"), Anchor::Sidecar),
+            Err(CreateError::UnknownExt(_)),
+        ));
+    }
+
+    #[test]
+    fn sidecar_paths() {
+        let temp_dir = TU::create_simple_dir(
+            "sidecar_paths",
+            ["a.flac", "a.flac.yml", "b.flac", "b.yml", "c.flac"],
+        );
+        let path = temp_dir.path();
+
+        let source = Source::from_name(str!("{name}.yml"), Anchor::Sidecar).unwrap();
+
+        assert_eq!(source.meta_path(&path.join("a.flac")).unwrap(), path.join("a.flac.yml"));
+        assert!(matches!(
+            source.meta_path(&path.join("c.flac")),
+            Err(SourceError::MetaAccess(..)),
+        ));
+
+        let produced = source
+            .item_paths(&path.join("a.flac.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![path.join("a.flac")], produced);
+
+        let source = Source::from_name(str!("{stem}.yml"), Anchor::Sidecar).unwrap();
+
+        assert_eq!(source.meta_path(&path.join("b.flac")).unwrap(), path.join("b.yml"));
+
+        let produced = source
+            .item_paths(&path.join("b.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![path.join("b.flac")], produced);
+
+        assert_eq!(source.name_pattern(), None);
+        assert_eq!(source.sidecar_template(), Some("{stem}.yml"));
+
+        // Only the sidecar meta files of actual item files are excluded.
+        std::fs::write(path.join("d.yml"), "").unwrap();
+        let selection = Selection::default().with_excluded_sidecar(str!("{stem}.yml"));
+
+        let mut produced = selection
+            .select_in_dir(path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        produced.sort();
+        assert_eq!(
+            vec![
+                path.join("a.flac"),
+                path.join("a.flac.yml"),
+                path.join("b.flac"),
+                path.join("c.flac"),
+                path.join("d.yml"),
+            ],
+            produced,
+        );
+
+        let meta_source = Source::from_name(str!("track.yml"), Anchor::External).unwrap();
+        std::fs::write(path.join("track.yml"), "").unwrap();
+
+        let mut produced = meta_source
+            .selected_item_paths(&path.join("track.yml"), &selection)
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        produced.sort();
+        assert!(!produced.contains(&path.join("b.yml")));
+        assert!(produced.contains(&path.join("d.yml")));
+
+        // Multiple item files that share a sidecar meta file are an error.
+        std::fs::write(path.join("b.mp3"), "").unwrap();
+        assert!(matches!(
+            source.item_paths(&path.join("b.yml")),
+            Err(SourceError::SidecarCollision(_)),
+        ));
+    }
+
+    #[test]
+    fn embedded_paths() {
+        let temp_dir = TU::create_simple_dir("embedded_paths", ["a.flac", "b.MP3", "c.wav"]);
+        let path = temp_dir.path();
+
+        let source = Source::embedded();
+
+        assert_eq!(source.meta_path(&path.join("a.flac")).unwrap(), path.join("a.flac"));
+        assert_eq!(source.meta_path(&path.join("b.MP3")).unwrap(), path.join("b.MP3"));
+        assert!(matches!(
+            source.meta_path(&path.join("c.wav")),
+            Err(SourceError::NoEmbeddedTags(_)),
+        ));
+        assert!(matches!(source.meta_path(path), Err(SourceError::NoEmbeddedTags(_))));
+
+        let produced = source
+            .item_paths(&path.join("a.flac"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![path.join("a.flac")], produced);
+
+        assert_eq!(source.name_pattern(), None);
+    }
+
+    #[test]
+    fn meta_dir_paths() {
+        let temp_dir = TU::create_simple_dir("meta_dir_paths", ["a.flac", "b.flac"]);
+        let path = temp_dir.path();
+
+        let meta_dir = path.join(".anagma");
+        std::fs::create_dir(&meta_dir).unwrap();
+        std::fs::write(meta_dir.join("track.yml"), "").unwrap();
+        std::fs::write(meta_dir.join("self.yml"), "").unwrap();
+        std::fs::write(meta_dir.join("a.flac.yml"), "").unwrap();
+
+        // Meta directory names must be valid item names.
+        assert!(matches!(
+            Source::from_name(str!("self.yml"), Anchor::Internal).unwrap()
+                .with_meta_dir(str!("a/b")),
+            Err(CreateError::InvalidName(..)),
+        ));
+
+        let source = Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+            .with_meta_dir(str!(".anagma")).unwrap();
+
+        assert_eq!(source.meta_path(&path.join("a.flac")).unwrap(), meta_dir.join("track.yml"));
+
+        let mut produced = source
+            .item_paths(&meta_dir.join("track.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        produced.sort();
+        assert_eq!(vec![meta_dir.clone(), path.join("a.flac"), path.join("b.flac")], produced);
+
+        // Meta files outside of the meta directory are rejected.
+        std::fs::write(path.join("track.yml"), "").unwrap();
+        assert!(matches!(
+            source.item_paths(&path.join("track.yml")),
+            Err(SourceError::NotInMetaDir(_)),
+        ));
+
+        let source = Source::from_name(str!("self.yml"), Anchor::Internal).unwrap()
+            .with_meta_dir(str!(".anagma")).unwrap();
+
+        assert_eq!(source.meta_path(path).unwrap(), meta_dir.join("self.yml"));
+
+        let produced = source
+            .item_paths(&meta_dir.join("self.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![path.to_path_buf()], produced);
+
+        let source = Source::from_name(str!("{name}.yml"), Anchor::Sidecar).unwrap()
+            .with_meta_dir(str!(".anagma")).unwrap();
+
+        assert_eq!(source.meta_path(&path.join("a.flac")).unwrap(), meta_dir.join("a.flac.yml"));
+
+        let produced = source
+            .item_paths(&meta_dir.join("a.flac.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![path.join("a.flac")], produced);
+    }
+
+    #[test]
+    fn mirror_paths() {
+        let temp_dir = TU::create_simple_dir("mirror_paths", &[] as &[&str]);
+        let media_root = temp_dir.path().join("media");
+        let meta_root = temp_dir.path().join("meta");
+
+        let media_album = media_root.join("Artist").join("Album");
+        let meta_album = meta_root.join("Artist").join("Album");
+        std::fs::create_dir_all(&media_album).unwrap();
+        std::fs::create_dir_all(&meta_album).unwrap();
+        std::fs::write(media_album.join("01.flac"), "").unwrap();
+        std::fs::write(media_album.join("02.flac"), "").unwrap();
+        std::fs::write(meta_album.join("track.yml"), "").unwrap();
+        std::fs::write(meta_album.join("self.yml"), "").unwrap();
+
+        let mirror = Mirror::new(media_root.clone(), meta_root.clone());
+
+        let source = Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+            .with_mirror(mirror.clone());
+
+        assert_eq!(
+            source.meta_path(&media_album.join("01.flac")).unwrap(),
+            meta_album.join("track.yml"),
+        );
+
+        let mut produced = source
+            .item_paths(&meta_album.join("track.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        produced.sort();
+        assert_eq!(vec![media_album.join("01.flac"), media_album.join("02.flac")], produced);
+
+        // The media root itself has no parent directory inside the media root.
+        let res = source.meta_path(&media_root);
+        assert!(matches!(res, Err(SourceError::OutsideMediaRoot(_))));
+        assert!(!res.unwrap_err().is_fatal());
+
+        let source = Source::from_name(str!("self.yml"), Anchor::Internal).unwrap()
+            .with_mirror(mirror);
+
+        assert_eq!(source.meta_path(&media_album).unwrap(), meta_album.join("self.yml"));
+
+        let produced = source
+            .item_paths(&meta_album.join("self.yml"))
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![media_album.clone()], produced);
+    }
+
+    #[test]
+    fn index_paths() {
+        let temp_dir = TU::create_simple_dir("index_paths", ["catalog.json"]);
+        let path = temp_dir.path();
+
+        let album_path = path.join("Artist").join("Album");
+        std::fs::create_dir_all(&album_path).unwrap();
+        std::fs::write(album_path.join("01.flac"), "").unwrap();
+
+        let source = Source::from_name(str!("catalog.json"), Anchor::Index).unwrap();
+
+        assert_eq!(source.meta_path(&album_path.join("01.flac")).unwrap(), path.join("catalog.json"));
+        assert_eq!(source.meta_path(&album_path).unwrap(), path.join("catalog.json"));
+        assert_eq!(source.anchor_dir_path(&path.join("catalog.json")).unwrap(), path);
+        assert_eq!(source.item_paths(&path.join("catalog.json")).unwrap().count(), 0);
+
+        // The nearest index wins.
+        std::fs::write(path.join("Artist").join("catalog.json"), "").unwrap();
+        assert_eq!(
+            source.meta_path(&album_path.join("01.flac")).unwrap(),
+            path.join("Artist").join("catalog.json"),
+        );
+
+        // An index does not provide metadata for its own directory.
+        assert!(matches!(source.meta_path(path), Err(SourceError::NoIndex(_))));
+    }
+
+    #[test]
+    fn read_schema_coerce_dates() {
+        let temp_dir = TU::create_simple_dir("read_schema_coerce_dates", &[] as &[&str]);
+        let path = temp_dir.path();
+
+        let dt = |s| Value::DateTime(DateTime::parse(s).unwrap());
+
+        std::fs::write(
+            path.join("album.json"),
+            r#"{"date": "2020-08-20", "year": "2019", "released": ["2019-08", "August 2019"], "count": 2019}"#,
+        ).unwrap();
+        std::fs::write(
+            path.join("album.yml"),
+            "date: '2020-08-20'\nyear: '2019'\nreleased: [2019-08, August 2019]\ncount: 2019\n",
+        ).unwrap();
+        std::fs::write(
+            path.join("album.toml"),
+            "date = '2020-08-20'\nyear = '2019'\nreleased = ['2019-08', 'August 2019']\ncount = 2019\n",
+        ).unwrap();
+        std::fs::write(
+            path.join("combined.toml"),
+            "[album]\ndate = '2020-08-20'\nyear = '2019'\nreleased = ['2019-08', 'August 2019']\ncount = 2019\n",
+        ).unwrap();
+        std::fs::write(path.join("track.csv"), "date,year,title\n2020-08-20,2019,August 2019\n").unwrap();
+
+        let expected = Schema::One(Block(btreemap![
+            str!("date") => dt("2020-08-20"),
+            str!("year") => dt("2019"),
+            str!("released") => Value::Sequence(vec![dt("2019-08"), Value::from("August 2019")]),
+            str!("count") => Value::Integer(2019),
+        ]));
+
+        for name in ["album.json", "album.yml", "album.toml", "combined.toml"] {
+            let source = Source::from_name(str!(name), Anchor::Internal).unwrap();
+            let source = if name.starts_with("combined") { source.with_layout(Layout::Combined) } else { source };
+
+            // Strings are only converted when asked to.
+            let produced = source.read_schema(&path.join(name)).unwrap();
+            assert!(matches!(&produced, Schema::One(b) if b.get("date") == Some(&Value::from("2020-08-20"))), "{}", name);
+
+            let produced = source.with_coerce_dates(true).read_schema(&path.join(name)).unwrap();
+            assert_eq!(expected, produced, "{}", name);
+        }
+
+        // Cells that are already integers are kept as integers.
+        let source = Source::from_name(str!("track.csv"), Anchor::External).unwrap().with_coerce_dates(true);
+        let expected = Schema::Seq(BlockSeq(vec![Block(btreemap![
+            str!("date") => dt("2020-08-20"),
+            str!("year") => Value::Integer(2019),
+            str!("title") => Value::from("August 2019"),
+        ])]));
+        assert_eq!(expected, source.read_schema(&path.join("track.csv")).unwrap());
+    }
+}
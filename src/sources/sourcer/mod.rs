@@ -25,6 +25,17 @@ impl Sourcer {
         MetaPaths {
             iter: self.0.iter(),
             item_path,
+            root_dir: None,
+        }
+    }
+
+    /// Similar to `meta_paths`, but index meta files are only looked for
+    /// inside of a root directory.
+    pub fn meta_paths_within<'a>(&'a self, item_path: &'a Path, root_dir: &'a Path) -> MetaPaths<'a> {
+        MetaPaths {
+            iter: self.0.iter(),
+            item_path,
+            root_dir: Some(root_dir),
         }
     }
 
@@ -42,21 +53,29 @@ impl From<Vec<Source>> for Sourcer {
 pub struct MetaPaths<'a> {
     iter: std::slice::Iter<'a, Source>,
     item_path: &'a Path,
+    root_dir: Option<&'a Path>,
 }
 
+/// Produces the meta file path of each source for an item file path, in source
+/// order. Sources that do not provide a meta file for the item file path are
+/// skipped, unless they report a fatal error, which is produced along with the
+/// source that reported it.
 impl<'a> Iterator for MetaPaths<'a> {
-    type Item = Result<(PathBuf, &'a Source), SourceError>;
+    type Item = Result<(PathBuf, &'a Source), (SourceError, &'a Source)>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(source) = self.iter.next() {
-            let res = source.meta_path(self.item_path);
+            let res = match self.root_dir {
+                None => source.meta_path(self.item_path),
+                Some(root_dir) => source.meta_path_within(self.item_path, root_dir),
+            };
 
             match res {
                 Ok(meta_path) => {
                     return Some(Ok((meta_path, source)));
                 }
                 Err(err) if err.is_fatal() => {
-                    return Some(Err(err));
+                    return Some(Err((err, source)));
                 }
                 Err(_) => {
                     continue;
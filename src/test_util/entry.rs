@@ -46,6 +46,9 @@ fn create_meta_json(name: &str, anchor: &Anchor, include_flag: bool) -> Json {
     let anchor_str = match anchor {
         Anchor::Internal => "self",
         Anchor::External => "item",
+        Anchor::Sidecar => "sidecar",
+        Anchor::Index => "index",
+        Anchor::Embedded => "embedded",
     };
 
     let mut json_map = JsonMap::new();
@@ -173,6 +173,9 @@ impl TestUtil {
         let anchor_str = match anchor {
             Anchor::Internal => "self",
             Anchor::External => "item",
+            Anchor::Sidecar => "sidecar",
+            Anchor::Index => "index",
+            Anchor::Embedded => "embedded",
         };
 
         block.insert(
@@ -14,8 +14,7 @@ use serde::{Serialize, Deserialize};
 use crate::types::Value;
 
 /// Represents a chunk of metadata for one item.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Block(pub(crate) InnerMap<String, Value>);
 
@@ -83,6 +82,12 @@ impl Block {
     pub fn values_mut(&mut self) -> ValuesMut<'_> {
         ValuesMut(self.0.values_mut())
     }
+
+    /// Converts the strings in this block that are dates or datetimes into
+    /// datetimes, including strings inside of nested values.
+    pub(crate) fn coerce_dates(&mut self) {
+        self.values_mut().for_each(Value::coerce_dates)
+    }
 }
 
 impl Extend<(String, Value)> for Block {
@@ -0,0 +1,595 @@
+//! Calendar dates and times of day with partial precision, such as `2019`,
+//! `2019-08`, `2019-08-20`, or `2019-08-20T10:30:00.5+02:00`.
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use serde::de::{Error as DeError, MapAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// The key that the `toml` crate uses to present native TOML datetimes when
+/// deserializing them directly.
+const TOML_DATETIME_KEY: &str = "$__toml_private_datetime";
+
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Error {
+    #[error("invalid datetime syntax")]
+    Syntax,
+    #[error("datetime component out of range")]
+    OutOfRange,
+}
+
+/// The most specific component that a datetime includes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Precision {
+    Year,
+    Month,
+    Day,
+    Minute,
+    Second,
+}
+
+/// A UTC offset, which is kept in the form it was written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Offset {
+    Z,
+    Plus(u16),
+    Minus(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Time {
+    hour: u8,
+    minute: u8,
+    second: Option<u8>,
+
+    /// The fraction of a second in nanoseconds, along with the number of
+    /// digits that it was written with.
+    fraction: Option<(u32, u8)>,
+
+    offset: Option<Offset>,
+}
+
+/// A date with a precision of a year, month, or day, optionally followed by a
+/// time of day with a precision of a minute or second and an optional UTC
+/// offset. Datetimes are written in the extended ISO 8601 form, with a `T`
+/// between the date and the time, and offsets written as `Z` or `+hh:mm`.
+///
+/// Only text in exactly this form is parsed, so that a datetime is always
+/// written back in the same form that it was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    year: u16,
+    month: Option<u8>,
+    day: Option<u8>,
+    time: Option<Time>,
+}
+
+impl DateTime {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        Parser { bytes: s.as_bytes(), pos: 0, native: false }.parse()
+    }
+
+    /// Reads the text of a native TOML datetime, which may also use a space or
+    /// a lowercase `t` between the date and the time, a lowercase `z` offset,
+    /// and more than nine digits of fractional seconds. The datetime is written
+    /// back in the extended form, with any extra fractional digits truncated.
+    fn parse_native(s: &str) -> Result<Self, Error> {
+        Parser { bytes: s.as_bytes(), pos: 0, native: true }.parse()
+    }
+
+    /// Reads a string as a datetime, for places that explicitly ask for
+    /// strings to be coerced into datetimes. Strings that are not datetimes
+    /// produce `None` instead of an error.
+    pub fn coerce(s: &str) -> Option<Self> {
+        Self::parse(s).ok()
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> Option<u8> {
+        self.month
+    }
+
+    pub fn day(&self) -> Option<u8> {
+        self.day
+    }
+
+    pub fn hour(&self) -> Option<u8> {
+        self.time.map(|t| t.hour)
+    }
+
+    pub fn minute(&self) -> Option<u8> {
+        self.time.map(|t| t.minute)
+    }
+
+    pub fn second(&self) -> Option<u8> {
+        self.time.and_then(|t| t.second)
+    }
+
+    pub fn nanosecond(&self) -> Option<u32> {
+        self.time.and_then(|t| t.fraction).map(|(nanos, _)| nanos)
+    }
+
+    /// The UTC offset in minutes, or `None` for local datetimes.
+    pub fn offset_minutes(&self) -> Option<i16> {
+        match self.time?.offset? {
+            Offset::Z => Some(0),
+            Offset::Plus(m) => Some(m as i16),
+            Offset::Minus(m) => Some(-(m as i16)),
+        }
+    }
+
+    pub fn precision(&self) -> Precision {
+        match (self.month, self.day, self.time) {
+            (None, _, _) => Precision::Year,
+            (Some(_), None, _) => Precision::Month,
+            (Some(_), Some(_), None) => Precision::Day,
+            (Some(_), Some(_), Some(time)) if time.second.is_none() => Precision::Minute,
+            _ => Precision::Second,
+        }
+    }
+
+    /// Compares two datetimes chronologically, based on the instant that each
+    /// one starts at. Local datetimes are treated as UTC. Datetimes that start
+    /// at the same instant are ordered from least to most precise, so `2019`
+    /// comes before `2019-01`.
+    pub fn val_cmp(&self, other: &Self) -> Ordering {
+        self.start_nanos()
+            .cmp(&other.start_nanos())
+            .then_with(|| self.precision().cmp(&other.precision()))
+    }
+
+    /// Compares two datetimes for chronological equality, ignoring how their
+    /// offsets and fractions of a second are written.
+    pub fn val_eq(&self, other: &Self) -> bool {
+        self.val_cmp(other) == Ordering::Equal
+    }
+
+    /// The number of nanoseconds since the Unix epoch in UTC at the start of
+    /// this datetime.
+    fn start_nanos(&self) -> i128 {
+        let days = days_from_civil(self.year as i64, self.month.unwrap_or(1), self.day.unwrap_or(1));
+
+        let (seconds_of_day, nanos, offset) = match self.time {
+            None => (0, 0, 0),
+            Some(time) => (
+                time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second.unwrap_or(0) as i64,
+                time.fraction.map(|(n, _)| n).unwrap_or(0),
+                self.offset_minutes().unwrap_or(0) as i64 * 60,
+            ),
+        };
+
+        let seconds = days * 86_400 + seconds_of_day - offset;
+
+        seconds as i128 * 1_000_000_000 + nanos as i128
+    }
+}
+
+/// The number of days since the Unix epoch of a date in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:04}", self.year)?;
+
+        if let Some(month) = self.month {
+            write!(f, "-{:02}", month)?;
+        }
+
+        if let Some(day) = self.day {
+            write!(f, "-{:02}", day)?;
+        }
+
+        if let Some(time) = self.time {
+            write!(f, "T{:02}:{:02}", time.hour, time.minute)?;
+
+            if let Some(second) = time.second {
+                write!(f, ":{:02}", second)?;
+            }
+
+            if let Some((nanos, digits)) = time.fraction {
+                let scaled = nanos / 10u32.pow(9 - digits as u32);
+                write!(f, ".{:0width$}", scaled, width = digits as usize)?;
+            }
+
+            match time.offset {
+                None => {},
+                Some(Offset::Z) => write!(f, "Z")?,
+                Some(Offset::Plus(m)) => write!(f, "+{:02}:{:02}", m / 60, m % 60)?,
+                Some(Offset::Minus(m)) => write!(f, "-{:02}:{:02}", m / 60, m % 60)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for DateTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct DateTimeVisitor {
+    native_only: bool,
+}
+
+impl<'de> Visitor<'de> for DateTimeVisitor {
+    type Value = DateTime;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if self.native_only {
+            write!(f, "a native TOML datetime")
+        } else {
+            write!(f, "an ISO 8601 date or datetime")
+        }
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> Result<Self::Value, E> {
+        if self.native_only {
+            return Err(E::invalid_type(Unexpected::Str(s), &self));
+        }
+
+        DateTime::parse(s).map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        match map.next_key::<String>()? {
+            Some(key) if key == TOML_DATETIME_KEY => {
+                let s = map.next_value::<String>()?;
+                DateTime::parse_native(&s).map_err(A::Error::custom)
+            },
+            _ => Err(A::Error::custom("expected a TOML datetime")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DateTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DateTimeVisitor { native_only: false })
+    }
+}
+
+/// Deserializes a datetime only from a native TOML datetime, and never from a
+/// string, so that strings in meta files are not read as datetimes.
+pub(crate) fn deserialize_native<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime, D::Error> {
+    deserializer.deserialize_any(DateTimeVisitor { native_only: true })
+}
+
+struct NativeTextVisitor;
+
+impl<'de> Visitor<'de> for NativeTextVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "a string")
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> Result<Self::Value, E> {
+        Ok(s.to_owned())
+    }
+
+    fn visit_string<E: DeError>(self, s: String) -> Result<Self::Value, E> {
+        Ok(s)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        match map.next_key::<String>()? {
+            Some(key) if key == TOML_DATETIME_KEY => map.next_value::<String>(),
+            _ => Err(A::Error::custom("expected a TOML datetime")),
+        }
+    }
+}
+
+/// Deserializes a string, or the text of a native TOML datetime that cannot be
+/// represented as a datetime, such as a time of day without a date.
+pub(crate) fn deserialize_string_or_native<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    deserializer.deserialize_any(NativeTextVisitor)
+}
+
+struct Parser<'s> {
+    bytes: &'s [u8],
+    pos: usize,
+
+    /// Whether to also accept the alternative forms of native TOML datetimes.
+    native: bool,
+}
+
+impl<'s> Parser<'s> {
+    fn parse(mut self) -> Result<DateTime, Error> {
+        let year = self.digits(4)? as u16;
+        let mut dt = DateTime { year, month: None, day: None, time: None };
+
+        if self.eat(b'-') {
+            let month = self.ranged(2, 1, 12)?;
+            dt.month = Some(month);
+
+            if self.eat(b'-') {
+                dt.day = Some(self.ranged(2, 1, days_in_month(year, month) as u32)?);
+
+                if self.eat(b'T') || (self.native && (self.eat(b't') || self.eat(b' '))) {
+                    dt.time = Some(self.time()?);
+                }
+            }
+        }
+
+        if self.pos != self.bytes.len() {
+            return Err(Error::Syntax);
+        }
+
+        Ok(dt)
+    }
+
+    fn time(&mut self) -> Result<Time, Error> {
+        let hour = self.ranged(2, 0, 23)?;
+        self.expect(b':')?;
+        let minute = self.ranged(2, 0, 59)?;
+
+        let mut second = None;
+        let mut fraction = None;
+
+        if self.eat(b':') {
+            second = Some(self.ranged(2, 0, 59)?);
+
+            if self.eat(b'.') {
+                let start = self.pos;
+
+                while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+                    self.pos += 1;
+                }
+
+                let mut digits = self.pos - start;
+
+                if digits == 0 || (digits > 9 && !self.native) {
+                    return Err(Error::Syntax);
+                }
+
+                // Native TOML datetimes may be more precise than nanoseconds.
+                digits = digits.min(9);
+
+                let value = std::str::from_utf8(&self.bytes[start..start + digits])
+                    .ok()
+                    .and_then(|s| s.parse::<u32>().ok())
+                    .ok_or(Error::Syntax)?;
+
+                fraction = Some((value * 10u32.pow(9 - digits as u32), digits as u8));
+            }
+        }
+
+        let offset = match self.bytes.get(self.pos) {
+            Some(b'Z') => {
+                self.pos += 1;
+                Some(Offset::Z)
+            },
+            Some(b'z') if self.native => {
+                self.pos += 1;
+                Some(Offset::Z)
+            },
+            Some(&sign @ (b'+' | b'-')) => {
+                self.pos += 1;
+                let hours = self.ranged(2, 0, 23)? as u16;
+                self.expect(b':')?;
+                let minutes = self.ranged(2, 0, 59)? as u16;
+
+                let total = hours * 60 + minutes;
+                Some(if sign == b'-' { Offset::Minus(total) } else { Offset::Plus(total) })
+            },
+            _ => None,
+        };
+
+        Ok(Time { hour, minute, second, fraction, offset })
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.bytes.get(self.pos) == Some(&b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), Error> {
+        if self.eat(b) { Ok(()) } else { Err(Error::Syntax) }
+    }
+
+    /// Parses exactly `n` ASCII digits.
+    fn digits(&mut self, n: usize) -> Result<u32, Error> {
+        let digits = self.bytes.get(self.pos..self.pos + n).ok_or(Error::Syntax)?;
+
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return Err(Error::Syntax);
+        }
+
+        self.pos += n;
+
+        Ok(digits.iter().fold(0, |acc, d| acc * 10 + (d - b'0') as u32))
+    }
+
+    fn ranged(&mut self, n: usize, min: u32, max: u32) -> Result<u8, Error> {
+        let value = self.digits(n)?;
+
+        if value < min || value > max {
+            return Err(Error::OutOfRange);
+        }
+
+        Ok(value as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let valid = [
+            ("2019", Precision::Year),
+            ("2019-08", Precision::Month),
+            ("2019-08-20", Precision::Day),
+            ("2020-02-29", Precision::Day),
+            ("2019-08-20T10:30", Precision::Minute),
+            ("2019-08-20T10:30:15", Precision::Second),
+            ("2019-08-20T10:30:15.5", Precision::Second),
+            ("2019-08-20T10:30:15.123456789Z", Precision::Second),
+            ("1979-05-27T07:32:00Z", Precision::Second),
+            ("1979-05-27T00:32:00.999-07:00", Precision::Second),
+            ("1979-05-27T00:32+05:30", Precision::Minute),
+            ("1979-05-27T00:32:00-00:00", Precision::Second),
+        ];
+
+        for (input, precision) in valid {
+            let produced = DateTime::parse(input).unwrap();
+            assert_eq!(precision, produced.precision(), "{}", input);
+
+            // Datetimes are written back exactly as they were read.
+            assert_eq!(input, produced.to_string());
+        }
+
+        let invalid = [
+            ("", Error::Syntax),
+            ("19", Error::Syntax),
+            ("20190", Error::Syntax),
+            ("2019-8", Error::Syntax),
+            ("2019-08-20 10:30:00", Error::Syntax),
+            ("2019-08-20t10:30:00", Error::Syntax),
+            ("2019-08-20T10", Error::Syntax),
+            ("2019-08-20T10:30:00.", Error::Syntax),
+            ("2019-08-20T10:30:00.1234567890", Error::Syntax),
+            ("2019-08-20T10:30:00z", Error::Syntax),
+            ("2019-08-20T10:30:00+0100", Error::Syntax),
+            ("2019-13", Error::OutOfRange),
+            ("2019-00", Error::OutOfRange),
+            ("2019-02-29", Error::OutOfRange),
+            ("1900-02-29", Error::OutOfRange),
+            ("2019-08-20T24:00", Error::OutOfRange),
+            ("2019-08-20T10:60", Error::OutOfRange),
+        ];
+
+        for (input, expected) in invalid {
+            assert_eq!(Err(expected), DateTime::parse(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn coerce() {
+        assert_eq!(DateTime::coerce("2019-08"), DateTime::parse("2019-08").ok());
+        assert_eq!(DateTime::coerce("2019-08-20T10:30Z"), DateTime::parse("2019-08-20T10:30Z").ok());
+        assert_eq!(DateTime::coerce("2019"), DateTime::parse("2019").ok());
+        assert_eq!(DateTime::coerce("August 2019"), None);
+        assert_eq!(DateTime::coerce("2019-02-29"), None);
+    }
+
+    #[test]
+    fn val_cmp() {
+        let ordered = [
+            "1969-12-31T23:59:59.999999999Z",
+            "1970",
+            "1970-01",
+            "1970-01-01",
+            "1970-01-01T00:00",
+            "1970-01-01T00:00:00",
+            "1970-01-01T00:00:00.000000001",
+            "2019",
+            "2019-08-20T12:00:00+02:00",
+            "2019-08-20T10:30:00Z",
+            "2019-08-20T10:30:00.25",
+            "2019-08-21",
+            "2020-02-29",
+            "2020-03",
+        ];
+
+        for (i, l) in ordered.iter().enumerate() {
+            for (j, r) in ordered.iter().enumerate() {
+                let l = DateTime::parse(l).unwrap();
+                let r = DateTime::parse(r).unwrap();
+                assert_eq!(i.cmp(&j), l.val_cmp(&r), "{} {}", l, r);
+            }
+        }
+
+        // Offsets and fractions are compared by their values.
+        let l = DateTime::parse("2019-08-20T10:30:00.50Z").unwrap();
+        let r = DateTime::parse("2019-08-20T11:30:00.5+01:00").unwrap();
+        assert_ne!(l, r);
+        assert!(l.val_eq(&r));
+    }
+
+    #[test]
+    fn serde() {
+        let produced = serde_json::from_str::<DateTime>(r#""2019-08-20T10:30:00+02:00""#).unwrap();
+        assert_eq!(DateTime::parse("2019-08-20T10:30:00+02:00").unwrap(), produced);
+        assert_eq!(r#""2019-08-20T10:30:00+02:00""#, serde_json::to_string(&produced).unwrap());
+
+        assert!(serde_json::from_str::<DateTime>(r#""August 2019""#).is_err());
+        assert!(serde_json::from_str::<DateTime>("2019").is_err());
+
+        // Native TOML datetimes are accepted.
+        #[derive(Deserialize)]
+        struct Doc {
+            date: DateTime,
+        }
+
+        let produced = toml::from_str::<Doc>("date = 1979-05-27T07:32:00Z").unwrap();
+        assert_eq!(DateTime::parse("1979-05-27T07:32:00Z").unwrap(), produced.date);
+
+        // Only native TOML datetimes are accepted where strings must be kept.
+        #[derive(Deserialize)]
+        struct NativeDoc {
+            #[serde(deserialize_with = "deserialize_native")]
+            date: DateTime,
+        }
+
+        let produced = toml::from_str::<NativeDoc>("date = 1979-05-27").unwrap();
+        assert_eq!(DateTime::parse("1979-05-27").unwrap(), produced.date);
+        assert!(toml::from_str::<NativeDoc>("date = '1979-05-27'").is_err());
+
+        // All forms of native TOML datetimes are accepted, and are written back
+        // in the extended form.
+        let inputs_and_expected = [
+            ("1979-05-27 07:32:00Z", "1979-05-27T07:32:00Z"),
+            ("1979-05-27t07:32:00z", "1979-05-27T07:32:00Z"),
+            ("1979-05-27T07:32:00.1234567891Z", "1979-05-27T07:32:00.123456789Z"),
+            ("1979-05-27 00:32:00.5-07:00", "1979-05-27T00:32:00.5-07:00"),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = toml::from_str::<NativeDoc>(&format!("date = {}", input)).unwrap();
+            assert_eq!(expected, produced.date.to_string(), "{}", input);
+        }
+
+        // Times of day without a date are not datetimes.
+        assert!(toml::from_str::<NativeDoc>("date = 07:32:00").is_err());
+    }
+}
@@ -1,11 +1,17 @@
 pub mod block;
 pub mod block_seq;
 pub mod block_map;
+pub mod datetime;
 mod number;
+pub mod ops;
+pub mod query;
 mod value;
 
 pub use self::block::Block;
 pub use self::block_seq::BlockSeq;
 pub use self::block_map::BlockMap;
-pub use self::number::Number;
+pub use self::datetime::{DateTime, Error as DateTimeError};
+pub use self::number::{Number, OverflowMode, Error as NumberError};
+pub use self::ops::Op;
+pub use self::query::{Query, Match, Error as QueryError};
 pub use self::value::{Value, Sequence, Decimal, Error as ValueError};
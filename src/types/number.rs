@@ -2,8 +2,34 @@ use std::cmp::Ordering;
 use std::ops::{Add, Sub, Mul, Div, Rem, Neg};
 
 use rust_decimal::Decimal;
+use thiserror::Error;
+
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Error {
+    #[error("arithmetic overflow")]
+    Overflow,
+    #[error("division by zero")]
+    DivisionByZero,
+}
+
+/// Describes what checked arithmetic does when an operation on integers
+/// overflows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OverflowMode {
+    /// Fail with an overflow error.
+    #[default]
+    Fail,
+
+    /// Promote the operands to decimals and try the operation again, which
+    /// only fails if the decimal operation overflows as well.
+    Promote,
+}
 
 /// Wrapper type to smooth over the differences between integers and decimals.
+///
+/// The arithmetic operator impls follow the behavior of the underlying types,
+/// and panic on overflow and division by zero. Use the `checked_*` methods for
+/// values that come from meta files.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum Number {
     Integer(i64),
@@ -35,6 +61,85 @@ impl Number {
         }
     }
 
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Integer(i) => *i == 0,
+            Self::Decimal(d) => d.is_zero(),
+        }
+    }
+
+    pub fn checked_add(self, other: Self, mode: OverflowMode) -> Result<Self, Error> {
+        self.checked_binary(other, mode, i64::checked_add, Decimal::checked_add)
+    }
+
+    pub fn checked_sub(self, other: Self, mode: OverflowMode) -> Result<Self, Error> {
+        self.checked_binary(other, mode, i64::checked_sub, Decimal::checked_sub)
+    }
+
+    pub fn checked_mul(self, other: Self, mode: OverflowMode) -> Result<Self, Error> {
+        self.checked_binary(other, mode, i64::checked_mul, Decimal::checked_mul)
+    }
+
+    /// Divides two numbers. Division of two integers is truncated towards zero,
+    /// unless it overflows and the operands are promoted to decimals.
+    pub fn checked_div(self, other: Self, mode: OverflowMode) -> Result<Self, Error> {
+        if other.is_zero() {
+            return Err(Error::DivisionByZero);
+        }
+
+        self.checked_binary(other, mode, i64::checked_div, Decimal::checked_div)
+    }
+
+    pub fn checked_rem(self, other: Self, mode: OverflowMode) -> Result<Self, Error> {
+        if other.is_zero() {
+            return Err(Error::DivisionByZero);
+        }
+
+        self.checked_binary(other, mode, i64::checked_rem, Decimal::checked_rem)
+    }
+
+    pub fn checked_neg(self, mode: OverflowMode) -> Result<Self, Error> {
+        match self {
+            Self::Integer(i) => match (i.checked_neg(), mode) {
+                (Some(n), _) => Ok(Self::Integer(n)),
+                (None, OverflowMode::Fail) => Err(Error::Overflow),
+                (None, OverflowMode::Promote) => Ok(Self::Decimal(-Decimal::from(i))),
+            },
+            Self::Decimal(d) => Ok(Self::Decimal(-d)),
+        }
+    }
+
+    /// Converts this number into a decimal, which is lossless.
+    pub fn to_decimal(self) -> Decimal {
+        match self {
+            Self::Integer(i) => Decimal::from(i),
+            Self::Decimal(d) => d,
+        }
+    }
+
+    /// Applies a checked operation to two integers, or to two decimals if
+    /// either operand is a decimal or the integer operation overflows in
+    /// promoting mode.
+    fn checked_binary(
+        self,
+        other: Self,
+        mode: OverflowMode,
+        int_op: fn(i64, i64) -> Option<i64>,
+        dec_op: fn(Decimal, Decimal) -> Option<Decimal>,
+    ) -> Result<Self, Error> {
+        if let (Self::Integer(l), Self::Integer(r)) = (self, other) {
+            match (int_op(l, r), mode) {
+                (Some(n), _) => return Ok(Self::Integer(n)),
+                (None, OverflowMode::Fail) => return Err(Error::Overflow),
+                (None, OverflowMode::Promote) => {},
+            }
+        }
+
+        dec_op(self.to_decimal(), other.to_decimal())
+            .map(Self::Decimal)
+            .ok_or(Error::Overflow)
+    }
+
     /// Returns the smaller of two number-likes, based on their numerical values.
     /// If equal, returns the first value, to match Rust's behavior.
     pub fn val_min(self, other: Self) -> Self {
@@ -69,6 +174,9 @@ impl From<&Decimal> for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow. See `Number::checked_add` for a fallible version.
 impl Add for Number {
     type Output = Number;
 
@@ -82,6 +190,9 @@ impl Add for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow. See `Number::checked_sub` for a fallible version.
 impl Sub for Number {
     type Output = Number;
 
@@ -95,6 +206,9 @@ impl Sub for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow. See `Number::checked_mul` for a fallible version.
 impl Mul for Number {
     type Output = Number;
 
@@ -108,6 +222,10 @@ impl Mul for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow and division by zero. See `Number::checked_div` for
+/// a fallible version.
 impl Div for Number {
     type Output = Number;
 
@@ -121,6 +239,10 @@ impl Div for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow and division by zero. See `Number::checked_rem` for
+/// a fallible version.
 impl Rem for Number {
     type Output = Number;
 
@@ -134,6 +256,9 @@ impl Rem for Number {
     }
 }
 
+/// # Panics
+///
+/// Panics on overflow. See `Number::checked_neg` for a fallible version.
 impl Neg for Number {
     type Output = Number;
 
@@ -287,6 +412,54 @@ mod tests {
         assert_eq!(Number::Decimal(dec!(1)), Number::Decimal(dec!(1)).val_min(Number::Integer(1)));
     }
 
+    #[test]
+    fn checked() {
+        let i = Number::Integer;
+        let d = Number::Decimal;
+        let fail = OverflowMode::Fail;
+        let promote = OverflowMode::Promote;
+
+        assert_eq!(Ok(i(3)), i(1).checked_add(i(2), fail));
+        assert_eq!(Ok(d(dec!(3.5))), i(1).checked_add(d(dec!(2.5)), fail));
+        assert_eq!(Err(Error::Overflow), i(i64::MAX).checked_add(i(1), fail));
+        assert_eq!(
+            Ok(d(Decimal::from(i64::MAX) + dec!(1))),
+            i(i64::MAX).checked_add(i(1), promote),
+        );
+        assert_eq!(Err(Error::Overflow), d(Decimal::MAX).checked_add(i(1), promote));
+
+        assert_eq!(Err(Error::Overflow), i(i64::MIN).checked_sub(i(1), fail));
+        assert_eq!(
+            Ok(d(Decimal::from(i64::MIN) - dec!(1))),
+            i(i64::MIN).checked_sub(i(1), promote),
+        );
+
+        assert_eq!(Err(Error::Overflow), i(i64::MAX).checked_mul(i(2), fail));
+        assert_eq!(
+            Ok(d(Decimal::from(i64::MAX) * dec!(2))),
+            i(i64::MAX).checked_mul(i(2), promote),
+        );
+
+        assert_eq!(Ok(i(2)), i(7).checked_div(i(3), fail));
+        assert_eq!(Ok(d(dec!(2.5))), i(5).checked_div(d(dec!(2)), fail));
+        assert_eq!(Err(Error::DivisionByZero), i(1).checked_div(i(0), promote));
+        assert_eq!(Err(Error::DivisionByZero), i(1).checked_div(d(dec!(0.0)), promote));
+        assert_eq!(Err(Error::Overflow), i(i64::MIN).checked_div(i(-1), fail));
+        assert_eq!(
+            Ok(d(-Decimal::from(i64::MIN))),
+            i(i64::MIN).checked_div(i(-1), promote),
+        );
+
+        assert_eq!(Ok(i(1)), i(7).checked_rem(i(3), fail));
+        assert_eq!(Err(Error::DivisionByZero), d(dec!(1.5)).checked_rem(i(0), fail));
+        assert_eq!(Err(Error::Overflow), i(i64::MIN).checked_rem(i(-1), fail));
+        assert_eq!(Ok(d(dec!(0))), i(i64::MIN).checked_rem(i(-1), promote));
+
+        assert_eq!(Ok(i(-1)), i(1).checked_neg(fail));
+        assert_eq!(Err(Error::Overflow), i(i64::MIN).checked_neg(fail));
+        assert_eq!(Ok(d(-Decimal::from(i64::MIN))), i(i64::MIN).checked_neg(promote));
+    }
+
     #[test]
     fn from_i64() {
         for i in -3i64..=3 {
@@ -0,0 +1,279 @@
+//! Operators that reduce or transform sequences of metadata values, which can
+//! be chained together.
+
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+
+use crate::types::{Decimal, Number, OverflowMode, Sequence, Value, ValueError as Error};
+
+/// An operator on a sequence of values. Operators that need numbers or strings
+/// fail with a conversion error on the first element of another kind, and all
+/// operators fail with a conversion error if the input is not a sequence.
+/// Arithmetic fails with an arithmetic error on overflow.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    /// The number of elements, as an integer.
+    Count,
+
+    /// The first element, or null if there are no elements.
+    First,
+
+    /// The last element, or null if there are no elements.
+    Last,
+
+    /// The elements, with the elements of any nested sequences spliced in
+    /// place, at any depth.
+    Flatten,
+
+    /// The elements, without elements that are equal to an earlier element.
+    /// Numbers are compared based on their numerical values.
+    Dedupe,
+
+    /// The elements, sorted by `Value::total_cmp`. Equal elements keep their
+    /// original order.
+    Sort,
+
+    /// The sum of numeric elements, or zero if there are no elements.
+    Sum,
+
+    /// The product of numeric elements, or one if there are no elements.
+    Product,
+
+    /// The arithmetic mean of numeric elements as a decimal, or null if there
+    /// are no elements.
+    Mean,
+
+    /// The middle numeric element after sorting, or the mean of the two middle
+    /// numeric elements as a decimal if there is an even number of elements.
+    /// This is null if there are no elements.
+    Median,
+
+    /// The string elements joined with a separator.
+    Join(String),
+
+    /// The smallest element by `Value::total_cmp`, or null if there are no
+    /// elements. If there are multiple smallest elements, the first is used.
+    Min,
+
+    /// The largest element by `Value::total_cmp`, or null if there are no
+    /// elements. If there are multiple largest elements, the last is used.
+    Max,
+}
+
+impl Op {
+    pub fn apply(&self, value: Value) -> Result<Value, Error> {
+        let seq = Sequence::try_from(value)?;
+
+        let output = match self {
+            Self::Count => Value::Integer(seq.len() as i64),
+            Self::First => seq.into_iter().next().unwrap_or(Value::Null),
+            Self::Last => seq.into_iter().last().unwrap_or(Value::Null),
+            Self::Flatten => {
+                let mut flattened = Vec::new();
+                flatten_into(seq, &mut flattened);
+                Value::Sequence(flattened)
+            },
+            Self::Dedupe => {
+                let mut deduped: Sequence = Vec::new();
+
+                for value in seq {
+                    if !deduped.iter().any(|d| d.val_eq(&value)) {
+                        deduped.push(value);
+                    }
+                }
+
+                Value::Sequence(deduped)
+            },
+            Self::Sort => {
+                let mut sorted = seq;
+                sorted.sort_by(Value::total_cmp);
+                Value::Sequence(sorted)
+            },
+            Self::Sum => {
+                to_numbers(seq)?
+                    .into_iter()
+                    .try_fold(Number::Integer(0), |acc, n| acc.checked_add(n, OverflowMode::Fail))?
+                    .into()
+            },
+            Self::Product => {
+                to_numbers(seq)?
+                    .into_iter()
+                    .try_fold(Number::Integer(1), |acc, n| acc.checked_mul(n, OverflowMode::Fail))?
+                    .into()
+            },
+            Self::Mean => mean(&to_numbers(seq)?)?.map(Value::Decimal).unwrap_or(Value::Null),
+            Self::Median => {
+                let mut numbers = to_numbers(seq)?;
+                numbers.sort_by(Number::val_cmp);
+
+                let mid = numbers.len() / 2;
+
+                if numbers.is_empty() {
+                    Value::Null
+                } else if numbers.len() % 2 == 1 {
+                    numbers[mid].into()
+                } else {
+                    mean(&numbers[mid - 1..=mid])?.map(Value::Decimal).unwrap_or(Value::Null)
+                }
+            },
+            Self::Join(separator) => {
+                let strings = seq
+                    .into_iter()
+                    .map(String::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Value::String(strings.join(separator))
+            },
+            Self::Min => seq.into_iter().reduce(|min, v| if v.total_cmp(&min).is_lt() { v } else { min }).unwrap_or(Value::Null),
+            Self::Max => seq.into_iter().reduce(|max, v| if v.total_cmp(&max).is_ge() { v } else { max }).unwrap_or(Value::Null),
+        };
+
+        Ok(output)
+    }
+
+    /// Applies operators in order, passing the output of each operator as the
+    /// input of the next.
+    pub fn apply_all<'a, I>(ops: I, value: Value) -> Result<Value, Error>
+    where
+        I: IntoIterator<Item = &'a Op>,
+    {
+        ops.into_iter().try_fold(value, |value, op| op.apply(value))
+    }
+}
+
+fn flatten_into(seq: Sequence, flattened: &mut Sequence) {
+    for value in seq {
+        match value {
+            Value::Sequence(inner) => flatten_into(inner, flattened),
+            value => flattened.push(value),
+        }
+    }
+}
+
+fn to_numbers(seq: Sequence) -> Result<Vec<Number>, Error> {
+    seq.into_iter().map(Number::try_from).collect()
+}
+
+fn mean(numbers: &[Number]) -> Result<Option<Decimal>, Error> {
+    if numbers.is_empty() {
+        return Ok(None);
+    }
+
+    // Summing as decimals avoids overflowing integers.
+    let sum = numbers
+        .iter()
+        .try_fold(Number::Decimal(Decimal::ZERO), |acc, n| acc.checked_add(*n, OverflowMode::Fail))?;
+
+    let mean = sum.checked_div(Number::Integer(numbers.len() as i64), OverflowMode::Fail)?;
+
+    Ok(Some(mean.to_decimal()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use rust_decimal_macros::dec;
+    use str_macro::str;
+
+    use crate::types::{Block, NumberError};
+    use crate::types::value::ValueKind;
+
+    fn seq(values: Vec<Value>) -> Value {
+        Value::Sequence(values)
+    }
+
+    #[test]
+    fn apply() {
+        let s = Value::from;
+        let i = Value::Integer;
+        let d = Value::Decimal;
+
+        let inputs_and_expected = vec![
+            ((Op::Count, seq(vec![i(1), s("a"), Value::Null])), Ok(i(3))),
+            ((Op::Count, seq(vec![])), Ok(i(0))),
+            ((Op::First, seq(vec![i(1), i(2)])), Ok(i(1))),
+            ((Op::First, seq(vec![])), Ok(Value::Null)),
+            ((Op::Last, seq(vec![i(1), i(2)])), Ok(i(2))),
+            ((Op::Last, seq(vec![])), Ok(Value::Null)),
+            (
+                (Op::Flatten, seq(vec![i(1), seq(vec![i(2), seq(vec![i(3)])]), seq(vec![])])),
+                Ok(seq(vec![i(1), i(2), i(3)])),
+            ),
+            (
+                (Op::Dedupe, seq(vec![i(1), s("a"), d(dec!(1.0)), s("a"), i(2)])),
+                Ok(seq(vec![i(1), s("a"), i(2)])),
+            ),
+            (
+                (Op::Sort, seq(vec![s("b"), i(2), Value::Null, d(dec!(1.5)), s("a"), Value::Boolean(true)])),
+                Ok(seq(vec![Value::Null, Value::Boolean(true), d(dec!(1.5)), i(2), s("a"), s("b")])),
+            ),
+            ((Op::Sum, seq(vec![i(1), i(2), i(3)])), Ok(i(6))),
+            ((Op::Sum, seq(vec![i(1), d(dec!(0.5))])), Ok(d(dec!(1.5)))),
+            ((Op::Sum, seq(vec![])), Ok(i(0))),
+            ((Op::Sum, seq(vec![i(1), s("2")])), Err(Error::CannotConvert(ValueKind::String))),
+            ((Op::Product, seq(vec![i(2), i(3), d(dec!(0.5))])), Ok(d(dec!(3.0)))),
+            ((Op::Product, seq(vec![])), Ok(i(1))),
+            ((Op::Sum, seq(vec![i(i64::MAX), i(1)])), Err(Error::Arithmetic(NumberError::Overflow))),
+            ((Op::Product, seq(vec![i(i64::MAX), i(2)])), Err(Error::Arithmetic(NumberError::Overflow))),
+            ((Op::Mean, seq(vec![i(i64::MAX), i(i64::MAX)])), Ok(d(Decimal::from(i64::MAX)))),
+            ((Op::Mean, seq(vec![i(1), i(2)])), Ok(d(dec!(1.5)))),
+            ((Op::Mean, seq(vec![])), Ok(Value::Null)),
+            ((Op::Mean, seq(vec![Value::Null])), Err(Error::CannotConvert(ValueKind::Null))),
+            ((Op::Median, seq(vec![i(3), i(1), d(dec!(2.5))])), Ok(d(dec!(2.5)))),
+            ((Op::Median, seq(vec![i(4), i(1), i(3), i(2)])), Ok(d(dec!(2.5)))),
+            ((Op::Median, seq(vec![])), Ok(Value::Null)),
+            ((Op::Join(str!(", ")), seq(vec![s("a"), s("b")])), Ok(s("a, b"))),
+            ((Op::Join(str!(", ")), seq(vec![s("a"), i(1)])), Err(Error::CannotConvert(ValueKind::Integer))),
+            ((Op::Min, seq(vec![i(3), d(dec!(1.5)), i(2)])), Ok(d(dec!(1.5)))),
+            ((Op::Min, seq(vec![i(1), d(dec!(1.0))])), Ok(i(1))),
+            ((Op::Min, seq(vec![])), Ok(Value::Null)),
+            ((Op::Max, seq(vec![i(3), s("a"), i(2)])), Ok(s("a"))),
+            ((Op::Max, seq(vec![i(1), d(dec!(1.0))])), Ok(d(dec!(1.0)))),
+            ((Op::Count, i(1)), Err(Error::CannotConvert(ValueKind::Integer))),
+        ];
+
+        for ((op, input), expected) in inputs_and_expected {
+            let produced = op.apply(input);
+            assert_eq!(expected, produced, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn apply_all() {
+        let input = seq(vec![
+            seq(vec![Value::from("b"), Value::from("a")]),
+            Value::from("b"),
+            seq(vec![Value::from("c")]),
+        ]);
+
+        let ops = [Op::Flatten, Op::Dedupe, Op::Sort, Op::Join(str!("/"))];
+        assert_eq!(Op::apply_all(&ops, input.clone()), Ok(Value::from("a/b/c")));
+
+        let ops = [Op::Flatten, Op::Count];
+        assert_eq!(Op::apply_all(&ops, input.clone()), Ok(Value::Integer(4)));
+
+        // Operators that do not output a sequence cannot be followed by more operators.
+        let ops = [Op::Flatten, Op::First, Op::Count];
+        assert_eq!(Op::apply_all(&ops, input), Err(Error::CannotConvert(ValueKind::String)));
+
+        let ops = [Op::Max];
+        let input = seq(vec![
+            Value::Mapping(Block(btreemap![str!("a") => Value::Integer(1)])),
+            Value::Mapping(Block(btreemap![str!("a") => Value::Integer(2)])),
+        ]);
+        assert_eq!(
+            Op::apply_all(&ops, input),
+            Ok(Value::Mapping(Block(btreemap![str!("a") => Value::Integer(2)]))),
+        );
+    }
+
+    #[test]
+    fn deserialize() {
+        let produced = serde_json::from_str::<Vec<Op>>(r#"["flatten", "dedupe", {"join": ", "}]"#).unwrap();
+        assert_eq!(produced, vec![Op::Flatten, Op::Dedupe, Op::Join(str!(", "))]);
+    }
+}
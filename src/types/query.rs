@@ -0,0 +1,527 @@
+//! Queries that select values nested inside of metadata values by path, such
+//! as `credits.performers[0].name`, `tracks[*].title`, or `genre[-1]`.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use thiserror::Error;
+
+use crate::types::{Block, Value};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("query is empty")]
+    Empty,
+    #[error("unexpected end of query")]
+    UnexpectedEnd,
+    #[error("unexpected character '{0}' at position {1}")]
+    UnexpectedChar(char, usize),
+    #[error(r#"invalid index "{0}" at position {1}"#)]
+    InvalidIndex(String, usize),
+}
+
+/// A single step of a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    /// Selects the value at a key of a mapping.
+    Key(String),
+
+    /// Selects the element at an index of a sequence. Negative indices count
+    /// backwards from the end of the sequence, so `-1` is the last element.
+    Index(i64),
+
+    /// Selects every element of a sequence, or every value of a mapping.
+    Wildcard,
+
+    /// Selects the elements of a sequence from a start index up to, but not
+    /// including, an end index. Missing bounds default to the start and end of
+    /// the sequence, negative bounds count backwards from the end, and bounds
+    /// past the ends of the sequence are clamped.
+    Slice(Option<i64>, Option<i64>),
+}
+
+/// A parsed query, which selects all of the values that can be reached by
+/// following its segments in order.
+///
+/// Keys are separated by dots, and may contain any characters other than dots
+/// and brackets. Keys with other characters can be written in quotes inside of
+/// brackets, such as `["key.with.dots"]`. Brackets otherwise contain an index,
+/// a slice such as `1:3` or `:-1`, or a `*` wildcard. A `*` in place of a key
+/// is also a wildcard.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Query(Vec<Segment>);
+
+/// A single step of a concrete path to a matched value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// The concrete path to a value matched by a query, with every wildcard and
+/// slice resolved to the key or index that was followed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValuePath(pub Vec<Step>);
+
+/// A value matched by a query, along with its concrete path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'v> {
+    pub path: ValuePath,
+    pub value: &'v Value,
+}
+
+impl Query {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        Self(segments)
+    }
+
+    pub fn parse(query: &str) -> Result<Self, Error> {
+        Parser::new(query).parse()
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    /// Finds all values matched by this query inside of a value, in order.
+    /// Mappings are visited in key order, and sequences in index order.
+    pub fn eval<'v>(&self, value: &'v Value) -> Vec<Match<'v>> {
+        let mut matches = Vec::new();
+        Self::visit(&self.0, value, &mut Vec::new(), &mut matches);
+        matches
+    }
+
+    /// Finds all values matched by this query inside of a block, which is
+    /// treated as a mapping.
+    pub fn eval_block<'b>(&self, block: &'b Block) -> Vec<Match<'b>> {
+        let mut matches = Vec::new();
+
+        if let Some((segment, rest)) = self.0.split_first() {
+            let mut path = Vec::new();
+
+            for (step, child) in Self::mapping_children(segment, block) {
+                path.push(step);
+                Self::visit(rest, child, &mut path, &mut matches);
+                path.pop();
+            }
+        }
+
+        matches
+    }
+
+    /// Returns the first value matched by this query inside of a value.
+    pub fn first<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        self.eval(value).into_iter().next().map(|m| m.value)
+    }
+
+    fn visit<'v>(segments: &[Segment], value: &'v Value, path: &mut Vec<Step>, matches: &mut Vec<Match<'v>>) {
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                matches.push(Match { path: ValuePath(path.clone()), value });
+                return;
+            },
+        };
+
+        let children = match value {
+            Value::Mapping(block) => Self::mapping_children(segment, block),
+            Value::Sequence(seq) => Self::sequence_children(segment, seq),
+            _ => Vec::new(),
+        };
+
+        for (step, child) in children {
+            path.push(step);
+            Self::visit(rest, child, path, matches);
+            path.pop();
+        }
+    }
+
+    fn mapping_children<'v>(segment: &Segment, block: &'v Block) -> Vec<(Step, &'v Value)> {
+        match segment {
+            Segment::Key(key) => {
+                block.get_full(key).map(|(k, v)| (Step::Key(k.clone()), v)).into_iter().collect()
+            },
+            Segment::Wildcard => block.iter().map(|(k, v)| (Step::Key(k.clone()), v)).collect(),
+            Segment::Index(_) | Segment::Slice(..) => Vec::new(),
+        }
+    }
+
+    fn sequence_children<'v>(segment: &Segment, seq: &'v [Value]) -> Vec<(Step, &'v Value)> {
+        let len = seq.len() as i64;
+
+        // Negative positions count backwards from the end of the sequence.
+        let resolve = |pos: i64| if pos < 0 { pos + len } else { pos };
+
+        let range = match segment {
+            Segment::Key(_) => return Vec::new(),
+            Segment::Index(index) => {
+                let index = resolve(*index);
+
+                if index < 0 || index >= len {
+                    return Vec::new();
+                }
+
+                index..index + 1
+            },
+            Segment::Wildcard => 0..len,
+            Segment::Slice(start, end) => {
+                let start = start.map(resolve).unwrap_or(0).clamp(0, len);
+                let end = end.map(resolve).unwrap_or(len).clamp(0, len);
+
+                start..end.max(start)
+            },
+        };
+
+        range
+            .map(|index| (Step::Index(index as usize), &seq[index as usize]))
+            .collect()
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl From<&ValuePath> for Query {
+    fn from(path: &ValuePath) -> Self {
+        let segments = path.0.iter().map(|step| match step {
+            Step::Key(key) => Segment::Key(key.clone()),
+            Step::Index(index) => Segment::Index(*index as i64),
+        });
+
+        Self(segments.collect())
+    }
+}
+
+/// Returns whether a key can be written without quotes.
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key != "*" && !key.contains(['.', '[', ']', '"'])
+}
+
+fn fmt_key(f: &mut Formatter<'_>, key: &str, is_first: bool) -> FmtResult {
+    if is_bare_key(key) {
+        if !is_first {
+            write!(f, ".")?;
+        }
+
+        write!(f, "{}", key)
+    } else {
+        write!(f, "[\"{}\"]", key.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) => fmt_key(f, key, i == 0)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::Wildcard => write!(f, "[*]")?,
+                Segment::Slice(start, end) => {
+                    write!(f, "[")?;
+
+                    if let Some(start) = start {
+                        write!(f, "{}", start)?;
+                    }
+
+                    write!(f, ":")?;
+
+                    if let Some(end) = end {
+                        write!(f, "{}", end)?;
+                    }
+
+                    write!(f, "]")?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for ValuePath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (i, step) in self.0.iter().enumerate() {
+            match step {
+                Step::Key(key) => fmt_key(f, key, i == 0)?,
+                Step::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Parser<'q> {
+    query: &'q str,
+    chars: Peekable<CharIndices<'q>>,
+}
+
+impl<'q> Parser<'q> {
+    fn new(query: &'q str) -> Self {
+        Self { query, chars: query.char_indices().peekable() }
+    }
+
+    fn parse(mut self) -> Result<Query, Error> {
+        let mut segments = Vec::new();
+
+        // The first segment may be a key without a leading dot.
+        match self.chars.peek() {
+            None => return Err(Error::Empty),
+            Some((_, '[')) => {},
+            Some(_) => segments.push(self.parse_key()?),
+        }
+
+        while let Some((pos, c)) = self.chars.next() {
+            let segment = match c {
+                '.' => self.parse_key()?,
+                '[' => self.parse_bracket()?,
+                _ => return Err(Error::UnexpectedChar(c, pos)),
+            };
+
+            segments.push(segment);
+        }
+
+        Ok(Query(segments))
+    }
+
+    /// Parses a bare key or a `*` wildcard, up to the next dot or bracket.
+    fn parse_key(&mut self) -> Result<Segment, Error> {
+        let start = match self.chars.peek() {
+            None => return Err(Error::UnexpectedEnd),
+            Some(&(pos, c @ ('.' | '[' | ']' | '"'))) => return Err(Error::UnexpectedChar(c, pos)),
+            Some(&(pos, _)) => pos,
+        };
+
+        let mut end = self.query.len();
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            match c {
+                '.' | '[' => {
+                    end = pos;
+                    break;
+                },
+                ']' | '"' => return Err(Error::UnexpectedChar(c, pos)),
+                _ => { self.chars.next(); },
+            }
+        }
+
+        Ok(match &self.query[start..end] {
+            "*" => Segment::Wildcard,
+            key => Segment::Key(key.to_string()),
+        })
+    }
+
+    /// Parses the contents of a bracket after the opening bracket, up to and
+    /// including the closing bracket.
+    fn parse_bracket(&mut self) -> Result<Segment, Error> {
+        let segment = match self.chars.peek() {
+            None => return Err(Error::UnexpectedEnd),
+            Some((_, '"')) => Segment::Key(self.parse_quoted()?),
+            Some((_, '*')) => {
+                self.chars.next();
+                Segment::Wildcard
+            },
+            Some(_) => {
+                let start = self.parse_index()?;
+
+                match self.chars.peek() {
+                    Some((_, ':')) => {
+                        self.chars.next();
+                        Segment::Slice(start, self.parse_index()?)
+                    },
+                    _ => match start {
+                        Some(index) => Segment::Index(index),
+                        None => return Err(self.unexpected()),
+                    },
+                }
+            },
+        };
+
+        match self.chars.next() {
+            Some((_, ']')) => Ok(segment),
+            Some((pos, c)) => Err(Error::UnexpectedChar(c, pos)),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+
+    /// Parses an optional integer, up to the next colon or closing bracket.
+    fn parse_index(&mut self) -> Result<Option<i64>, Error> {
+        let start = match self.chars.peek() {
+            None => return Err(Error::UnexpectedEnd),
+            Some(&(pos, _)) => pos,
+        };
+
+        let mut end = self.query.len();
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            if c == ':' || c == ']' {
+                end = pos;
+                break;
+            }
+
+            self.chars.next();
+        }
+
+        let raw = self.query[start..end].trim();
+
+        if raw.is_empty() {
+            return Ok(None);
+        }
+
+        raw.parse::<i64>()
+            .map(Some)
+            .map_err(|_| Error::InvalidIndex(raw.to_string(), start))
+    }
+
+    /// Parses a quoted key, where quotes and backslashes are escaped with a backslash.
+    fn parse_quoted(&mut self) -> Result<String, Error> {
+        // Skip the opening quote.
+        self.chars.next();
+
+        let mut key = String::new();
+
+        loop {
+            match self.chars.next() {
+                None => return Err(Error::UnexpectedEnd),
+                Some((_, '"')) => return Ok(key),
+                Some((_, '\\')) => match self.chars.next() {
+                    None => return Err(Error::UnexpectedEnd),
+                    Some((_, c @ ('"' | '\\'))) => key.push(c),
+                    Some((pos, c)) => return Err(Error::UnexpectedChar(c, pos)),
+                },
+                Some((_, c)) => key.push(c),
+            }
+        }
+    }
+
+    fn unexpected(&mut self) -> Error {
+        match self.chars.peek() {
+            Some(&(pos, c)) => Error::UnexpectedChar(c, pos),
+            None => Error::UnexpectedEnd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    fn key(k: &str) -> Segment {
+        Segment::Key(k.to_string())
+    }
+
+    #[test]
+    fn parse() {
+        let inputs_and_expected = vec![
+            ("title", Ok(vec![key("title")])),
+            ("credits.performers[0].name", Ok(vec![key("credits"), key("performers"), Segment::Index(0), key("name")])),
+            ("tracks[*].title", Ok(vec![key("tracks"), Segment::Wildcard, key("title")])),
+            ("credits.*", Ok(vec![key("credits"), Segment::Wildcard])),
+            ("genre[-1]", Ok(vec![key("genre"), Segment::Index(-1)])),
+            ("[2]", Ok(vec![Segment::Index(2)])),
+            ("a[1:3]", Ok(vec![key("a"), Segment::Slice(Some(1), Some(3))])),
+            ("a[:-1]", Ok(vec![key("a"), Segment::Slice(None, Some(-1))])),
+            ("a[2:]", Ok(vec![key("a"), Segment::Slice(Some(2), None)])),
+            ("a[:]", Ok(vec![key("a"), Segment::Slice(None, None)])),
+            (r#"a["b.c"]["*"]"#, Ok(vec![key("a"), key("b.c"), key("*")])),
+            (r#"["say \"hi\""]"#, Ok(vec![key(r#"say "hi""#)])),
+            ("track title", Ok(vec![key("track title")])),
+            ("", Err(Error::Empty)),
+            ("a.", Err(Error::UnexpectedEnd)),
+            ("a..b", Err(Error::UnexpectedChar('.', 2))),
+            (".a", Err(Error::UnexpectedChar('.', 0))),
+            ("a[0", Err(Error::UnexpectedEnd)),
+            ("a[]", Err(Error::UnexpectedChar(']', 2))),
+            ("a[x]", Err(Error::InvalidIndex(str!("x"), 2))),
+            ("a[0]b", Err(Error::UnexpectedChar('b', 4))),
+            ("a]", Err(Error::UnexpectedChar(']', 1))),
+            (r#"a["b"c]"#, Err(Error::UnexpectedChar('c', 5))),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = Query::parse(input).map(|q| q.0);
+            assert_eq!(expected, produced, "{}", input);
+        }
+    }
+
+    #[test]
+    fn display() {
+        for input in ["credits.performers[0].name", "tracks[*].title", "a[1:-1][:2][3:]", r#"a["b.c"]["*"]"#] {
+            assert_eq!(input, Query::parse(input).unwrap().to_string());
+        }
+
+        assert_eq!("a[*]", Query::parse("a.*").unwrap().to_string());
+    }
+
+    #[test]
+    fn eval() {
+        let name = |n: &str| Value::Mapping(Block(btreemap![str!("name") => Value::from(n)]));
+        let value = Value::Mapping(Block(btreemap![
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("performers") => Value::Sequence(vec![name("Alice"), name("Bob"), name("Carol")]),
+            ])),
+            str!("genre") => Value::Sequence(vec![Value::from("rock"), Value::from("pop")]),
+            str!("year") => Value::Integer(2000),
+        ]));
+
+        let eval = |query: &str| {
+            Query::parse(query)
+                .unwrap()
+                .eval(&value)
+                .into_iter()
+                .map(|m| (m.path.to_string(), m.value.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(eval("credits.performers[0].name"), vec![
+            (str!("credits.performers[0].name"), Value::from("Alice")),
+        ]);
+        assert_eq!(eval("credits.performers[*].name"), vec![
+            (str!("credits.performers[0].name"), Value::from("Alice")),
+            (str!("credits.performers[1].name"), Value::from("Bob")),
+            (str!("credits.performers[2].name"), Value::from("Carol")),
+        ]);
+        assert_eq!(eval("credits.performers[-1].name"), vec![
+            (str!("credits.performers[2].name"), Value::from("Carol")),
+        ]);
+        assert_eq!(eval("credits.performers[1:].name"), vec![
+            (str!("credits.performers[1].name"), Value::from("Bob")),
+            (str!("credits.performers[2].name"), Value::from("Carol")),
+        ]);
+        assert_eq!(eval("credits.performers[-10:1].name"), vec![
+            (str!("credits.performers[0].name"), Value::from("Alice")),
+        ]);
+        assert_eq!(eval("genre[-1]"), vec![(str!("genre[1]"), Value::from("pop"))]);
+        assert_eq!(eval("*[0]"), vec![(str!("genre[0]"), Value::from("rock"))]);
+
+        // Missing keys, out of range indices, empty slices, and mismatched
+        // segments match nothing.
+        for query in ["missing", "genre[2]", "genre[-3]", "genre[2:1]", "genre.name", "year[0]", "year.*", "credits[0]"] {
+            assert_eq!(eval(query), vec![], "{}", query);
+        }
+
+        let block = match &value {
+            Value::Mapping(block) => block,
+            _ => unreachable!(),
+        };
+
+        let produced = Query::parse("genre[*]").unwrap().eval_block(block);
+        assert_eq!(produced, vec![
+            Match { path: ValuePath(vec![Step::Key(str!("genre")), Step::Index(0)]), value: &Value::from("rock") },
+            Match { path: ValuePath(vec![Step::Key(str!("genre")), Step::Index(1)]), value: &Value::from("pop") },
+        ]);
+
+        // Concrete paths can be used as queries.
+        let query = Query::from(&produced[1].path);
+        assert_eq!(query.first(&value), Some(&Value::from("pop")));
+    }
+}
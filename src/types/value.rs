@@ -1,5 +1,6 @@
 //! Primitive metadata value types.
 
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 
 pub use rust_decimal::Decimal;
@@ -9,12 +10,14 @@ use serde::Serialize;
 use strum::{EnumDiscriminants, AsRefStr};
 use thiserror::Error;
 
-use crate::types::{Block, Number};
+use crate::types::{Block, DateTime, Number, NumberError};
 
 #[derive(Debug, Error, Copy, Clone, PartialEq, Hash)]
 pub enum Error {
     #[error("cannot convert value of kind {} into target type", .0.as_ref())]
     CannotConvert(ValueKind),
+    #[error("{0}")]
+    Arithmetic(#[from] NumberError),
 }
 
 // Re-exporting to allow downstream users to ensure usage of the correct types.
@@ -23,12 +26,26 @@ pub type Boolean = bool;
 pub type Sequence = Vec<Value>;
 
 /// Represents the types of data that can be used as metadata values.
-#[derive(Debug, Clone, Deserialize, Serialize, EnumDiscriminants)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+///
+/// When deserializing, only native TOML datetimes are read as datetimes, and
+/// strings are kept as strings, unless a source is set to coerce dates. Native
+/// TOML times of day without a date are read as strings of their text.
+/// Datetimes are serialized as strings.
+///
+/// Equality between values is structural, so an integer and a decimal with the
+/// same numerical value are not equal. Use `val_eq` to compare numerically.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumDiscriminants)]
 #[serde(untagged)]
 #[strum_discriminants(name(ValueKind), derive(Hash, AsRefStr))]
 pub enum Value {
     Null,
+    // This needs to be tried before mappings when deserializing, since native
+    // TOML datetimes are presented as mappings with a special key.
+    #[serde(deserialize_with = "crate::types::datetime::deserialize_native")]
+    DateTime(DateTime),
+    // Native TOML datetimes that are not datetimes are kept as their text,
+    // instead of being read as mappings with a special key.
+    #[serde(deserialize_with = "crate::types::datetime::deserialize_string_or_native")]
     String(String),
     Integer(i64),
     Boolean(bool),
@@ -60,6 +77,90 @@ impl Value {
         // The remaining current value is what is needed to return.
         Some(curr_val)
     }
+
+    /// Converts the strings in this value that are dates or datetimes into
+    /// datetimes, including strings inside of sequences and mappings.
+    pub(crate) fn coerce_dates(&mut self) {
+        match self {
+            Self::String(s) => {
+                if let Some(dt) = DateTime::coerce(s) {
+                    *self = Self::DateTime(dt);
+                }
+            },
+            Self::Sequence(seq) => seq.iter_mut().for_each(Self::coerce_dates),
+            Self::Mapping(block) => block.coerce_dates(),
+            _ => {},
+        }
+    }
+
+    /// Compares two values for equality. Integers and decimals are compared
+    /// based on their numerical values, so `1` and `1.0` are equal.
+    pub fn val_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::String(l), Self::String(r)) => l == r,
+            (Self::Boolean(l), Self::Boolean(r)) => l == r,
+            (Self::Integer(l), Self::Integer(r)) => l == r,
+            (Self::Decimal(l), Self::Decimal(r)) => l == r,
+            (Self::DateTime(l), Self::DateTime(r)) => l.val_eq(r),
+            (Self::Integer(i), Self::Decimal(d)) | (Self::Decimal(d), Self::Integer(i)) => {
+                Number::Integer(*i).val_eq(&Number::Decimal(*d))
+            },
+            (Self::Sequence(l), Self::Sequence(r)) => {
+                l.len() == r.len() && l.iter().zip(r).all(|(lv, rv)| lv.val_eq(rv))
+            },
+            (Self::Mapping(l), Self::Mapping(r)) => {
+                l.len() == r.len()
+                    && l.iter().all(|(k, lv)| r.get(k).map(|rv| lv.val_eq(rv)).unwrap_or(false))
+            },
+            _ => false,
+        }
+    }
+
+    /// Compares two values using a total ordering, which is consistent with
+    /// `val_eq`. Values of different kinds are ordered as null, booleans,
+    /// numbers, datetimes, strings, sequences, and then mappings. Integers and
+    /// decimals are ordered together based on their numerical values, and
+    /// datetimes are ordered chronologically. Sequences are
+    /// ordered lexicographically, and mappings are ordered lexicographically
+    /// by their entries in key order.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Boolean(_) => 1,
+                Value::Integer(_) | Value::Decimal(_) => 2,
+                Value::DateTime(_) => 3,
+                Value::String(_) => 4,
+                Value::Sequence(_) => 5,
+                Value::Mapping(_) => 6,
+            }
+        }
+
+        match (self, other) {
+            (Self::Boolean(l), Self::Boolean(r)) => l.cmp(r),
+            (Self::String(l), Self::String(r)) => l.cmp(r),
+            (Self::DateTime(l), Self::DateTime(r)) => l.val_cmp(r),
+            (Self::Sequence(l), Self::Sequence(r)) => {
+                l.iter()
+                    .zip(r)
+                    .map(|(lv, rv)| lv.total_cmp(rv))
+                    .find(|o| o.is_ne())
+                    .unwrap_or_else(|| l.len().cmp(&r.len()))
+            },
+            (Self::Mapping(l), Self::Mapping(r)) => {
+                l.iter()
+                    .zip(r.iter())
+                    .map(|((lk, lv), (rk, rv))| lk.cmp(rk).then_with(|| lv.total_cmp(rv)))
+                    .find(|o| o.is_ne())
+                    .unwrap_or_else(|| l.len().cmp(&r.len()))
+            },
+            _ => match (Number::try_from(self), Number::try_from(other)) {
+                (Ok(l), Ok(r)) => l.val_cmp(&r),
+                _ => rank(self).cmp(&rank(other)),
+            },
+        }
+    }
 }
 
 #[cfg(test)]
@@ -199,6 +300,34 @@ impl<'k> TryFrom<&'k Value> for Decimal {
     }
 }
 
+impl From<DateTime> for Value {
+    fn from(value: DateTime) -> Self {
+        Self::DateTime(value)
+    }
+}
+
+impl TryFrom<Value> for DateTime {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::DateTime(dt) => Ok(dt),
+            _ => Err(Error::CannotConvert(value.into())),
+        }
+    }
+}
+
+impl<'k> TryFrom<&'k Value> for DateTime {
+    type Error = Error;
+
+    fn try_from(value: &'k Value) -> Result<Self, Self::Error> {
+        match value {
+            &Value::DateTime(dt) => Ok(dt),
+            _ => Err(Error::CannotConvert(value.into())),
+        }
+    }
+}
+
 impl From<Sequence> for Value {
     fn from(value: Sequence) -> Self {
         Self::Sequence(value)
@@ -291,6 +420,8 @@ mod tests {
             ("-3.1415", Value::Decimal(dec!(-3.1415))),
             ("true", Value::Boolean(true)),
             ("false", Value::Boolean(false)),
+            (r#""2019-08""#, Value::String(str!("2019-08"))),
+            (r#""1999""#, Value::String(str!("1999"))),
             (
                 r#"[null, "string", 27, true]"#,
                 Value::Sequence(vec![
@@ -326,6 +457,9 @@ mod tests {
             ("-3.1415", Value::Decimal(dec!(-3.1415))),
             ("true", Value::Boolean(true)),
             ("false", Value::Boolean(false)),
+            ("2019", Value::Integer(2019)),
+            ("2019-08-20", Value::String(str!("2019-08-20"))),
+            ("'2019-08-20T10:30:00Z'", Value::String(str!("2019-08-20T10:30:00Z"))),
             (
                 r#"[null, "string", 27, true]"#,
                 Value::Sequence(vec![
@@ -366,6 +500,131 @@ mod tests {
             let produced = serde_yaml::from_str::<Value>(&input).unwrap();
             assert_eq!(expected, produced);
         }
+
+        let dt = |s| Value::DateTime(DateTime::parse(s).unwrap());
+
+        let inputs_and_expected = vec![
+            ("'string'", Value::String(str!("string"))),
+            ("'2019-08-20'", Value::String(str!("2019-08-20"))),
+            ("2019-08-20", dt("2019-08-20")),
+            ("1979-05-27T07:32:00Z", dt("1979-05-27T07:32:00Z")),
+            ("1979-05-27 07:32:00Z", dt("1979-05-27T07:32:00Z")),
+            ("1979-05-27t07:32:00z", dt("1979-05-27T07:32:00Z")),
+            ("1979-05-27T07:32:00.1234567891", dt("1979-05-27T07:32:00.123456789")),
+            ("07:32:00", Value::String(str!("07:32:00"))),
+            ("[07:32:00.5, 1979-05-27]", Value::Sequence(vec![Value::String(str!("07:32:00.5")), dt("1979-05-27")])),
+            ("{ t = 07:32:00 }", Value::Mapping(Block(btreemap![str!("t") => Value::String(str!("07:32:00"))]))),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let produced = toml::from_str::<Block>(&format!("key = {}", input)).unwrap();
+            assert_eq!(Some(&expected), produced.get("key"), "{}", input);
+        }
+    }
+
+    #[test]
+    fn serialize() {
+        let value = Value::Sequence(vec![
+            Value::DateTime(DateTime::parse("2019-08").unwrap()),
+            Value::DateTime(DateTime::parse("2019-08-20T10:30:00.50+02:00").unwrap()),
+        ]);
+
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(r#"["2019-08","2019-08-20T10:30:00.50+02:00"]"#, json);
+
+        // Datetimes are written as strings, which are read back as strings.
+        let expected = Value::Sequence(vec![
+            Value::from("2019-08"),
+            Value::from("2019-08-20T10:30:00.50+02:00"),
+        ]);
+        assert_eq!(expected, serde_json::from_str::<Value>(&json).unwrap());
+
+        let yaml = serde_yaml::to_string(&value).unwrap();
+        assert_eq!(expected, serde_yaml::from_str::<Value>(&yaml).unwrap());
+
+        // Strings that look like years are never read as datetimes.
+        assert_eq!(
+            serde_json::from_str::<Value>(r#"{"title": "1999"}"#).unwrap(),
+            Value::Mapping(Block(btreemap![str!("title") => Value::from("1999")])),
+        );
+    }
+
+    #[test]
+    fn val_eq() {
+        let inputs_and_expected = vec![
+            ((Value::Null, Value::Null), true),
+            ((Value::from("a"), Value::from("a")), true),
+            ((Value::from("a"), Value::from("b")), false),
+            ((Value::Integer(1), Value::Decimal(dec!(1.0))), true),
+            ((Value::Decimal(dec!(1.5)), Value::Integer(1)), false),
+            ((Value::Boolean(true), Value::from("true")), false),
+            (
+                (
+                    Value::from(vec![Value::Integer(1), Value::from("a")]),
+                    Value::from(vec![Value::Decimal(dec!(1)), Value::from("a")]),
+                ),
+                true,
+            ),
+            (
+                (
+                    Value::from(vec![Value::Integer(1)]),
+                    Value::from(vec![Value::Integer(1), Value::Integer(1)]),
+                ),
+                false,
+            ),
+            (
+                (
+                    Value::from(Block(btreemap![str!("a") => Value::Integer(1)])),
+                    Value::from(Block(btreemap![str!("a") => Value::Decimal(dec!(1.00))])),
+                ),
+                true,
+            ),
+            (
+                (
+                    Value::from(Block(btreemap![str!("a") => Value::Integer(1)])),
+                    Value::from(Block(btreemap![str!("b") => Value::Integer(1)])),
+                ),
+                false,
+            ),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (l, r) = input;
+            assert_eq!(expected, l.val_eq(&r));
+            assert_eq!(expected, r.val_eq(&l));
+        }
+    }
+
+    #[test]
+    fn total_cmp() {
+        let ordered = vec![
+            Value::Null,
+            Value::Boolean(false),
+            Value::Boolean(true),
+            Value::Integer(-1),
+            Value::Decimal(dec!(0.5)),
+            Value::Integer(1),
+            Value::DateTime(DateTime::parse("2019").unwrap()),
+            Value::DateTime(DateTime::parse("2019-08-20").unwrap()),
+            Value::from(""),
+            Value::from("a"),
+            Value::from(vec![]),
+            Value::from(vec![Value::Integer(1)]),
+            Value::from(vec![Value::Integer(1), Value::Null]),
+            Value::from(vec![Value::Integer(2)]),
+            Value::from(Block(btreemap![])),
+            Value::from(Block(btreemap![str!("a") => Value::Integer(2)])),
+            Value::from(Block(btreemap![str!("b") => Value::Integer(1)])),
+        ];
+
+        for (i, l) in ordered.iter().enumerate() {
+            for (j, r) in ordered.iter().enumerate() {
+                assert_eq!(i.cmp(&j), l.total_cmp(r), "{:?} {:?}", l, r);
+            }
+        }
+
+        // Integers and decimals with the same numerical value are equal.
+        assert_eq!(Ordering::Equal, Value::Integer(1).total_cmp(&Value::Decimal(dec!(1.00))));
     }
 
     #[test]
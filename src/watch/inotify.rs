@@ -0,0 +1,135 @@
+//! Minimal bindings to the Linux inotify API.
+
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult};
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+pub const IN_ATTRIB: u32 = 0x0000_0004;
+pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub const IN_MOVED_TO: u32 = 0x0000_0080;
+pub const IN_CREATE: u32 = 0x0000_0100;
+pub const IN_DELETE: u32 = 0x0000_0200;
+pub const IN_DELETE_SELF: u32 = 0x0000_0400;
+pub const IN_MOVE_SELF: u32 = 0x0000_0800;
+pub const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+pub const IN_IGNORED: u32 = 0x0000_8000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+
+const IN_CLOEXEC: c_int = 0o2_000_000;
+
+/// The size of the fixed part of an event: the watch descriptor, mask, cookie,
+/// and name length.
+const EVENT_HEADER_LEN: usize = 16;
+
+/// Enough space for many events at once, with a maximum-length name.
+const BUFFER_LEN: usize = 64 * (EVENT_HEADER_LEN + 256);
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+}
+
+/// A watch descriptor, which identifies a watched directory.
+pub type WatchDescriptor = i32;
+
+/// A single filesystem event.
+#[derive(Debug)]
+pub struct Event {
+    pub wd: WatchDescriptor,
+    pub mask: u32,
+
+    /// The name of the entry inside the watched directory that the event is
+    /// about, or empty if the event is about the watched directory itself.
+    pub name: Vec<u8>,
+}
+
+impl Event {
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(&self.name)
+    }
+}
+
+pub struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    pub fn new() -> IoResult<Self> {
+        // SAFETY: `inotify_init1` has no memory safety preconditions.
+        let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        // SAFETY: The file descriptor was just created, and is owned by nothing else.
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        Ok(Self { file })
+    }
+
+    /// Adds a watch for a directory, or updates the existing watch for it.
+    pub fn add_watch(&self, path: &Path, mask: u32) -> IoResult<WatchDescriptor> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| IoError::new(IoErrorKind::InvalidInput, err))?;
+
+        // SAFETY: The path is a valid null-terminated string that outlives the call.
+        let wd = unsafe { inotify_add_watch(self.raw_fd(), c_path.as_ptr(), mask) };
+
+        if wd < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(wd)
+    }
+
+    /// Blocks until at least one event is available, and reads all available events.
+    pub fn read_events(&mut self) -> IoResult<Vec<Event>> {
+        let mut buffer = vec![0u8; BUFFER_LEN];
+
+        let len = loop {
+            match self.file.read(&mut buffer) {
+                Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        };
+
+        let mut events = Vec::new();
+        let mut offset = 0;
+
+        while offset + EVENT_HEADER_LEN <= len {
+            let field = |i: usize| {
+                let start = offset + i * 4;
+                [buffer[start], buffer[start + 1], buffer[start + 2], buffer[start + 3]]
+            };
+
+            let wd = i32::from_ne_bytes(field(0));
+            let mask = u32::from_ne_bytes(field(1));
+            let name_len = u32::from_ne_bytes(field(3)) as usize;
+
+            let name_start = offset + EVENT_HEADER_LEN;
+            let name_end = (name_start + name_len).min(len);
+
+            // Names are padded with null bytes.
+            let name = buffer[name_start..name_end]
+                .iter()
+                .take_while(|&&b| b != 0)
+                .copied()
+                .collect();
+
+            events.push(Event { wd, mask, name });
+            offset = name_end;
+        }
+
+        Ok(events)
+    }
+
+    fn raw_fd(&self) -> c_int {
+        self.file.as_raw_fd()
+    }
+}
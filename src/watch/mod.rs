@@ -0,0 +1,670 @@
+//! Watching of a library for changes to item files and meta files, and
+//! incremental loading of the metadata of the affected item files.
+
+#[cfg(target_os = "linux")]
+mod inotify;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use thiserror::Error;
+
+use crate::library::Library;
+use crate::metadata::processor::Error as ProcessorError;
+use crate::sources::Anchor;
+use crate::types::Block;
+use crate::util::file_walker::ChildFileWalker;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot start watching: {0}")]
+    CannotInit(#[source] IoError),
+    #[error(r#"cannot watch directory "{}": {1}"#, .0.display())]
+    CannotWatch(PathBuf, #[source] IoError),
+    #[error("cannot read filesystem events: {0}")]
+    CannotRead(#[source] IoError),
+}
+
+/// Describes how the metadata of an item file changed.
+#[derive(Debug)]
+pub struct Change {
+    pub item_path: PathBuf,
+
+    /// The previously loaded metadata, or `None` if the item file was not
+    /// known before, or its metadata could not be loaded.
+    pub old: Option<Block>,
+
+    /// The newly loaded metadata, or `None` if the item file no longer exists
+    /// or is no longer selected.
+    pub new: Result<Option<Block>, ProcessorError>,
+}
+
+/// Keeps track of the metadata of the item files in a library, and of which
+/// meta files each item file uses, in order to find the item files that are
+/// affected by changes to paths in the library and load their metadata again.
+pub struct Tracker {
+    library: Library,
+
+    /// The last loaded metadata of each known item file, or `None` if it could
+    /// not be loaded.
+    blocks: HashMap<PathBuf, Option<Block>>,
+
+    item_metas: HashMap<PathBuf, Vec<PathBuf>>,
+    meta_items: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl Tracker {
+    /// Creates a new tracker, and loads the metadata of every selected item
+    /// file in the library.
+    pub fn new(library: Library) -> Self {
+        let threads = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        let processed = library.process_parallel(threads);
+
+        let mut tracker = Self {
+            library,
+            blocks: HashMap::new(),
+            item_metas: HashMap::new(),
+            meta_items: HashMap::new(),
+        };
+
+        for (item_path, res) in processed {
+            match res {
+                // Errors from walking refer to directories that are also processed.
+                Err(ProcessorError::CannotWalk(_)) => continue,
+                res => {
+                    tracker.reindex(&item_path);
+                    tracker.blocks.insert(item_path, res.ok());
+                },
+            }
+        }
+
+        tracker
+    }
+
+    pub fn library(&self) -> &Library {
+        &self.library
+    }
+
+    /// Returns the last loaded metadata of an item file, if it is known and
+    /// its metadata could be loaded.
+    pub fn block(&self, item_path: &Path) -> Option<&Block> {
+        self.blocks.get(item_path).and_then(Option::as_ref)
+    }
+
+    /// Returns the meta files that were used to load the metadata of an item file.
+    pub fn meta_paths(&self, item_path: &Path) -> &[PathBuf] {
+        self.item_metas.get(item_path).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Finds the item files whose metadata may be affected by a change to a
+    /// path, which may have been created, modified, or removed.
+    /// A changed meta file affects the item files that used it before, and the
+    /// item files that it provides metadata for now. A changed item file
+    /// affects itself, the item files that share meta files with it, and any
+    /// item files inside of it.
+    pub fn affected_item_paths(&self, changed_path: &Path) -> BTreeSet<PathBuf> {
+        let config = self.library.config();
+        let mut affected = BTreeSet::new();
+
+        // Item files that used the changed path as a meta file.
+        if let Some(item_paths) = self.meta_items.get(changed_path) {
+            affected.extend(item_paths.iter().cloned());
+        }
+
+        // Item files that the changed path provides metadata for, as a meta file.
+        for source in config.sourcer.as_sources() {
+            if let Anchor::Index = source.anchor {
+                // Index meta files do not list their item files without being
+                // parsed, so check the known item files instead.
+                if changed_path.file_name() == Some(OsStr::new(&source.name)) {
+                    let item_paths = self.blocks
+                        .keys()
+                        .filter(|item_path| source.meta_path(item_path).ok().as_deref() == Some(changed_path));
+
+                    affected.extend(item_paths.cloned());
+                }
+
+                continue;
+            }
+
+            if let Ok(item_paths) = source.selected_item_paths(changed_path, &config.selection) {
+                let item_paths = item_paths
+                    .filter_map(Result::ok)
+                    .filter(|item_path| self.is_selected_item(item_path))
+                    .filter(|item_path| source.meta_path(item_path).ok().as_deref() == Some(changed_path));
+
+                affected.extend(item_paths.map(|item_path| item_path.into_owned()));
+            }
+        }
+
+        // The changed path itself, as an item file.
+        let was_item = self.blocks.contains_key(changed_path);
+        let is_item = self.is_selected_item(changed_path);
+
+        if !was_item && !is_item {
+            return affected;
+        }
+
+        affected.insert(changed_path.to_path_buf());
+
+        // Item files that share meta files with the changed item file, such
+        // as the siblings covered by the same sequence of meta blocks.
+        let mut meta_paths = self.meta_paths(changed_path).to_vec();
+
+        if is_item {
+            meta_paths.extend(
+                config.sourcer
+                    .meta_paths_within(changed_path, self.library.root_dir())
+                    .filter_map(Result::ok)
+                    .map(|(mp, _)| mp)
+            );
+        }
+
+        for meta_path in meta_paths {
+            if let Some(item_paths) = self.meta_items.get(&meta_path) {
+                affected.extend(item_paths.iter().cloned());
+            }
+        }
+
+        // Item files inside of the changed item file, if it is a directory.
+        if was_item {
+            affected.extend(self.blocks.keys().filter(|p| p.starts_with(changed_path)).cloned());
+        }
+
+        if is_item && changed_path.is_dir() {
+            let mut walker = ChildFileWalker::new(changed_path);
+
+            while let Some(path_res) = walker.next() {
+                if let Ok(path) = path_res {
+                    affected.insert(path.into_owned());
+                    walker.delve(&config.selection, &config.sorter).ok();
+                }
+            }
+        }
+
+        affected
+    }
+
+    /// Finds the meta files whose cached results may be stale after a change
+    /// to a path. Besides the changed path itself as a meta file, adding or
+    /// removing an item file changes how the meta files it uses are plexed.
+    fn stale_meta_paths(&self, changed_path: &Path) -> BTreeSet<PathBuf> {
+        let mut stale = BTreeSet::new();
+        stale.insert(changed_path.to_path_buf());
+        stale.extend(self.meta_paths(changed_path).iter().cloned());
+
+        if self.is_selected_item(changed_path) {
+            stale.extend(
+                self.library
+                    .config()
+                    .sourcer
+                    .meta_paths_within(changed_path, self.library.root_dir())
+                    .filter_map(Result::ok)
+                    .map(|(meta_path, _)| meta_path)
+            );
+        }
+
+        stale
+    }
+
+    /// Loads the metadata of the item files affected by changes to paths
+    /// again, and returns the changes in item file path order.
+    /// Only the cached meta files that may be stale are read again.
+    pub fn update<I, P>(&mut self, changed_paths: I) -> Vec<Change>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut affected = BTreeSet::new();
+        let mut stale = BTreeSet::new();
+
+        for changed_path in changed_paths {
+            let changed_path = changed_path.as_ref();
+
+            affected.append(&mut self.affected_item_paths(changed_path));
+            stale.append(&mut self.stale_meta_paths(changed_path));
+        }
+
+        for meta_path in &stale {
+            self.library.invalidate_cache(meta_path);
+        }
+
+        affected.into_iter().map(|item_path| self.reload(item_path)).collect()
+    }
+
+    fn reload(&mut self, item_path: PathBuf) -> Change {
+        let old = self.blocks.remove(&item_path).flatten();
+
+        let new = if self.is_selected_item(&item_path) {
+            let res = self.library.get(&item_path);
+
+            self.reindex(&item_path);
+            self.blocks.insert(item_path.clone(), res.as_ref().ok().cloned());
+
+            res.map(Some)
+        } else {
+            self.unindex(&item_path);
+            Ok(None)
+        };
+
+        Change { item_path, old, new }
+    }
+
+    /// Returns whether a path is an existing item file that is selected, along
+    /// with all of its ancestors inside of the library.
+    fn is_selected_item(&self, path: &Path) -> bool {
+        let root_dir = self.library.root_dir();
+        let selection = &self.library.config().selection;
+
+        let is_sidecar = || match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => selection
+                .sidecar_names_in_dir(parent)
+                .is_ok_and(|names| names.contains(name)),
+            _ => false,
+        };
+
+        path.starts_with(root_dir)
+            && path.exists()
+            && path
+                .ancestors()
+                .take_while(|p| *p != root_dir)
+                .all(|p| selection.is_selected(&p).unwrap_or(false))
+            && !is_sidecar()
+    }
+
+    fn reindex(&mut self, item_path: &Path) {
+        self.unindex(item_path);
+
+        let meta_paths = self.library
+            .config()
+            .sourcer
+            .meta_paths_within(item_path, self.library.root_dir())
+            .filter_map(Result::ok)
+            .map(|(meta_path, _)| meta_path)
+            .collect::<Vec<_>>();
+
+        for meta_path in &meta_paths {
+            self.meta_items.entry(meta_path.clone()).or_default().insert(item_path.to_path_buf());
+        }
+
+        self.item_metas.insert(item_path.to_path_buf(), meta_paths);
+    }
+
+    fn unindex(&mut self, item_path: &Path) {
+        for meta_path in self.item_metas.remove(item_path).unwrap_or_default() {
+            if let Some(item_paths) = self.meta_items.get_mut(&meta_path) {
+                item_paths.remove(item_path);
+
+                if item_paths.is_empty() {
+                    self.meta_items.remove(&meta_path);
+                }
+            }
+        }
+    }
+}
+
+/// Watches a library for filesystem changes using inotify, and reports how
+/// the metadata of the affected item files changed.
+/// All directories inside of the library are watched, along with the
+/// directories of any meta files outside of the library.
+#[cfg(target_os = "linux")]
+pub struct Watcher {
+    tracker: Tracker,
+    inotify: inotify::Inotify,
+    watched: HashMap<inotify::WatchDescriptor, PathBuf>,
+    watched_dirs: HashSet<PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    // Files are only reloaded once they are closed after writing, rather than
+    // on every write, so that partially-written meta files are not read.
+    const WATCH_MASK: u32 = inotify::IN_ATTRIB
+        | inotify::IN_CLOSE_WRITE
+        | inotify::IN_MOVED_FROM
+        | inotify::IN_MOVED_TO
+        | inotify::IN_CREATE
+        | inotify::IN_DELETE
+        | inotify::IN_DELETE_SELF
+        | inotify::IN_MOVE_SELF;
+
+    /// Starts watching a library, after loading the metadata of every selected
+    /// item file in it.
+    pub fn new(library: Library) -> Result<Self, Error> {
+        let inotify = inotify::Inotify::new().map_err(Error::CannotInit)?;
+
+        let mut watcher = Self {
+            tracker: Tracker::new(library),
+            inotify,
+            watched: HashMap::new(),
+            watched_dirs: HashSet::new(),
+        };
+
+        let root_dir = watcher.tracker.library.root_dir().to_path_buf();
+        watcher.watch_tree(&root_dir)?;
+        watcher.watch_meta_dirs()?;
+
+        Ok(watcher)
+    }
+
+    pub fn tracker(&self) -> &Tracker {
+        &self.tracker
+    }
+
+    /// Blocks until filesystem changes that affect the library happen, and
+    /// returns how the metadata of the affected item files changed.
+    /// All changes that are available at once are handled together.
+    pub fn next_changes(&mut self) -> Result<Vec<Change>, Error> {
+        loop {
+            let events = self.inotify.read_events().map_err(Error::CannotRead)?;
+            let mut changed_paths = BTreeSet::new();
+
+            for event in events {
+                if event.mask & inotify::IN_Q_OVERFLOW != 0 {
+                    // Events were lost, so treat the whole library as changed.
+                    changed_paths.insert(self.tracker.library.root_dir().to_path_buf());
+                    continue;
+                }
+
+                if event.mask & inotify::IN_IGNORED != 0 {
+                    // The watched directory was removed.
+                    if let Some(dir_path) = self.watched.remove(&event.wd) {
+                        self.watched_dirs.remove(&dir_path);
+                    }
+                    continue;
+                }
+
+                let changed_path = match self.watched.get(&event.wd) {
+                    None => continue,
+                    Some(dir_path) if event.name.is_empty() => dir_path.clone(),
+                    Some(dir_path) => dir_path.join(event.name()),
+                };
+
+                if event.mask & inotify::IN_ISDIR != 0 && event.mask & (inotify::IN_CREATE | inotify::IN_MOVED_TO) != 0 {
+                    self.watch_tree(&changed_path)?;
+                }
+
+                changed_paths.insert(changed_path);
+            }
+
+            if changed_paths.is_empty() {
+                continue;
+            }
+
+            let changes = self.tracker.update(changed_paths);
+
+            // Item files may now use meta files in directories not watched yet.
+            self.watch_meta_dirs()?;
+
+            return Ok(changes);
+        }
+    }
+
+    fn watch_dir(&mut self, dir_path: &Path) -> Result<(), Error> {
+        if self.watched_dirs.contains(dir_path) {
+            return Ok(());
+        }
+
+        match self.inotify.add_watch(dir_path, Self::WATCH_MASK) {
+            Ok(wd) => {
+                self.watched.insert(wd, dir_path.to_path_buf());
+                self.watched_dirs.insert(dir_path.to_path_buf());
+                Ok(())
+            },
+
+            // The directory was removed before it could be watched.
+            Err(err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+
+            Err(err) => Err(Error::CannotWatch(dir_path.into(), err)),
+        }
+    }
+
+    /// Watches a directory and all directories inside of it, regardless of
+    /// selection, since meta files may be inside of excluded directories.
+    fn watch_tree(&mut self, dir_path: &Path) -> Result<(), Error> {
+        self.watch_dir(dir_path)?;
+
+        let entries = match dir_path.read_dir() {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(Error::CannotWatch(dir_path.into(), err)),
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
+                self.watch_tree(&entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Watches the directories that contain the meta files of the library, or
+    /// that would contain them once they are created, such as directories
+    /// inside of a mirror. A directory that does not exist yet is watched
+    /// through its nearest existing ancestor, which reports its creation.
+    fn watch_meta_dirs(&mut self) -> Result<(), Error> {
+        let sources = self.tracker.library.config().sourcer.as_sources();
+
+        let mut meta_dir_paths = self.tracker
+            .meta_items
+            .keys()
+            .filter_map(|meta_path| meta_path.parent())
+            .map(Path::to_path_buf)
+            .collect::<BTreeSet<_>>();
+
+        // Every item directory is the anchor directory of its own meta files,
+        // and of those of the item files inside of it.
+        let item_dir_paths = self.tracker.blocks.keys().filter(|item_path| item_path.is_dir());
+
+        for item_dir_path in item_dir_paths {
+            for source in sources {
+                if let Anchor::Embedded = source.anchor {
+                    continue;
+                }
+
+                if let Ok(meta_parent_dir) = source.meta_parent_dir(item_dir_path) {
+                    meta_dir_paths.insert(meta_parent_dir.into_owned());
+                }
+            }
+        }
+
+        for dir_path in meta_dir_paths {
+            if let Some(existing_path) = dir_path.ancestors().find(|p| p.is_dir()) {
+                self.watch_dir(existing_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::config::Config;
+    use crate::test_util::TestUtil as TU;
+
+    fn title_block(title: &str) -> Block {
+        Block(btreemap![str!("title") => TU::s(title)])
+    }
+
+    fn album_dir(name: &str) -> tempfile::TempDir {
+        let temp_dir = TU::create_simple_dir(name, ["01.flac", "02.flac"]);
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("album.json"), r#"{"title": "album_title"}"#).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "track_01_title"}, {"title": "track_02_title"}]"#,
+        ).unwrap();
+
+        temp_dir
+    }
+
+    #[test]
+    fn tracker_update() {
+        let temp_dir = album_dir("tracker_update");
+        let path = temp_dir.path();
+
+        let mut tracker = Tracker::new(Library::new(path, Config::default()));
+
+        assert_eq!(tracker.block(&path.join("01.flac")), Some(&title_block("track_01_title")));
+        assert_eq!(tracker.meta_paths(&path.join("01.flac")), &[path.join("track.json")]);
+
+        // Editing a meta file affects the item files it provides metadata for.
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "new_01_title"}, {"title": "new_02_title"}]"#,
+        ).unwrap();
+
+        let changes = tracker.update([path.join("track.json")]);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].item_path, path.join("01.flac"));
+        assert_eq!(changes[0].old, Some(title_block("track_01_title")));
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(title_block("new_01_title")));
+        assert_eq!(changes[1].item_path, path.join("02.flac"));
+        assert_eq!(changes[1].new.as_ref().unwrap(), &Some(title_block("new_02_title")));
+
+        // Adding an item file affects the item files that share its meta files.
+        std::fs::write(path.join("03.flac"), "").unwrap();
+
+        let changes = tracker.update([path.join("03.flac")]);
+
+        assert_eq!(
+            changes.iter().map(|c| c.item_path.clone()).collect::<Vec<_>>(),
+            vec![path.join("01.flac"), path.join("02.flac"), path.join("03.flac")],
+        );
+        assert_eq!(changes[2].old, None);
+        assert!(changes.iter().all(|c| c.new.is_err()));
+
+        // Fixing the meta file recovers all of the item files.
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "new_01_title"}, {"title": "new_02_title"}, {"title": "new_03_title"}]"#,
+        ).unwrap();
+
+        let changes = tracker.update([path.join("track.json")]);
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[2].new.as_ref().unwrap(), &Some(title_block("new_03_title")));
+
+        // Removing an item file reports it as gone.
+        std::fs::remove_file(path.join("03.flac")).unwrap();
+        std::fs::write(
+            path.join("track.json"),
+            r#"[{"title": "new_01_title"}, {"title": "new_02_title"}]"#,
+        ).unwrap();
+
+        let changes = tracker.update([path.join("03.flac"), path.join("track.json")]);
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[2].item_path, path.join("03.flac"));
+        assert_eq!(changes[2].old, Some(title_block("new_03_title")));
+        assert_eq!(changes[2].new.as_ref().unwrap(), &None);
+        assert_eq!(tracker.block(&path.join("03.flac")), None);
+
+        // Only the cached meta files that may be stale are read again, so an
+        // unreported change to another meta file is not seen yet.
+        std::fs::write(path.join("album.json"), r#"{"title": "new_album_title"}"#).unwrap();
+        std::fs::write(path.join("track.json"), r#"[{"title": "unseen_01_title"}, {}]"#).unwrap();
+
+        let changes = tracker.update([path.join("album.json")]);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].item_path, path);
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(title_block("new_album_title")));
+        assert_eq!(tracker.library().get(&path.join("01.flac")).unwrap(), title_block("new_01_title"));
+
+        // Unrelated paths affect nothing.
+        assert!(tracker.affected_item_paths(&path.join("unrelated.json")).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn watcher() {
+        let temp_dir = TU::create_simple_dir("watcher", &[] as &[&str]);
+        let path = temp_dir.path();
+        let album_path = path.join("Album");
+
+        std::fs::write(path.join("album.json"), r#"{"title": "library_title"}"#).unwrap();
+        std::fs::create_dir(&album_path).unwrap();
+        std::fs::write(album_path.join("album.json"), r#"{"title": "album_title"}"#).unwrap();
+
+        let mut watcher = Watcher::new(Library::new(path, Config::default())).unwrap();
+
+        std::fs::write(album_path.join("album.json"), r#"{"title": "new_album_title"}"#).unwrap();
+
+        let changes = watcher.next_changes().unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].item_path, album_path);
+        assert_eq!(changes[0].old, Some(title_block("album_title")));
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(title_block("new_album_title")));
+
+        // New directories are watched as well.
+        std::fs::create_dir(path.join("Extras")).unwrap();
+
+        let changes = watcher.next_changes().unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].item_path, path.join("Extras"));
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(Block::new()));
+
+        std::fs::write(path.join("Extras").join("album.json"), r#"{"title": "extras_title"}"#).unwrap();
+
+        let changes = watcher.next_changes().unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(title_block("extras_title")));
+        assert_eq!(watcher.tracker().block(&path.join("Extras")), Some(&title_block("extras_title")));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn watcher_mirror() {
+        let media_dir = TU::create_simple_dir("watcher_mirror_media", &[] as &[&str]);
+        let meta_dir = TU::create_simple_dir("watcher_mirror_meta", &[] as &[&str]);
+        let media_path = media_dir.path();
+        let meta_path = meta_dir.path();
+        let album_path = media_path.join("Album");
+
+        std::fs::create_dir(&album_path).unwrap();
+
+        let config = Config::from_str(&format!(
+            "[sourcing.mirror]\nmedia_root = {:?}\nmeta_root = {:?}\n",
+            media_path, meta_path,
+        )).unwrap();
+
+        let mut watcher = Watcher::new(Library::new(media_path, config)).unwrap();
+
+        assert_eq!(watcher.tracker().block(&album_path), Some(&Block::new()));
+
+        // A meta file is seen even when its directory in the mirror did not
+        // exist when watching started.
+        std::fs::create_dir(meta_path.join("Album")).unwrap();
+
+        let changes = watcher.next_changes().unwrap();
+
+        assert!(changes.is_empty());
+
+        std::fs::write(meta_path.join("Album").join("album.json"), r#"{"title": "album_title"}"#).unwrap();
+
+        let changes = watcher.next_changes().unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].item_path, album_path);
+        assert_eq!(changes[0].old, Some(Block::new()));
+        assert_eq!(changes[0].new.as_ref().unwrap(), &Some(title_block("album_title")));
+    }
+}